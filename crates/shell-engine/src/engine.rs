@@ -1,12 +1,18 @@
-use monty::{DictPairs, MontyObject};
+use std::collections::HashMap;
+
+use monty::{DictPairs, MontyObject, MontyRepl, NoLimitTracker};
 
 use crate::icons;
 use crate::magic::{self, MagicCommand};
 use crate::monty_runtime;
-use crate::render::RenderSpec;
+use crate::render::{ColumnType, RenderSpec};
 use crate::render::LogbookEntry;
 use crate::render::TraceEntry;
-use crate::session::{PendingMonty, Session};
+use crate::session::{
+    EntityCall, HistoryView, MagicView, OutputFormat, PendingMagic, PendingMonty, Session, Theme,
+    MAX_HOST_CALLS_PER_SNIPPET,
+};
+use crate::yaml;
 
 /// The shell engine — owns REPL state, dispatches commands, returns render specs.
 pub struct ShellEngine {
@@ -25,9 +31,44 @@ impl ShellEngine {
         "≫ ".to_string()
     }
 
+    /// All Python functions available to user code, with short call
+    /// signatures — `[{"name": "state", "signature": "state(entity_id)"}, ...]`.
+    pub fn functions(&self) -> serde_json::Value {
+        monty_runtime::FUNCTION_SIGNATURES
+            .iter()
+            .map(|(name, signature)| serde_json::json!({ "name": name, "signature": signature }))
+            .collect()
+    }
+
+    /// Completion candidates for `prefix`: magic commands when it starts
+    /// with `%` or `:`, otherwise HA domains and names defined this session.
+    pub fn complete(&self, prefix: &str) -> Vec<String> {
+        if prefix.starts_with('%') || prefix.starts_with(':') {
+            return magic::MAGIC_COMMANDS
+                .iter()
+                .filter(|c| c.starts_with(prefix))
+                .map(|c| c.to_string())
+                .collect();
+        }
+        let mut candidates: Vec<String> = HA_DOMAINS
+            .iter()
+            .map(|d| d.to_string())
+            .chain(self.session.defined_names().iter().cloned())
+            .filter(|c| c.starts_with(prefix))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        candidates
+    }
+
     /// Evaluate a line of user input.
     /// Returns a render spec (or host call request) as the result.
     pub fn eval(&mut self, input: &str) -> RenderSpec {
+        let spec = self.eval_line(input);
+        self.gate_host_call(spec)
+    }
+
+    fn eval_line(&mut self, input: &str) -> RenderSpec {
         let trimmed = input.trim();
 
         // Don't record empty input.
@@ -37,6 +78,7 @@ impl ShellEngine {
 
         // Record in history.
         self.session.push_history(trimmed);
+        self.session.clear_now_cache();
 
         // Try magic commands first.
         if let Some(cmd) = magic::parse_magic(trimmed) {
@@ -45,12 +87,26 @@ impl ShellEngine {
 
         // Auto-resolve: bare entity_id → %get
         if looks_like_entity_id(trimmed) {
-            return self.dispatch_magic(MagicCommand::Get(trimmed.to_string()));
+            return self.dispatch_magic(MagicCommand::Get {
+                entity_id: trimmed.to_string(),
+                badge: false,
+                attribute: None,
+                expand: false,
+                services: false,
+            });
         }
 
         // Auto-resolve: bare domain name → %ls domain
         if looks_like_domain(trimmed) {
-            return self.dispatch_magic(MagicCommand::Ls(Some(trimmed.to_string())));
+            return self.dispatch_magic(MagicCommand::Ls {
+                domain: Some(trimmed.to_string()),
+                count: false,
+                chart: false,
+                json: false,
+                all: false,
+                attr: None,
+                spark: false,
+            });
         }
 
         // Otherwise treat as Python snippet.
@@ -62,14 +118,45 @@ impl ShellEngine {
         match cmd {
             MagicCommand::Help => magic::help_text(),
 
-            MagicCommand::Clear => {
+            MagicCommand::Examples => magic::examples(),
+
+            MagicCommand::Clear { last } => {
                 // Return a special spec that TypeScript interprets as "clear output".
-                RenderSpec::text("\x1b[clear]")
+                RenderSpec::text(if last { "\x1b[clear:last]" } else { "\x1b[clear]" })
             }
 
-            MagicCommand::Ls(domain) => {
+            MagicCommand::Info => self.session_info(),
+
+            MagicCommand::Ls {
+                domain,
+                count,
+                chart,
+                json,
+                all,
+                attr,
+                spark,
+            } => {
                 // Request entity list from TypeScript host.
                 let call_id = self.session.next_call_id();
+                let view = if count {
+                    Some(MagicView::Count { domain: domain.clone() })
+                } else if chart {
+                    Some(MagicView::DomainChart)
+                } else if json {
+                    Some(MagicView::Json)
+                } else if spark {
+                    Some(MagicView::Spark { attr })
+                } else if let Some(key) = attr {
+                    Some(MagicView::AttrColumn { key })
+                } else {
+                    (domain.is_none() && !all).then_some(MagicView::Overview)
+                };
+                if let Some(view) = view {
+                    self.session.store_pending_magic(PendingMagic {
+                        call_id: call_id.clone(),
+                        view,
+                    });
+                }
                 let params = match domain {
                     Some(d) => serde_json::json!({ "domain": d }),
                     None => serde_json::json!({}),
@@ -77,17 +164,51 @@ impl ShellEngine {
                 RenderSpec::host_call(call_id, "get_states", params)
             }
 
-            MagicCommand::Get(entity_id) => {
+            MagicCommand::Get { entity_id, .. } if entity_id.contains('*') => {
                 let call_id = self.session.next_call_id();
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::GlobGet { pattern: entity_id.clone() },
+                });
                 RenderSpec::host_call(
                     call_id,
-                    "get_state",
-                    serde_json::json!({ "entity_id": entity_id }),
+                    "find_entities",
+                    serde_json::json!({ "pattern": entity_id }),
                 )
             }
 
+            // No domain means it can't be an entity ID — search for it instead.
+            // Find patterns are anchored, so match the name anywhere in the ID.
+            MagicCommand::Get { entity_id, .. } if !entity_id.contains('.') => {
+                self.dispatch_magic(MagicCommand::Find(format!("*{entity_id}*")))
+            }
+
+            MagicCommand::Get {
+                entity_id,
+                badge,
+                attribute,
+                expand,
+                services,
+            } => {
+                let view = match attribute {
+                    Some(path) => Some(MagicView::Attribute { path }),
+                    None if badge => Some(MagicView::Badge),
+                    None if services => Some(MagicView::EntityServices { state: None }),
+                    None => expand.then_some(MagicView::GroupMembers),
+                };
+                self.entity_host_call(EntityCall {
+                    method: "get_state".into(),
+                    params: serde_json::json!({ "entity_id": entity_id }),
+                    view,
+                })
+            }
+
             MagicCommand::Find(pattern) => {
                 let call_id = self.session.next_call_id();
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::Find { pattern: pattern.clone() },
+                });
                 RenderSpec::host_call(
                     call_id,
                     "find_entities",
@@ -95,55 +216,265 @@ impl ShellEngine {
                 )
             }
 
-            MagicCommand::Hist { entity_id, hours } => {
-                let call_id = self.session.next_call_id();
-                RenderSpec::host_call(
-                    call_id,
-                    "get_history",
-                    serde_json::json!({
-                        "entity_id": entity_id,
-                        "hours": hours.unwrap_or(6),
+            MagicCommand::Hist {
+                entity_id,
+                hours,
+                compare: Some(offset_hours),
+                ..
+            } => self.hist_compare_call(entity_id, hours.unwrap_or(offset_hours), offset_hours, None),
+
+            MagicCommand::Hist {
+                entity_id,
+                hours,
+                render_as,
+                compare: None,
+            } => self.entity_host_call(EntityCall {
+                method: "get_history".into(),
+                params: serde_json::json!({
+                    "entity_id": entity_id,
+                    "hours": hours.unwrap_or(6),
+                }),
+                view: render_as.map(|render_as| MagicView::History { render_as }),
+            }),
+
+            MagicCommand::Attrs(entity_id) => {
+                self.entity_host_call(EntityCall {
+                    method: "get_state".into(),
+                    params: serde_json::json!({ "entity_id": entity_id, "attrs_only": true }),
+                    view: None,
+                })
+            }
+
+            MagicCommand::Inspect(entity_id) => {
+                self.entity_host_call(EntityCall {
+                    method: "get_state".into(),
+                    params: serde_json::json!({ "entity_id": entity_id }),
+                    view: Some(MagicView::Inspect {
+                        entity_id,
+                        state: None,
+                        attrs: None,
                     }),
-                )
+                })
             }
 
-            MagicCommand::Attrs(entity_id) => {
+            MagicCommand::Snapshot(entity_id) => {
                 let call_id = self.session.next_call_id();
-                RenderSpec::host_call(
-                    call_id,
-                    "get_state",
-                    serde_json::json!({ "entity_id": entity_id, "attrs_only": true }),
-                )
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::Snapshot { entity_id: entity_id.clone() },
+                });
+                RenderSpec::host_call(call_id, "get_state", serde_json::json!({ "entity_id": entity_id }))
+            }
+
+            MagicCommand::Diff {
+                entity_ids,
+                attrs_only,
+                changed_only,
+            } if entity_ids.len() == 2 && entity_ids[1] == "@snapshot" => {
+                let entity_a = &entity_ids[0];
+                if self.session.snapshot(entity_a).is_none() {
+                    return RenderSpec::error(format!(
+                        "No snapshot of {entity_a} — save one with %snapshot {entity_a}"
+                    ));
+                }
+                let call_id = self.session.next_call_id();
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::SnapshotDiff {
+                        entity_id: entity_a.clone(),
+                        attrs_only,
+                        changed_only,
+                    },
+                });
+                RenderSpec::host_call(call_id, "get_state", serde_json::json!({ "entity_id": entity_a }))
+            }
+
+            MagicCommand::Diff {
+                entity_ids,
+                attrs_only,
+                changed_only,
+            } if entity_ids.len() > 2 => {
+                let call_id = self.session.next_call_id();
+                let params = serde_json::json!({ "entity_ids": entity_ids });
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::MultiDiff {
+                        entity_ids,
+                        attrs_only,
+                        changed_only,
+                    },
+                });
+                RenderSpec::host_call(call_id, "get_states_by_id", params)
             }
 
-            MagicCommand::Diff(entity_a, entity_b) => {
-                // Need both entities — issue two host calls.
-                // For now, fetch entity_a first; we'll chain in TS.
+            MagicCommand::Diff {
+                entity_ids,
+                attrs_only,
+                changed_only,
+            } => {
+                // Two entities — the host's get_diff fetches both.
                 let call_id = self.session.next_call_id();
+                if attrs_only || changed_only {
+                    self.session.store_pending_magic(PendingMagic {
+                        call_id: call_id.clone(),
+                        view: MagicView::Diff {
+                            attrs_only,
+                            changed_only,
+                        },
+                    });
+                }
                 RenderSpec::host_call(
                     call_id,
                     "get_diff",
                     serde_json::json!({
-                        "entity_a": entity_a,
-                        "entity_b": entity_b,
+                        "entity_a": entity_ids[0],
+                        "entity_b": entity_ids[1],
                     }),
                 )
             }
 
+            MagicCommand::Services { domain, query } => {
+                let call_id = self.session.next_call_id();
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::Services { query },
+                });
+                let params = match domain {
+                    Some(d) => serde_json::json!({ "domain": d }),
+                    None => serde_json::json!({}),
+                };
+                RenderSpec::host_call(call_id, "get_services", params)
+            }
+
+            MagicCommand::Refresh => match self.session.last_entity_call().cloned() {
+                Some(call) => self.entity_host_call(call),
+                None => RenderSpec::error(
+                    "Nothing to refresh — view an entity with %get, %hist or %attrs first",
+                ),
+            },
+
+            MagicCommand::Copy => match self.session.last_result() {
+                Some(value) => {
+                    let json = monty_runtime::monty_obj_to_json(value);
+                    let pretty =
+                        serde_json::to_string_pretty(&json).unwrap_or_else(|_| json.to_string());
+                    RenderSpec::copyable(pretty, Some("JSON".into()))
+                }
+                None => RenderSpec::error("Nothing to copy — evaluate an expression first"),
+            },
+
+            MagicCommand::Yaml { service: false } => match self.session.last_result() {
+                Some(value) => {
+                    let json = monty_runtime::monty_obj_to_json(value);
+                    RenderSpec::copyable(yaml::to_yaml(&json), Some("YAML".into()))
+                }
+                None => RenderSpec::error("Nothing to export — evaluate an expression first"),
+            },
+
+            MagicCommand::Yaml { service: true } => match self.session.last_service_call() {
+                Some(params) => {
+                    let domain = params.get("domain").and_then(|v| v.as_str()).unwrap_or("?");
+                    let service = params.get("service").and_then(|v| v.as_str()).unwrap_or("?");
+                    let mut action = serde_json::json!({ "action": format!("{domain}.{service}") });
+                    match params.get("service_data") {
+                        Some(data) if data.as_object().is_some_and(|d| !d.is_empty()) => {
+                            action["data"] = data.clone();
+                        }
+                        _ => {}
+                    }
+                    RenderSpec::copyable(yaml::to_yaml(&action), Some("YAML".into()))
+                }
+                None => RenderSpec::error("No service call yet — run call_service(...) first"),
+            },
+
+            MagicCommand::Plot => self.plot_last_result(),
+
+            MagicCommand::Raw(setting) => {
+                let raw = setting.unwrap_or(!self.session.raw_output());
+                self.session.set_raw_output(raw);
+                RenderSpec::text(if raw {
+                    "Raw output on — host data is shown as JSON."
+                } else {
+                    "Raw output off — host data is auto-visualized."
+                })
+            }
+
+            MagicCommand::Theme(Some(theme)) => {
+                self.session.set_theme(theme);
+                RenderSpec::text(format!("Theme set to {}.", theme.name()))
+            }
+
+            MagicCommand::Py(code) => self.eval_python(&code),
+
+            MagicCommand::Theme(None) => {
+                RenderSpec::text(format!("Theme: {}", self.session.theme().name()))
+            }
+
+            MagicCommand::Caps => {
+                let call_id = self.session.next_call_id();
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::Capabilities,
+                });
+                RenderSpec::host_call(call_id, "get_capabilities", serde_json::json!({}))
+            }
+
+            MagicCommand::StateCounts => self.count_last_states(),
+
+            MagicCommand::Presence => {
+                let call_id = self.session.next_call_id();
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::Presence,
+                });
+                RenderSpec::host_call(call_id, "get_states", serde_json::json!({ "domain": "person" }))
+            }
+
+            MagicCommand::Strict(setting) => {
+                let strict = setting.unwrap_or(!self.session.strict());
+                self.session.set_strict(strict);
+                RenderSpec::text(if strict {
+                    "Strict mode on — state() on a missing entity returns the host error."
+                } else {
+                    "Strict mode off — missing entities return EntityState with exists=False."
+                })
+            }
+
             MagicCommand::Bundle(name) => {
                 // TODO: bundle loading
                 RenderSpec::error(format!("Bundle '{}' not found", name))
             }
 
-            MagicCommand::Fmt(format) => {
-                // TODO: store format preference in session
-                RenderSpec::text(format!("Output format set to: {}", format))
-            }
+            MagicCommand::Fmt(format) => match format.as_str() {
+                "grouped" | "ungrouped" => {
+                    let grouped = format == "grouped";
+                    self.session.set_grouped_numbers(grouped);
+                    RenderSpec::text(if grouped {
+                        "Large whole-number states shown with thousands separators"
+                    } else {
+                        "Numeric states shown as reported"
+                    })
+                }
+                _ => match OutputFormat::parse(&format) {
+                    Some(output_format) => {
+                        self.session.set_output_format(output_format);
+                        RenderSpec::text(format!("Output format set to: {}", format))
+                    }
+                    None => RenderSpec::error(format!(
+                        "Unknown format '{format}' — use table, json, grouped or ungrouped."
+                    )),
+                },
+            },
 
-            MagicCommand::Ask(question) => {
+            MagicCommand::Ask { question, agent } => {
                 // Build context from recent shell history.
                 let history = self.session.history();
-                let recent: Vec<&str> = history.iter().rev().take(10).map(|s| s.as_str()).collect();
+                let recent: Vec<&str> = history
+                    .iter()
+                    .rev()
+                    .take(ASK_CONTEXT_COMMANDS)
+                    .map(|s| s.as_str())
+                    .collect();
                 let context = if recent.is_empty() {
                     String::new()
                 } else {
@@ -151,19 +482,88 @@ impl ShellEngine {
                     format!("Recent shell commands:\n{}", cmds.join("\n"))
                 };
 
+                let mut params = serde_json::json!({
+                    "text": question,
+                    "context": context,
+                });
+                if let Some(agent) = agent {
+                    params["agent_id"] = serde_json::json!(agent);
+                }
                 let call_id = self.session.next_call_id();
-                RenderSpec::host_call(
-                    call_id,
-                    "conversation_process",
-                    serde_json::json!({
-                        "text": question,
-                        "context": context,
-                    }),
-                )
+                self.session.store_pending_magic(PendingMagic {
+                    call_id: call_id.clone(),
+                    view: MagicView::Ask { question },
+                });
+                RenderSpec::host_call(call_id, "conversation_process", params)
             }
         }
     }
 
+    /// Render read-only session stats for `:info`.
+    fn session_info(&self) -> RenderSpec {
+        let repl = if self.session.repl.is_some() {
+            "ready"
+        } else {
+            "in-flight"
+        };
+        let last_result = match self.session.last_result() {
+            Some(value) => monty_runtime::monty_obj_to_json(value)
+                .to_string()
+                .chars()
+                .take(60)
+                .collect(),
+            None => "-".to_string(),
+        };
+        let last_entity = self
+            .session
+            .last_entity_call()
+            .and_then(|c| c.params.get("entity_id"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("-")
+            .to_string();
+
+        let history = self.session.history().len();
+        let mut format = self.session.output_format().name().to_string();
+        if self.session.grouped_numbers() {
+            format.push_str(", grouped");
+        }
+        if self.session.raw_output() {
+            format.push_str(", raw");
+        }
+
+        RenderSpec::key_value(
+            Some("Session".into()),
+            vec![
+                ("history".into(), history.to_string()),
+                (
+                    "context lines".into(),
+                    history.min(ASK_CONTEXT_COMMANDS).to_string(),
+                ),
+                ("host calls".into(), self.session.call_count().to_string()),
+                ("REPL".into(), repl.into()),
+                ("output format".into(), format),
+                ("theme".into(), self.session.theme().name().into()),
+                ("defined names".into(), self.session.defined_names().len().to_string()),
+                ("last result".into(), last_result),
+                ("last entity".into(), last_entity),
+            ],
+        )
+    }
+
+    /// Issue an entity host call and remember it for `%refresh`.
+    fn entity_host_call(&mut self, call: EntityCall) -> RenderSpec {
+        let call_id = self.session.next_call_id();
+        if let Some(view) = call.view.clone() {
+            self.session.store_pending_magic(PendingMagic {
+                call_id: call_id.clone(),
+                view,
+            });
+        }
+        let spec = RenderSpec::host_call(call_id, call.method.clone(), call.params.clone());
+        self.session.set_last_entity_call(call);
+        spec
+    }
+
     /// Evaluate a Python snippet using the Monty sandboxed interpreter.
     ///
     /// Two-phase approach:
@@ -174,6 +574,19 @@ impl ShellEngine {
     ///    fall back to `start()` with a try/except wrapper.  `start()`
     ///    consumes the REPL but the wrapper guarantees we get it back.
     fn eval_python(&mut self, input: &str) -> RenderSpec {
+        let names = defined_names(input);
+        // The REPL keeps globals for the whole session, so `state = 5`
+        // would break every later `state(...)` with "int is not callable".
+        if let Some(name) = names
+            .iter()
+            .find(|n| monty_runtime::HA_EXTERNAL_FUNCTIONS.contains(&n.as_str()))
+        {
+            return RenderSpec::error(format!(
+                "`{name}` is a built-in function — assigning to it would break later \
+                 {name}(...) calls this session. Pick another name (e.g. {name}_)."
+            ));
+        }
+
         // --- Phase 1: try feed() ---
         let feed_result = {
             let repl = match self.session.repl.as_mut() {
@@ -195,9 +608,10 @@ impl ShellEngine {
         match feed_result {
             Ok((output, value)) => {
                 // feed() succeeded — render with expression value.
+                self.session.record_defined_names(names);
                 self.render_complete(&output, value.as_ref())
             }
-            Err(err_msg) => {
+            Err((output, err_msg)) => {
                 // Check if the error is "external function not implemented"
                 // — that means the snippet calls an ext function and we
                 // need to use start() instead.
@@ -217,12 +631,20 @@ impl ShellEngine {
                 } else {
                     // Genuine error (syntax, runtime, etc.)
                     // REPL is still alive — feed() borrows it.
-                    RenderSpec::error(err_msg)
+                    error_with_output(&output, err_msg)
                 }
             }
         }
     }
 
+    /// Store the REPL back once `snippet` has run to completion, and only
+    /// then remember the names it assigned — a snippet that errors, even
+    /// after pausing for host calls, leaves autocomplete and `:info` alone.
+    fn finish_snippet(&mut self, repl: MontyRepl<NoLimitTracker>, snippet: &str) {
+        self.session.store_repl(repl);
+        self.session.record_defined_names(defined_names(snippet));
+    }
+
     /// Handle a ReplEvalResult — unified handler for eval_python and resumed executions.
     fn handle_monty_eval_result(
         &mut self,
@@ -233,7 +655,7 @@ impl ShellEngine {
         match result {
             monty_runtime::ReplEvalResult::Complete { repl, output, value } => {
                 // Store the REPL back for the next snippet.
-                self.session.store_repl(repl);
+                self.finish_snippet(repl, input);
                 let full_output = combine_output(prefix_output, &output);
                 self.render_complete(&full_output, value.as_ref())
             }
@@ -241,6 +663,7 @@ impl ShellEngine {
                 output,
                 function_name,
                 args,
+                kwargs,
                 snapshot,
             } => {
                 let combined = combine_output(prefix_output, &output);
@@ -251,19 +674,19 @@ impl ShellEngine {
                     if !combined.is_empty() {
                         specs.push(RenderSpec::text(combined.clone()));
                     }
-                    if let Some(first_arg) = args.first() {
-                        specs.push(self.format_monty_show(first_arg));
-                    }
-                    // Resume immediately with None since show() returns None.
+                    specs.extend(self.format_show_call(&args));
+                    // show() returns its argument so it can be chained.
+                    let shown = self.remember_shown(&args);
                     let resumed = monty_runtime::resume_snapshot(
                         snapshot,
-                        monty::ExternalResult::Return(MontyObject::None),
+                        monty::ExternalResult::Return(shown),
                     );
-                    // If the resumed result is just Complete with None, return our show spec.
-                    // Otherwise chain it.
+                    // Once the snippet completes, the show spec is the output —
+                    // the final value is dropped, as it would only repeat what
+                    // was shown. Another pause is handled like any other.
                     match resumed {
                         monty_runtime::ReplEvalResult::Complete { repl, .. } => {
-                            self.session.store_repl(repl);
+                            self.finish_snippet(repl, input);
                             return if specs.len() == 1 {
                                 specs.remove(0)
                             } else {
@@ -282,7 +705,7 @@ impl ShellEngine {
                     if !combined.is_empty() {
                         specs.push(RenderSpec::text(combined.clone()));
                     }
-                    specs.push(self.build_chart(&function_name, &args));
+                    specs.push(self.build_chart(&function_name, &args, &kwargs));
                     // Resume with None.
                     let resumed = monty_runtime::resume_snapshot(
                         snapshot,
@@ -290,7 +713,7 @@ impl ShellEngine {
                     );
                     match resumed {
                         monty_runtime::ReplEvalResult::Complete { repl, .. } => {
-                            self.session.store_repl(repl);
+                            self.finish_snippet(repl, input);
                             return if specs.len() == 1 {
                                 specs.remove(0)
                             } else {
@@ -305,7 +728,7 @@ impl ShellEngine {
 
                 // Handle ago() locally — pure time calculation, no host call.
                 if function_name == "ago" {
-                    let result_obj = parse_ago_to_monty(&args);
+                    let result_obj = parse_ago_to_monty(&args, self.session.now_cache());
                     let resume_result = monty_runtime::resume_snapshot(
                         snapshot,
                         monty::ExternalResult::Return(result_obj),
@@ -313,7 +736,11 @@ impl ShellEngine {
                     return self.handle_monty_eval_result(input, &combined, resume_result);
                 }
 
-                match monty_runtime::map_ext_call_to_host_call(&function_name, &args) {
+                let mapped = match self.map_host_call(&function_name, &args, &kwargs) {
+                    Ok(mapped) => mapped,
+                    Err(message) => return invalid_call_error(&combined, message),
+                };
+                match mapped {
                     Some((method, params)) => {
                         let call_id = self.session.next_call_id();
                         self.session.store_pending_monty(PendingMonty {
@@ -321,6 +748,7 @@ impl ShellEngine {
                             snapshot,
                             output_so_far: combined,
                             original_snippet: input.to_string(),
+                            host_calls: 1,
                             method: method.to_string(),
                             params: params.clone(),
                         });
@@ -331,21 +759,12 @@ impl ShellEngine {
                     )),
                 }
             }
-            monty_runtime::ReplEvalResult::Error { message, repl } => {
+            monty_runtime::ReplEvalResult::Error { output, message, repl } => {
                 // Store the REPL back if we got one (e.g. parse error before exec started).
                 if let Some(r) = repl {
                     self.session.store_repl(r);
                 }
-                let mut specs = Vec::new();
-                if !prefix_output.is_empty() {
-                    specs.push(RenderSpec::text(prefix_output.to_string()));
-                }
-                specs.push(RenderSpec::error(message));
-                if specs.len() == 1 {
-                    specs.remove(0)
-                } else {
-                    RenderSpec::vstack(specs)
-                }
+                error_with_output(&combine_output(prefix_output, &output), message)
             }
         }
     }
@@ -353,42 +772,245 @@ impl ShellEngine {
     /// Handle the result of a host call.
     /// TypeScript calls this after fulfilling a host_call request.
     pub fn fulfill_host_call(&mut self, call_id: &str, data: &str) -> RenderSpec {
+        self.session.mark_fulfilled(call_id);
+
+        // The last chunk of a streamed state list completes the rows
+        // already received through `fulfill_host_call_chunk`. Anything
+        // else (an error) ends the stream, and the rows are dropped.
+        let merged;
+        let mut dropped = 0;
+        let data = match self.session.take_partial_states(call_id) {
+            Some(mut rows) => match serde_json::from_str::<serde_json::Value>(data) {
+                Ok(serde_json::Value::Array(rest)) => {
+                    rows.extend(rest);
+                    merged = serde_json::Value::Array(rows).to_string();
+                    merged.as_str()
+                }
+                _ => {
+                    dropped = rows.len();
+                    data
+                }
+            },
+            None => data,
+        };
+
         // Check if this call originated from a Monty execution.
-        if self.session.has_pending_monty(call_id) {
-            return self.fulfill_monty_host_call(call_id, data);
+        let spec = if self.session.has_pending_monty(call_id) {
+            self.fulfill_monty_host_call(call_id, data)
+        } else {
+            self.fulfill_magic_host_call(call_id, data)
+        };
+        let spec = self.gate_host_call(spec);
+        if dropped == 0 {
+            return spec;
         }
+        let noun = if dropped == 1 { "entity" } else { "entities" };
+        RenderSpec::vstack(vec![
+            spec,
+            RenderSpec::text(format!("Discarded {dropped} {noun} streamed before this response.")),
+        ])
+    }
 
-        // Otherwise it's a magic command host call — parse and format.
-        match serde_json::from_str::<serde_json::Value>(data) {
-            Ok(value) => {
-                // Check for conversation (assistant) response.
-                if value.get("__conversation").is_some() {
-                    let response = value
-                        .get("response")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("")
-                        .to_string();
-                    let agent = value
-                        .get("agent_id")
-                        .and_then(|v| v.as_str())
-                        .unwrap_or("unknown")
-                        .to_string();
-                    return RenderSpec::assistant(response, agent);
-                }
-                // Check for diff response.
-                if value.get("__diff").is_some() {
-                    return self.format_diff_response(&value);
-                }
-                // Check for attrs-only response.
-                if value.get("__attrs_only").is_some() {
-                    return self.format_attrs_response(&value);
-                }
-                self.format_host_response(value)
+    /// Feed one chunk of a large state list that the host delivers in
+    /// pieces, and render the rows received so far the way the final
+    /// response will be. The call stays pending; the final chunk goes
+    /// through [`Self::fulfill_host_call`].
+    pub fn fulfill_host_call_chunk(&mut self, call_id: &str, partial: &str) -> RenderSpec {
+        if !self.session.is_awaiting(call_id) {
+            return RenderSpec::error(format!("No host call {call_id} is waiting for data."));
+        }
+        let chunk = match serde_json::from_str::<serde_json::Value>(partial) {
+            Ok(serde_json::Value::Array(chunk)) => chunk,
+            other => {
+                self.session.take_partial_states(call_id);
+                return RenderSpec::error(match other {
+                    Err(e) => format!("Failed to parse host response: {e}"),
+                    Ok(_) => "Expected a chunk of state objects (a JSON array).".to_string(),
+                });
+            }
+        };
+        let rows = self.session.append_partial_states(call_id, chunk).to_vec();
+        let loading = RenderSpec::text(format!("Loading entities… {} so far", rows.len()));
+
+        // A snippet only sees the list once it's complete, and views that
+        // chain another host call need all of it.
+        if rows.is_empty() || self.session.has_pending_monty(call_id) {
+            return loading;
+        }
+        let view = self.session.pending_magic_view(call_id).cloned();
+        let renders_rows = matches!(
+            view,
+            None | Some(
+                MagicView::Overview
+                    | MagicView::Json
+                    | MagicView::Count { .. }
+                    | MagicView::DomainChart
+                    | MagicView::Presence
+                    | MagicView::AttrColumn { .. }
+                    | MagicView::Find { .. }
+            )
+        );
+        if !renders_rows {
+            return loading;
+        }
+        RenderSpec::vstack(vec![
+            self.render_magic_response(view, serde_json::Value::Array(rows)),
+            loading,
+        ])
+    }
+
+    /// Stop a follow-up magic host call for a method the host said (via
+    /// `%caps`) it doesn't support, instead of letting it fail silently.
+    /// Python calls are checked before their snapshot is stored.
+    fn gate_host_call(&mut self, spec: RenderSpec) -> RenderSpec {
+        match &spec {
+            RenderSpec::HostCall { call_id, method, .. } if !self.session.supports(method) => {
+                let message = unsupported_method_message(method);
+                let call_id = call_id.clone();
+                self.session.take_pending_magic(&call_id);
+                RenderSpec::error(message)
             }
+            _ => spec,
+        }
+    }
+
+    /// Format the response to a magic command's host call.
+    fn fulfill_magic_host_call(&mut self, call_id: &str, data: &str) -> RenderSpec {
+        let view = self.session.take_pending_magic(call_id).map(|p| p.view);
+        match serde_json::from_str::<serde_json::Value>(data) {
+            Ok(value) => self.render_magic_response(view, value),
             Err(e) => RenderSpec::error(format!("Failed to parse host response: {e}")),
         }
     }
 
+    /// Render a magic command's host response in the view it was issued
+    /// with — also used for the rows received so far of a streamed list.
+    fn render_magic_response(
+        &mut self,
+        view: Option<MagicView>,
+        value: serde_json::Value,
+    ) -> RenderSpec {
+        self.remember_states(&value);
+        let previous = self.remember_numeric_state(&value);
+        match view {
+            Some(MagicView::Badge) => return self.format_entity_badge(&value),
+            Some(MagicView::Attribute { path }) => {
+                return self.format_entity_attribute(&value, &path);
+            }
+            Some(MagicView::Capabilities) => return self.save_capabilities(&value),
+            Some(MagicView::GroupMembers) => return self.expand_group(value),
+            Some(MagicView::GlobGet { pattern }) => {
+                return self.fetch_glob_matches(value, &pattern);
+            }
+            Some(MagicView::HistCompare {
+                entity_id,
+                hours,
+                offset_hours,
+                current: None,
+            }) => return self.hist_compare_call(entity_id, hours, offset_hours, Some(value)),
+            Some(MagicView::HistCompare {
+                hours,
+                offset_hours,
+                current: Some(current),
+                ..
+            }) => return self.format_history_compare(&current, &value, hours, offset_hours),
+            Some(MagicView::Find { pattern }) => {
+                return self.format_find_response(value, &pattern);
+            }
+            Some(MagicView::Count { domain }) => {
+                return self.format_entity_count(&value, domain.as_deref());
+            }
+            Some(MagicView::DomainChart) => return self.format_domain_chart(&value),
+            Some(MagicView::Presence) => return self.format_presence(&value),
+            Some(MagicView::Overview) => return self.format_entity_overview(value),
+            Some(MagicView::AttrColumn { key }) => {
+                return match value.as_array() {
+                    Some(arr) if !arr.is_empty() => self.format_entity_table_with(arr, Some(&key), None),
+                    _ => self.format_host_response(value),
+                };
+            }
+            Some(MagicView::Spark { attr }) => return self.fetch_spark_history(value, attr),
+            Some(MagicView::SparkHistory { attr, states }) => {
+                let trends = spark_trends(&value);
+                let arr = states.as_array().map(Vec::as_slice).unwrap_or_default();
+                return self.format_entity_table_with(arr, attr.as_deref(), Some(&trends));
+            }
+            Some(MagicView::Json) => {
+                let pretty = serde_json::to_string_pretty(&value)
+                    .unwrap_or_else(|_| value.to_string());
+                return RenderSpec::copyable(pretty, Some("JSON".into()));
+            }
+            Some(MagicView::Diff {
+                attrs_only,
+                changed_only,
+            }) => return self.format_diff_response(&value, attrs_only, changed_only),
+            Some(MagicView::MultiDiff {
+                entity_ids,
+                attrs_only,
+                changed_only,
+            }) => return self.format_multi_diff(&value, &entity_ids, attrs_only, changed_only),
+            Some(MagicView::Inspect {
+                entity_id,
+                state,
+                attrs,
+            }) => return self.continue_inspect(entity_id, state, attrs, value),
+            Some(MagicView::Snapshot { entity_id }) => {
+                return self.save_snapshot(&entity_id, value);
+            }
+            Some(MagicView::EntityServices { state: None }) => {
+                return self.fetch_entity_services(value);
+            }
+            Some(MagicView::EntityServices { state: Some(state) }) => {
+                return RenderSpec::vstack(vec![
+                    self.format_entity_card(&state),
+                    self.format_services_response(value, None),
+                ]);
+            }
+            Some(MagicView::SnapshotDiff {
+                entity_id,
+                attrs_only,
+                changed_only,
+            }) => return self.diff_against_snapshot(&entity_id, value, attrs_only, changed_only),
+            Some(MagicView::Services { query }) => {
+                return self.format_services_response(value, query.as_deref());
+            }
+            Some(MagicView::History { render_as }) => {
+                return self.format_history_response(&value, Some(render_as));
+            }
+            Some(MagicView::Ask { question }) if value.get("no_agent").is_some() => {
+                return suggest_commands(&question);
+            }
+            Some(MagicView::Ask { .. }) | None => {}
+        }
+        // Check for conversation (assistant) response.
+        if value.get("__conversation").is_some() {
+            let response = value
+                .get("response")
+                .and_then(|v| v.as_str())
+                .unwrap_or("")
+                .to_string();
+            let agent = value
+                .get("agent_id")
+                .and_then(|v| v.as_str())
+                .unwrap_or("unknown")
+                .to_string();
+            return RenderSpec::assistant(response, agent);
+        }
+        // Check for diff response.
+        if value.get("__diff").is_some() {
+            return self.format_diff_response(&value, false, false);
+        }
+        // Check for attrs-only response.
+        if value.get("__attrs_only").is_some() {
+            return self.format_attrs_response(&value);
+        }
+        let mut spec = self.format_host_response(value);
+        if let (Some(previous), RenderSpec::EntityCard { state, trend, .. }) = (previous, &mut spec) {
+            *trend = state.parse::<f64>().ok().map(|current| trend_direction(previous, current).into());
+        }
+        spec
+    }
+
     /// Resume a paused Monty execution with host call data.
     fn fulfill_monty_host_call(&mut self, call_id: &str, data: &str) -> RenderSpec {
         let pending = match self.session.take_pending_monty(call_id) {
@@ -399,11 +1021,41 @@ impl ShellEngine {
         // Convert the JSON response to a MontyObject so Monty can use it.
         let json_value: serde_json::Value = match serde_json::from_str(data) {
             Ok(v) => v,
-            Err(e) => return RenderSpec::error(format!("Failed to parse host response: {e}")),
+            Err(e) => {
+                // There's nothing sensible to resume with, so the snapshot —
+                // and the REPL frozen inside it — is dropped here. The next
+                // snippet starts from a fresh REPL.
+                let output = pending.output_so_far.clone();
+                let method = pending.method.clone();
+                drop(pending);
+                return error_with_output(
+                    &output,
+                    format!(
+                        "Host returned invalid data for {method}: {e}\n\
+                         Execution aborted; Python variables were reset."
+                    ),
+                );
+            }
         };
 
+        if pending.method == "get_datetime" {
+            self.session.set_now_cache(json_value.clone());
+        }
+        if pending.method == "call_service" {
+            self.session.set_last_service_call(pending.params.clone());
+        }
+        if pending.method == "get_states" {
+            self.remember_states(&json_value);
+        }
+
         // Use typed EntityState for state/states/area responses.
         let monty_value = match pending.method.as_str() {
+            // Missing entity — a placeholder with exists=False unless strict.
+            "get_state" if json_value.get("error").is_some() && !self.session.strict() => {
+                let entity_id = pending.params.get("entity_id").and_then(|v| v.as_str()).unwrap_or("");
+                monty_runtime::missing_entity_state(entity_id)
+            }
+            "get_state" if json_value.get("error").is_some() => monty_runtime::json_to_monty_obj(&json_value),
             "get_state" => monty_runtime::json_to_entity_state(&json_value),
             "get_states" => monty_runtime::json_to_entity_state_list(&json_value),
             "get_area_entities" => {
@@ -417,6 +1069,11 @@ impl ShellEngine {
             }
             "get_areas" => monty_runtime::json_to_monty_obj(&json_value),
             "get_events" => monty_runtime::json_to_calendar_event_list(&json_value),
+            // Python sees the rendered value itself, not the {result} envelope.
+            "render_template" => match json_value.get("result") {
+                Some(result) => monty_runtime::json_to_monty_obj(result),
+                None => monty_runtime::json_to_monty_obj(&json_value),
+            },
             _ => monty_runtime::json_to_monty_obj(&json_value),
         };
 
@@ -430,7 +1087,7 @@ impl ShellEngine {
         match result {
             monty_runtime::ReplEvalResult::Complete { repl, output, value } => {
                 // Store the REPL back for the next snippet.
-                self.session.store_repl(repl);
+                self.finish_snippet(repl, &pending.original_snippet);
                 let full_output = combine_output(&pending.output_so_far, &output);
 
                 // Auto-visualize specific methods — render rich displays
@@ -438,7 +1095,8 @@ impl ShellEngine {
                 let is_viz_method = matches!(
                     pending.method.as_str(),
                     "get_history" | "get_statistics" | "get_logbook" | "get_services" | "get_datetime"
-                    | "get_trace" | "list_traces" | "get_events"
+                    | "get_trace" | "list_traces" | "get_events" | "render_template" | "call_service"
+                    | "get_areas"
                 );
                 if is_viz_method {
                     let mut specs = Vec::new();
@@ -446,12 +1104,28 @@ impl ShellEngine {
                         specs.push(RenderSpec::text(full_output));
                     }
                     let viz = match pending.method.as_str() {
+                        _ if self.session.raw_output()
+                            || self.session.output_format() == OutputFormat::Json =>
+                        {
+                            let pretty = serde_json::to_string_pretty(&json_value)
+                                .unwrap_or_else(|_| json_value.to_string());
+                            RenderSpec::copyable(pretty, Some("JSON".into()))
+                        }
                         "get_logbook" => self.format_logbook_response(json_value, &pending.params),
-                        "get_services" => self.format_services_response(json_value),
+                        "get_services" => {
+                            let query = pending.params.get("query").and_then(|v| v.as_str());
+                            self.format_services_response(json_value, query)
+                        }
                         "get_datetime" => self.format_datetime_response(json_value),
                         "get_trace" => self.format_traces_response(json_value, &pending.params),
                         "list_traces" => self.format_traces_response(json_value, &pending.params),
                         "get_events" => self.format_calendar_events_response(json_value, &pending.params),
+                        "render_template" => self.format_template_response(json_value),
+                        "call_service" => self.format_service_result_response(&json_value, &pending.params),
+                        "get_areas" => self.format_areas_response(&json_value),
+                        "get_history" if pending.params.get("entity_ids").is_some() => {
+                            self.format_history_overlay(&json_value)
+                        }
                         _ => self.format_host_response(json_value),
                     };
                     specs.push(viz);
@@ -468,6 +1142,7 @@ impl ShellEngine {
                 output,
                 function_name,
                 args,
+                kwargs,
                 snapshot,
             } => {
                 // Another external call — chain it, carrying the original snippet.
@@ -479,17 +1154,15 @@ impl ShellEngine {
                     if !combined_output.is_empty() {
                         specs.push(RenderSpec::text(combined_output.clone()));
                     }
-                    if let Some(first_arg) = args.first() {
-                        specs.push(self.format_monty_show(first_arg));
-                    }
-                    // Resume with None.
+                    specs.extend(self.format_show_call(&args));
+                    let shown = self.remember_shown(&args);
                     let resumed = monty_runtime::resume_snapshot(
                         snapshot,
-                        monty::ExternalResult::Return(MontyObject::None),
+                        monty::ExternalResult::Return(shown),
                     );
                     match resumed {
                         monty_runtime::ReplEvalResult::Complete { repl, .. } => {
-                            self.session.store_repl(repl);
+                            self.finish_snippet(repl, &pending.original_snippet);
                             return if specs.len() == 1 {
                                 specs.remove(0)
                             } else {
@@ -499,6 +1172,7 @@ impl ShellEngine {
                         other => {
                             return self.handle_monty_resumed_result(
                                 &pending.original_snippet,
+                                pending.host_calls,
                                 &combined_output,
                                 other,
                             );
@@ -512,14 +1186,14 @@ impl ShellEngine {
                     if !combined_output.is_empty() {
                         specs.push(RenderSpec::text(combined_output.clone()));
                     }
-                    specs.push(self.build_chart(&function_name, &args));
+                    specs.push(self.build_chart(&function_name, &args, &kwargs));
                     let resumed = monty_runtime::resume_snapshot(
                         snapshot,
                         monty::ExternalResult::Return(MontyObject::None),
                     );
                     match resumed {
                         monty_runtime::ReplEvalResult::Complete { repl, .. } => {
-                            self.session.store_repl(repl);
+                            self.finish_snippet(repl, &pending.original_snippet);
                             return if specs.len() == 1 {
                                 specs.remove(0)
                             } else {
@@ -529,6 +1203,7 @@ impl ShellEngine {
                         other => {
                             return self.handle_monty_resumed_result(
                                 &pending.original_snippet,
+                                pending.host_calls,
                                 &combined_output,
                                 other,
                             );
@@ -538,26 +1213,47 @@ impl ShellEngine {
 
                 // Handle ago() locally — pure time calculation.
                 if function_name == "ago" {
-                    let result_obj = parse_ago_to_monty(&args);
+                    let result_obj = parse_ago_to_monty(&args, self.session.now_cache());
                     let resume_result = monty_runtime::resume_snapshot(
                         snapshot,
                         monty::ExternalResult::Return(result_obj),
                     );
                     return self.handle_monty_resumed_result(
                         &pending.original_snippet,
+                        pending.host_calls,
                         &combined_output,
                         resume_result,
                     );
                 }
 
-                match monty_runtime::map_ext_call_to_host_call(&function_name, &args) {
+                let mapped = match self.map_host_call(&function_name, &args, &kwargs) {
+                    Ok(mapped) => mapped,
+                    Err(message) => return invalid_call_error(&combined_output, message),
+                };
+                match mapped {
                     Some((method, params)) => {
+                        if let Some(now) = self.cached_now(method) {
+                            let resumed = monty_runtime::resume_snapshot(
+                                snapshot,
+                                monty::ExternalResult::Return(now),
+                            );
+                            return self.handle_monty_resumed_result(
+                                &pending.original_snippet,
+                                pending.host_calls,
+                                &combined_output,
+                                resumed,
+                            );
+                        }
+                        if pending.host_calls >= MAX_HOST_CALLS_PER_SNIPPET {
+                            return host_call_limit_error(&combined_output);
+                        }
                         let new_call_id = self.session.next_call_id();
                         self.session.store_pending_monty(PendingMonty {
                             call_id: new_call_id.clone(),
                             snapshot,
                             output_so_far: combined_output,
                             original_snippet: pending.original_snippet,
+                            host_calls: pending.host_calls + 1,
                             method: method.to_string(),
                             params: params.clone(),
                         });
@@ -568,20 +1264,11 @@ impl ShellEngine {
                     )),
                 }
             }
-            monty_runtime::ReplEvalResult::Error { message, repl } => {
+            monty_runtime::ReplEvalResult::Error { output, message, repl } => {
                 if let Some(r) = repl {
                     self.session.store_repl(r);
                 }
-                let mut specs = Vec::new();
-                if !pending.output_so_far.is_empty() {
-                    specs.push(RenderSpec::text(pending.output_so_far));
-                }
-                specs.push(RenderSpec::error(message));
-                if specs.len() == 1 {
-                    specs.remove(0)
-                } else {
-                    RenderSpec::vstack(specs)
-                }
+                error_with_output(&combine_output(&pending.output_so_far, &output), message)
             }
         }
     }
@@ -591,12 +1278,13 @@ impl ShellEngine {
     fn handle_monty_resumed_result(
         &mut self,
         original_snippet: &str,
+        host_calls: u32,
         prefix_output: &str,
         result: monty_runtime::ReplEvalResult,
     ) -> RenderSpec {
         match result {
             monty_runtime::ReplEvalResult::Complete { repl, output, value } => {
-                self.session.store_repl(repl);
+                self.finish_snippet(repl, original_snippet);
                 let full_output = combine_output(prefix_output, &output);
                 self.render_complete(&full_output, value.as_ref())
             }
@@ -604,6 +1292,7 @@ impl ShellEngine {
                 output,
                 function_name,
                 args,
+                kwargs,
                 snapshot,
             } => {
                 let combined = combine_output(prefix_output, &output);
@@ -613,16 +1302,15 @@ impl ShellEngine {
                     if !combined.is_empty() {
                         specs.push(RenderSpec::text(combined.clone()));
                     }
-                    if let Some(first_arg) = args.first() {
-                        specs.push(self.format_monty_show(first_arg));
-                    }
+                    specs.extend(self.format_show_call(&args));
+                    let shown = self.remember_shown(&args);
                     let resumed = monty_runtime::resume_snapshot(
                         snapshot,
-                        monty::ExternalResult::Return(MontyObject::None),
+                        monty::ExternalResult::Return(shown),
                     );
                     match resumed {
                         monty_runtime::ReplEvalResult::Complete { repl, .. } => {
-                            self.session.store_repl(repl);
+                            self.finish_snippet(repl, original_snippet);
                             return if specs.len() == 1 {
                                 specs.remove(0)
                             } else {
@@ -631,7 +1319,7 @@ impl ShellEngine {
                         }
                         other => {
                             return self.handle_monty_resumed_result(
-                                original_snippet, &combined, other,
+                                original_snippet, host_calls, &combined, other,
                             );
                         }
                     }
@@ -643,14 +1331,14 @@ impl ShellEngine {
                     if !combined.is_empty() {
                         specs.push(RenderSpec::text(combined.clone()));
                     }
-                    specs.push(self.build_chart(&function_name, &args));
+                    specs.push(self.build_chart(&function_name, &args, &kwargs));
                     let resumed = monty_runtime::resume_snapshot(
                         snapshot,
                         monty::ExternalResult::Return(MontyObject::None),
                     );
                     match resumed {
                         monty_runtime::ReplEvalResult::Complete { repl, .. } => {
-                            self.session.store_repl(repl);
+                            self.finish_snippet(repl, original_snippet);
                             return if specs.len() == 1 {
                                 specs.remove(0)
                             } else {
@@ -659,31 +1347,48 @@ impl ShellEngine {
                         }
                         other => {
                             return self.handle_monty_resumed_result(
-                                original_snippet, &combined, other,
+                                original_snippet, host_calls, &combined, other,
                             );
                         }
                     }
                 }
 
                 if function_name == "ago" {
-                    let result_obj = parse_ago_to_monty(&args);
+                    let result_obj = parse_ago_to_monty(&args, self.session.now_cache());
                     let resume_result = monty_runtime::resume_snapshot(
                         snapshot,
                         monty::ExternalResult::Return(result_obj),
                     );
                     return self.handle_monty_resumed_result(
-                        original_snippet, &combined, resume_result,
+                        original_snippet, host_calls, &combined, resume_result,
                     );
                 }
 
-                match monty_runtime::map_ext_call_to_host_call(&function_name, &args) {
+                let mapped = match self.map_host_call(&function_name, &args, &kwargs) {
+                    Ok(mapped) => mapped,
+                    Err(message) => return invalid_call_error(&combined, message),
+                };
+                match mapped {
                     Some((method, params)) => {
+                        if let Some(now) = self.cached_now(method) {
+                            let resumed = monty_runtime::resume_snapshot(
+                                snapshot,
+                                monty::ExternalResult::Return(now),
+                            );
+                            return self.handle_monty_resumed_result(
+                                original_snippet, host_calls, &combined, resumed,
+                            );
+                        }
+                        if host_calls >= MAX_HOST_CALLS_PER_SNIPPET {
+                            return host_call_limit_error(&combined);
+                        }
                         let new_call_id = self.session.next_call_id();
                         self.session.store_pending_monty(PendingMonty {
                             call_id: new_call_id.clone(),
                             snapshot,
                             output_so_far: combined,
                             original_snippet: original_snippet.to_string(),
+                            host_calls: host_calls + 1,
                             method: method.to_string(),
                             params: params.clone(),
                         });
@@ -694,20 +1399,11 @@ impl ShellEngine {
                     )),
                 }
             }
-            monty_runtime::ReplEvalResult::Error { message, repl } => {
+            monty_runtime::ReplEvalResult::Error { output, message, repl } => {
                 if let Some(r) = repl {
                     self.session.store_repl(r);
                 }
-                let mut specs = Vec::new();
-                if !prefix_output.is_empty() {
-                    specs.push(RenderSpec::text(prefix_output.to_string()));
-                }
-                specs.push(RenderSpec::error(message));
-                if specs.len() == 1 {
-                    specs.remove(0)
-                } else {
-                    RenderSpec::vstack(specs)
-                }
+                error_with_output(&combine_output(prefix_output, &output), message)
             }
         }
     }
@@ -722,6 +1418,10 @@ impl ShellEngine {
         }
 
         if let Some(obj) = result {
+            if !matches!(obj, MontyObject::None) {
+                self.session.set_last_result(obj.clone());
+            }
+
             // Rich auto-display for EntityState and lists of EntityState.
             match obj {
                 MontyObject::Dataclass { name, .. } if name == "EntityState" => {
@@ -735,6 +1435,16 @@ impl ShellEngine {
                 {
                     specs.push(self.format_monty_show(obj));
                 }
+                // Booleans scan faster as a coloured badge than as `→ True`.
+                MontyObject::Bool(b) => {
+                    let (label, color) = if *b { ("True", "success") } else { ("False", "dim") };
+                    specs.push(RenderSpec::badge(label, color));
+                }
+                // Long strings (logs, templates, JSON dumps) go in a
+                // scrollable block with a copy button.
+                MontyObject::String(s) if s.chars().count() > LONG_TEXT_THRESHOLD => {
+                    specs.push(RenderSpec::copyable(s.clone(), None));
+                }
                 other => {
                     specs.push(RenderSpec::text(format!("→ {other}")));
                 }
@@ -748,14 +1458,71 @@ impl ShellEngine {
         }
     }
 
-    /// Format a MontyObject for show() — rich rendering for EntityState,
-    /// CalendarEvent, plain text for everything else.
-    fn format_monty_show(&self, obj: &MontyObject) -> RenderSpec {
-        match obj {
-            MontyObject::Dataclass {
-                name, attrs, ..
-            } if name == "EntityState" => {
-                self.format_entity_state_card(attrs)
+    /// Map a Monty external call to a host call, ready to issue.
+    ///
+    /// `Ok(None)` means the function isn't a host call. `Err` carries the
+    /// message for arguments the host must not see: a malformed entity ID,
+    /// or a method `%caps` reported as unsupported.
+    fn map_host_call(
+        &self,
+        function_name: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> Result<Option<(&'static str, serde_json::Value)>, String> {
+        let Some((method, params)) = monty_runtime::map_ext_call_to_host_call(function_name, args)
+            .map(|(method, params)| with_trace_state_filter(method, params, kwargs))
+        else {
+            return Ok(None);
+        };
+        if let Some(message) = monty_runtime::invalid_entity_id(function_name, method, &params) {
+            return Err(message);
+        }
+        if !self.session.supports(method) {
+            return Err(unsupported_method_message(method));
+        }
+        Ok(Some((method, params)))
+    }
+
+    /// The cached `now()` result for this eval, if `method` is
+    /// `get_datetime` and the time has already been fetched.
+    fn cached_now(&self, method: &str) -> Option<MontyObject> {
+        if method != "get_datetime" {
+            return None;
+        }
+        self.session.now_cache().map(monty_runtime::json_to_monty_obj)
+    }
+
+    /// Render a `show(value, [label])` call — the value, captioned by the
+    /// label when one is given.
+    fn format_show_call(&self, args: &[MontyObject]) -> Vec<RenderSpec> {
+        let mut specs = Vec::new();
+        if let Some(MontyObject::String(label)) = args.get(1) {
+            specs.push(RenderSpec::summary(label.clone()));
+        }
+        if let Some(value) = args.first() {
+            specs.push(self.format_monty_show(value));
+        }
+        specs
+    }
+
+    /// The value a `show(x)` call returns to Python: `x` itself, which also
+    /// becomes the last result for `%copy` / `%plot`.
+    fn remember_shown(&mut self, args: &[MontyObject]) -> MontyObject {
+        let shown = args.first().cloned().unwrap_or(MontyObject::None);
+        if !matches!(shown, MontyObject::None) {
+            self.session.set_last_result(shown.clone());
+        }
+        shown
+    }
+
+    /// Format a MontyObject for show() — rich rendering for EntityState,
+    /// CalendarEvent, plain text for everything else.
+    fn format_monty_show(&self, obj: &MontyObject) -> RenderSpec {
+        match obj {
+            MontyObject::Dataclass {
+                name, attrs, ..
+            } if name == "EntityState" => {
+                self.format_entity_state_card(attrs)
             }
             MontyObject::List(items) => {
                 // Check if it's a list of EntityState — render as table.
@@ -776,6 +1543,11 @@ impl ShellEngine {
                     return self.format_calendar_event_list_from_monty(items);
                 }
 
+                // A list of dicts sharing the same keys — render as a table.
+                if let Some(table) = dict_rows_table(items) {
+                    return table;
+                }
+
                 RenderSpec::text(format!("{obj}"))
             }
             other => RenderSpec::text(format!("{other}")),
@@ -819,6 +1591,7 @@ impl ShellEngine {
         // Extract device_class and unit from the attributes dict.
         let mut device_class: Option<String> = None;
         let mut unit: Option<String> = None;
+        let mut position: Option<f64> = None;
         let mut attr_pairs: Vec<(String, String)> = Vec::new();
         let skip_keys = [
             "friendly_name",
@@ -842,6 +1615,8 @@ impl ShellEngine {
                                     if let MontyObject::String(s) = av {
                                         unit = Some(s.clone());
                                     }
+                                } else if ak_str == "current_position" {
+                                    position = self.monty_to_f64(av);
                                 }
                                 if !skip_keys.contains(&ak_str.as_str()) {
                                     attr_pairs.push((ak_str.clone(), format!("{av}")));
@@ -857,6 +1632,7 @@ impl ShellEngine {
             &entity_id,
             device_class.as_deref(),
             Some(&state),
+            position,
         );
         let state_color = crate::icons::state_color(&state);
         let time_str = format_timestamp(&last_changed);
@@ -887,6 +1663,8 @@ impl ShellEngine {
         let mut rows: Vec<Vec<String>> = Vec::new();
         let mut domain_counts: std::collections::BTreeMap<String, usize> =
             std::collections::BTreeMap::new();
+        // Domain of each row, for grouping into per-domain tables.
+        let mut row_domains: Vec<String> = Vec::new();
 
         for item in items {
             if let MontyObject::Dataclass { attrs, .. } = item {
@@ -911,6 +1689,7 @@ impl ShellEngine {
                 // Extract device_class and unit from nested attributes.
                 let mut device_class: Option<String> = None;
                 let mut unit: Option<String> = None;
+                let mut position: Option<f64> = None;
                 for (k, v) in attrs {
                     if let MontyObject::String(k_str) = k {
                         if k_str == "attributes" {
@@ -925,6 +1704,8 @@ impl ShellEngine {
                                             if let MontyObject::String(s) = av {
                                                 unit = Some(s.clone());
                                             }
+                                        } else if ak_str == "current_position" {
+                                            position = self.monty_to_f64(av);
                                         }
                                     }
                                 }
@@ -937,13 +1718,11 @@ impl ShellEngine {
                     &entity_id,
                     device_class.as_deref(),
                     Some(&state),
+                    position,
                 );
                 let indicator = crate::icons::state_indicator(&state);
                 let time_str = format_timestamp(&last_changed);
-                let state_display = match unit {
-                    Some(u) if state.parse::<f64>().is_ok() => format!("{state} {u}"),
-                    _ => state.clone(),
-                };
+                let state_display = self.state_display(&state, unit.as_deref());
 
                 rows.push(vec![
                     format!("{icon} {indicator}"),
@@ -951,6 +1730,7 @@ impl ShellEngine {
                     state_display,
                     time_str,
                 ]);
+                row_domains.push(domain.clone());
 
                 *domain_counts.entry(domain).or_insert(0) += 1;
             }
@@ -966,6 +1746,23 @@ impl ShellEngine {
             domain_parts.join(", ")
         );
 
+        // Long mixed-domain lists (e.g. `states()`) read better as one
+        // table per domain.
+        if domain_counts.len() > 1 && rows.len() > GROUP_BY_DOMAIN_MIN_ROWS {
+            let mut specs = vec![RenderSpec::summary(summary_text)];
+            for (domain, count) in &domain_counts {
+                let domain_rows: Vec<Vec<String>> = rows
+                    .iter()
+                    .zip(&row_domains)
+                    .filter(|(_, d)| *d == domain)
+                    .map(|(row, _)| row.clone())
+                    .collect();
+                specs.push(RenderSpec::summary(format!("{domain} ({count})")));
+                specs.push(RenderSpec::table(headers.clone(), domain_rows));
+            }
+            return RenderSpec::vstack(specs);
+        }
+
         RenderSpec::vstack(vec![
             RenderSpec::summary(summary_text),
             RenderSpec::table(headers, rows),
@@ -982,7 +1779,7 @@ impl ShellEngine {
 
             // Check if it's a history response: array of arrays.
             if arr[0].is_array() {
-                return self.format_history_response(&value);
+                return self.format_history_response(&value, None);
             }
 
             // Check if items look like HA state objects.
@@ -1015,15 +1812,445 @@ impl ShellEngine {
         RenderSpec::copyable(pretty, Some("JSON".into()))
     }
 
+    /// Format a `%find` response, keeping only entities whose ID matches
+    /// the glob pattern.  The host normally filters already; this guards
+    /// against hosts that return the full state list.
+    fn format_find_response(&self, value: serde_json::Value, pattern: &str) -> RenderSpec {
+        let arr = match value.as_array() {
+            Some(arr) => arr,
+            None => return self.format_host_response(value),
+        };
+        let mut matches: Vec<serde_json::Value> = arr
+            .iter()
+            .filter(|item| {
+                item.get("entity_id")
+                    .and_then(|v| v.as_str())
+                    .is_some_and(|id| glob_match(pattern, id))
+            })
+            .cloned()
+            .collect();
+        if matches.is_empty() {
+            return RenderSpec::text(format!("No entities match '{pattern}'."));
+        }
+        // Stable, so equally relevant matches keep the host's order.
+        matches.sort_by_key(|item| find_rank(item, pattern));
+        self.format_entity_table(&matches)
+    }
+
+    /// Format an entity list as a single count line (`%ls --count`).
+    fn format_entity_count(&self, value: &serde_json::Value, domain: Option<&str>) -> RenderSpec {
+        let arr = match value.as_array() {
+            Some(arr) => arr,
+            None => return self.format_host_response(value.clone()),
+        };
+        let noun = if arr.len() == 1 { "entity" } else { "entities" };
+        match domain {
+            Some(d) => RenderSpec::summary(format!("{} {d} {noun}", arr.len())),
+            None => RenderSpec::summary(format!("{} {noun}", arr.len())),
+        }
+    }
+
+    /// Format `person` states as a row of badges (`%presence`): green for
+    /// home, accent for away or a named zone, error-red when unknown.
+    fn format_presence(&self, value: &serde_json::Value) -> RenderSpec {
+        let Some(arr) = value.as_array() else {
+            return self.format_host_response(value.clone());
+        };
+        let mut people: Vec<(&str, &str)> = arr
+            .iter()
+            .filter_map(|item| {
+                let entity_id = item.get("entity_id")?.as_str()?;
+                if !entity_id.starts_with("person.") {
+                    return None;
+                }
+                let name = item
+                    .get("attributes")
+                    .and_then(|a| a.get("friendly_name"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(entity_id);
+                let state = item.get("state").and_then(|v| v.as_str()).unwrap_or("unknown");
+                Some((name, state))
+            })
+            .collect();
+        if people.is_empty() {
+            return RenderSpec::text("No person entities found.");
+        }
+        people.sort_by_key(|(name, _)| name.to_lowercase());
+
+        let home = people.iter().filter(|(_, state)| *state == "home").count();
+        let badges = people
+            .iter()
+            .map(|(name, state)| {
+                let (label, color) = match *state {
+                    "home" => ("home", "home"),
+                    "not_home" => ("away", "away"),
+                    "unknown" | "unavailable" => (*state, "unknown"),
+                    zone => (zone, "away"),
+                };
+                RenderSpec::badge(format!("{name} · {label}"), color)
+            })
+            .collect();
+
+        RenderSpec::vstack(vec![
+            RenderSpec::summary(format!("{home} of {} home", people.len())),
+            RenderSpec::hstack(badges),
+        ])
+    }
+
+    /// Cache `(entity_id, state)` pairs if `value` is a list of state
+    /// objects, for `%count`.
+    fn remember_states(&mut self, value: &serde_json::Value) {
+        let Some(arr) = value.as_array() else { return };
+        if arr.is_empty() || arr[0].get("entity_id").is_none() {
+            return;
+        }
+        let states = dedup_by_entity_id(arr)
+            .into_iter()
+            .filter_map(|item| {
+                let entity_id = item.get("entity_id")?.as_str()?;
+                let state = item.get("state").and_then(|v| v.as_str()).unwrap_or("unknown");
+                Some((entity_id.to_string(), state.to_string()))
+            })
+            .collect();
+        self.session.set_last_states(states);
+    }
+
+    /// Record the numeric state of a single-entity response, returning the
+    /// value it had the previous time it was fetched.
+    fn remember_numeric_state(&mut self, value: &serde_json::Value) -> Option<f64> {
+        let entity_id = value.get("entity_id")?.as_str()?;
+        let current = value.get("state")?.as_str()?.parse::<f64>().ok()?;
+        self.session.record_numeric_state(entity_id, current)
+    }
+
+    /// `%count` — tally the last state list by state value, most common first.
+    fn count_last_states(&self) -> RenderSpec {
+        let states = match self.session.last_states() {
+            Some(states) => states,
+            None => return RenderSpec::error("Nothing to count — list entities with %ls or states() first"),
+        };
+        let mut counts: Vec<(String, usize)> = Vec::new();
+        for (_, state) in states {
+            match counts.iter_mut().find(|(s, _)| s == state) {
+                Some((_, n)) => *n += 1,
+                None => counts.push((state.clone(), 1)),
+            }
+        }
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let pairs = counts
+            .into_iter()
+            .map(|(state, n)| (state, n.to_string()))
+            .collect();
+        RenderSpec::key_value(Some(format!("States — {} entities", states.len())), pairs)
+    }
+
+    /// `%get <group> --expand` — follow up a group's state with a fetch of
+    /// the members listed in its `entity_id` attribute. Anything without
+    /// members renders as a plain `%get`.
+    fn expand_group(&mut self, value: serde_json::Value) -> RenderSpec {
+        let members: Vec<&str> = value
+            .pointer("/attributes/entity_id")
+            .and_then(|v| v.as_array())
+            .map(|ids| ids.iter().filter_map(|id| id.as_str()).collect())
+            .unwrap_or_default();
+        if value.get("error").is_some() || members.is_empty() {
+            return self.format_host_response(value);
+        }
+        self.states_by_id_call(&members)
+    }
+
+    /// `%get <glob>` — follow up the entities the pattern found with a
+    /// fetch of their current states.
+    fn fetch_glob_matches(&mut self, value: serde_json::Value, pattern: &str) -> RenderSpec {
+        let arr = match value.as_array() {
+            Some(arr) => arr,
+            None => return self.format_host_response(value),
+        };
+        let ids: Vec<&str> = arr
+            .iter()
+            .filter_map(|item| item.get("entity_id").and_then(|v| v.as_str()))
+            .filter(|id| glob_match(pattern, id))
+            .collect();
+        if ids.is_empty() {
+            return RenderSpec::text(format!("No entities match '{pattern}'."));
+        }
+        self.states_by_id_call(&ids)
+    }
+
+    /// `%hist --compare` — fetch the latest `hours` of history, then (once
+    /// `current` has arrived) the same window `offset_hours` earlier.
+    fn hist_compare_call(
+        &mut self,
+        entity_id: String,
+        hours: u32,
+        offset_hours: u32,
+        current: Option<serde_json::Value>,
+    ) -> RenderSpec {
+        let mut params = serde_json::json!({ "entity_id": entity_id, "hours": hours });
+        if current.is_some() {
+            params["offset_hours"] = serde_json::json!(offset_hours);
+        }
+        let call_id = self.session.next_call_id();
+        self.session.store_pending_magic(PendingMagic {
+            call_id: call_id.clone(),
+            view: MagicView::HistCompare {
+                entity_id,
+                hours,
+                offset_hours,
+                current,
+            },
+        });
+        RenderSpec::host_call(call_id, "get_history", params)
+    }
+
+    /// Host call fetching the states of specific entities, rendered as the
+    /// usual entity table.
+    fn states_by_id_call(&mut self, entity_ids: &[&str]) -> RenderSpec {
+        let call_id = self.session.next_call_id();
+        RenderSpec::host_call(
+            call_id,
+            "get_states_by_id",
+            serde_json::json!({ "entity_ids": entity_ids }),
+        )
+    }
+
+    /// `%caps` — remember which host methods the host supports, so later
+    /// calls to anything else fail with a clear message.
+    fn save_capabilities(&mut self, value: &serde_json::Value) -> RenderSpec {
+        let Some(methods) = value.as_array() else {
+            return self.format_host_response(value.clone());
+        };
+        let mut methods: Vec<String> = methods
+            .iter()
+            .filter_map(|m| m.as_str().map(str::to_string))
+            .collect();
+        methods.sort();
+        let spec = RenderSpec::vstack(vec![
+            RenderSpec::summary(format!("{} host methods available", methods.len())),
+            RenderSpec::text(methods.join("\n")),
+        ]);
+        self.session.set_capabilities(methods);
+        spec
+    }
+
+    /// `%snapshot <id>` — keep the fetched state for a later
+    /// `%diff <id> @snapshot`.
+    fn save_snapshot(&mut self, entity_id: &str, value: serde_json::Value) -> RenderSpec {
+        if value.get("error").is_some() {
+            return self.format_host_response(value);
+        }
+        let state = value.get("state").and_then(|v| v.as_str()).unwrap_or("?");
+        let attrs = value
+            .get("attributes")
+            .and_then(|a| a.as_object())
+            .map_or(0, |a| a.len());
+        let summary = format!("Saved snapshot of {entity_id} (state {state}, {attrs} attributes)");
+        self.session.save_snapshot(entity_id, value);
+        RenderSpec::summary(summary)
+    }
+
+    /// `%diff <id> @snapshot` — compare the live state (right) against the
+    /// saved snapshot (left), reusing the two-entity diff table.
+    fn diff_against_snapshot(
+        &self,
+        entity_id: &str,
+        live: serde_json::Value,
+        attrs_only: bool,
+        changed_only: bool,
+    ) -> RenderSpec {
+        if live.get("error").is_some() {
+            return self.format_host_response(live);
+        }
+        let mut saved = match self.session.snapshot(entity_id) {
+            Some(saved) => saved.clone(),
+            None => return RenderSpec::error(format!("No snapshot of {entity_id}")),
+        };
+        // Label the columns apart — both sides share the entity ID.
+        saved["entity_id"] = serde_json::json!(format!("{entity_id} @snapshot"));
+        let value = serde_json::json!({ "entity_a": saved, "entity_b": live });
+        self.format_diff_response(&value, attrs_only, changed_only)
+    }
+
+    /// Advance a `%inspect` chain with the latest host response: after the
+    /// state, fetch attributes; after attributes, fetch 6h of history; after
+    /// history, render card + attributes + history as one panel.
+    fn continue_inspect(
+        &mut self,
+        entity_id: String,
+        state: Option<serde_json::Value>,
+        attrs: Option<serde_json::Value>,
+        value: serde_json::Value,
+    ) -> RenderSpec {
+        // Entity not found etc. — stop the chain and show the error as-is.
+        if value.get("error").is_some() {
+            return self.format_host_response(value);
+        }
+        let (method, params, view) = match (state, attrs) {
+            (None, _) => (
+                "get_state",
+                serde_json::json!({ "entity_id": entity_id, "attrs_only": true }),
+                MagicView::Inspect {
+                    entity_id: entity_id.clone(),
+                    state: Some(value),
+                    attrs: None,
+                },
+            ),
+            (Some(state), None) => (
+                "get_history",
+                serde_json::json!({ "entity_id": entity_id, "hours": 6 }),
+                MagicView::Inspect {
+                    entity_id: entity_id.clone(),
+                    state: Some(state),
+                    attrs: Some(value),
+                },
+            ),
+            (Some(state), Some(attrs)) => {
+                return RenderSpec::vstack(vec![
+                    self.format_entity_card(&state),
+                    self.format_attrs_response(&attrs),
+                    self.format_history_response(&value, None),
+                ]);
+            }
+        };
+        let call_id = self.session.next_call_id();
+        self.session.store_pending_magic(PendingMagic {
+            call_id: call_id.clone(),
+            view,
+        });
+        RenderSpec::host_call(call_id, method, params)
+    }
+
+    /// `%get <id> --services`, first step: with the entity's state in hand,
+    /// ask for the services of its domain.
+    fn fetch_entity_services(&mut self, state: serde_json::Value) -> RenderSpec {
+        let Some(domain) = state
+            .get("entity_id")
+            .and_then(|v| v.as_str())
+            .and_then(|id| id.split_once('.'))
+            .map(|(domain, _)| domain.to_string())
+        else {
+            return self.format_host_response(state);
+        };
+        let call_id = self.session.next_call_id();
+        self.session.store_pending_magic(PendingMagic {
+            call_id: call_id.clone(),
+            view: MagicView::EntityServices { state: Some(state) },
+        });
+        RenderSpec::host_call(call_id, "get_services", serde_json::json!({ "domain": domain }))
+    }
+
+    /// A state for an entity table cell: the unit appended to numeric
+    /// states, and whole numbers grouped (`12,345,678`) under `%fmt grouped`.
+    fn state_display(&self, state: &str, unit: Option<&str>) -> String {
+        let grouped = self.session.grouped_numbers().then(|| group_thousands(state)).flatten();
+        let shown = grouped.unwrap_or_else(|| state.to_string());
+        match unit {
+            Some(u) if state.parse::<f64>().is_ok() => format!("{shown} {u}"),
+            _ => shown,
+        }
+    }
+
+    /// Format a `call_service` result as a success badge naming the service
+    /// and its targets, or an error if the host reported a failure (or the
+    /// user cancelled it).
+    fn format_service_result_response(
+        &self,
+        value: &serde_json::Value,
+        params: &serde_json::Value,
+    ) -> RenderSpec {
+        if let Some(error) = value.get("error") {
+            return RenderSpec::error(error.as_str().map_or_else(|| error.to_string(), str::to_string));
+        }
+        let domain = params.get("domain").and_then(|v| v.as_str()).unwrap_or("?");
+        let service = params.get("service").and_then(|v| v.as_str()).unwrap_or("?");
+        let badge = RenderSpec::badge(format!("✓ {domain}.{service}"), "success");
+        let targets = match params.pointer("/service_data/entity_id") {
+            Some(serde_json::Value::String(id)) => id.clone(),
+            Some(serde_json::Value::Array(ids)) => {
+                ids.iter().filter_map(|id| id.as_str()).collect::<Vec<_>>().join(", ")
+            }
+            _ => String::new(),
+        };
+        let result = if targets.is_empty() {
+            badge
+        } else {
+            RenderSpec::hstack(vec![badge, RenderSpec::text(format!("→ {targets}"))])
+        };
+        // States the call changed, when the host reports them.
+        match value.get("states").and_then(|v| v.as_array()) {
+            Some(states) if states.iter().any(|s| s.get("entity_id").is_some()) => {
+                RenderSpec::vstack(vec![result, self.format_entity_table(states)])
+            }
+            _ => result,
+        }
+    }
+
+    /// Format a `%ls --chart` response as a pie chart of entities per domain.
+    fn format_domain_chart(&self, value: &serde_json::Value) -> RenderSpec {
+        let arr = match value.as_array() {
+            Some(arr) if !arr.is_empty() => dedup_by_entity_id(arr),
+            Some(_) => return RenderSpec::text("No entities found."),
+            None => return self.format_host_response(value.clone()),
+        };
+        let data: Vec<(String, f64)> = count_by_domain(&arr)
+            .into_iter()
+            .map(|(domain, count)| (domain, count as f64))
+            .collect();
+        RenderSpec::vstack(vec![
+            RenderSpec::summary(format!("{} entities in {} domains", arr.len(), data.len())),
+            RenderSpec::echarts(pie_chart_option(&data, false, None), Some("Entities by domain".into()), None),
+        ])
+    }
+
+    /// `%ls` with no domain: the full table for small setups, otherwise
+    /// entity counts per domain, busiest first.
+    fn format_entity_overview(&self, value: serde_json::Value) -> RenderSpec {
+        let arr = match value.as_array() {
+            Some(arr) if arr.len() > LS_OVERVIEW_MIN_ENTITIES => dedup_by_entity_id(arr),
+            _ => return self.format_host_response(value),
+        };
+        let mut counts: Vec<(String, usize)> = count_by_domain(&arr).into_iter().collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1));
+        let rows = counts
+            .iter()
+            .map(|(domain, count)| vec![domain.clone(), count.to_string()])
+            .collect();
+        RenderSpec::vstack(vec![
+            RenderSpec::summary(format!(
+                "{} entities in {} domains — %ls <domain> to list one, %ls --all for everything",
+                arr.len(),
+                counts.len()
+            )),
+            RenderSpec::table_with_types(
+                vec!["domain".into(), "entities".into()],
+                rows,
+                vec![ColumnType::Text, ColumnType::Number],
+            ),
+        ])
+    }
+
     /// Format an array of HA state objects into a table with summary.
     fn format_entity_table(&self, arr: &[serde_json::Value]) -> RenderSpec {
-        let headers = vec![
+        self.format_entity_table_with(arr, None, None)
+    }
+
+    /// [`Self::format_entity_table`] plus, for `%ls --attrs <key>`, a
+    /// column with that attribute's value per entity and, for `%ls --spark`,
+    /// a sparkline column from each entity's recent values.
+    fn format_entity_table_with(
+        &self,
+        arr: &[serde_json::Value],
+        attr: Option<&str>,
+        trends: Option<&HashMap<String, Vec<f64>>>,
+    ) -> RenderSpec {
+        let arr = dedup_by_entity_id(arr);
+        let mut headers = vec![
             " ".into(),
             "entity_id".into(),
             "state".into(),
             "last_changed".into(),
         ];
-        let rows: Vec<Vec<String>> = arr
+        let mut rows: Vec<Vec<String>> = arr
             .iter()
             .map(|item| {
                 let entity_id = item
@@ -1042,7 +2269,7 @@ impl ShellEngine {
                     .get("attributes")
                     .and_then(|a| a.get("unit_of_measurement"))
                     .and_then(|v| v.as_str());
-                let icon = icons::entity_icon(entity_id, device_class, Some(state));
+                let icon = icons::entity_icon(entity_id, device_class, Some(state), cover_position(item));
                 let indicator = icons::state_indicator(state);
                 let last_changed = item
                     .get("last_changed")
@@ -1050,11 +2277,7 @@ impl ShellEngine {
                     .unwrap_or("-");
                 let time_str = format_timestamp(last_changed);
 
-                // Append unit to numeric states.
-                let state_display = match unit {
-                    Some(u) if state.parse::<f64>().is_ok() => format!("{state} {u}"),
-                    _ => state.to_string(),
-                };
+                let state_display = self.state_display(state, unit);
 
                 vec![
                     format!("{icon} {indicator}"),
@@ -1066,13 +2289,7 @@ impl ShellEngine {
             .collect();
 
         // Count by domain for summary.
-        let mut domain_counts: std::collections::BTreeMap<String, usize> = std::collections::BTreeMap::new();
-        for item in arr {
-            if let Some(eid) = item.get("entity_id").and_then(|v| v.as_str()) {
-                let domain = eid.split('.').next().unwrap_or("?");
-                *domain_counts.entry(domain.to_string()).or_insert(0) += 1;
-            }
-        }
+        let domain_counts = count_by_domain(&arr);
         let domain_parts: Vec<String> = domain_counts
             .iter()
             .map(|(d, c)| format!("{d}: {c}"))
@@ -1083,17 +2300,97 @@ impl ShellEngine {
             domain_parts.join(", ")
         );
 
+        // Right-align the state column when every state is numeric.
+        let numeric_states = arr.iter().all(|item| {
+            item.get("state")
+                .and_then(|v| v.as_str())
+                .is_some_and(|s| s.parse::<f64>().is_ok())
+        });
+        let state_type = if numeric_states {
+            ColumnType::Number
+        } else {
+            ColumnType::Text
+        };
+        let mut column_types = vec![ColumnType::Icon, ColumnType::Text, state_type, ColumnType::Text];
+        let mut cell_styles: Vec<Vec<Option<String>>> = arr
+            .iter()
+            .map(|item| {
+                let state = item.get("state").and_then(|v| v.as_str()).unwrap_or("-");
+                vec![None, None, Some(icons::state_color(state).to_string()), None]
+            })
+            .collect();
+
+        if let Some(key) = attr {
+            let values: Vec<Option<&serde_json::Value>> = arr
+                .iter()
+                .map(|item| item.get("attributes").and_then(|a| a.get(key)))
+                .collect();
+            let numeric = values.iter().flatten().all(|v| v.is_number());
+            headers.push(key.to_string());
+            column_types.push(if numeric { ColumnType::Number } else { ColumnType::Text });
+            for ((row, styles), value) in rows.iter_mut().zip(&mut cell_styles).zip(values) {
+                row.push(value.map_or_else(|| "-".to_string(), format_json_value));
+                styles.push(None);
+            }
+        }
+
+        let mut sparklines = Vec::new();
+        if let Some(trends) = trends {
+            headers.push("trend".into());
+            column_types.push(ColumnType::Sparkline);
+            for ((row, styles), item) in rows.iter_mut().zip(&mut cell_styles).zip(&arr) {
+                let entity_id = item.get("entity_id").and_then(|v| v.as_str()).unwrap_or("");
+                row.push(String::new());
+                styles.push(None);
+                sparklines.push(trends.get(entity_id).cloned().unwrap_or_default());
+            }
+        }
+
         RenderSpec::vstack(vec![
             RenderSpec::summary(summary_text),
-            RenderSpec::table(headers, rows),
+            RenderSpec::table_with_sparklines(headers, rows, column_types, cell_styles, sparklines),
         ])
     }
 
+    /// `%ls --spark` — fetch recent history for every numeric entity in
+    /// the states list in one call; non-numeric entities get no sparkline.
+    fn fetch_spark_history(&mut self, value: serde_json::Value, attr: Option<String>) -> RenderSpec {
+        let arr = match value.as_array() {
+            Some(arr) if !arr.is_empty() => arr,
+            _ => return self.format_host_response(value),
+        };
+        let ids: Vec<&str> = arr
+            .iter()
+            .filter(|item| {
+                item.get("state")
+                    .and_then(|v| v.as_str())
+                    .is_some_and(|s| s.parse::<f64>().is_ok())
+            })
+            .filter_map(|item| item.get("entity_id").and_then(|v| v.as_str()))
+            .collect();
+        if ids.is_empty() {
+            return self.format_entity_table_with(arr, attr.as_deref(), None);
+        }
+
+        let call_id = self.session.next_call_id();
+        let params = serde_json::json!({ "entity_ids": ids, "hours": SPARK_HOURS });
+        self.session.store_pending_magic(PendingMagic {
+            call_id: call_id.clone(),
+            view: MagicView::SparkHistory { attr, states: value },
+        });
+        RenderSpec::host_call(call_id, "get_history", params)
+    }
+
     /// Format a history API response into a sparkline or timeline.
     ///
     /// History API returns `[[{entity_id, state, last_changed}, ...]]`.
-    /// Numeric entities → sparkline, binary/discrete → timeline.
-    fn format_history_response(&self, value: &serde_json::Value) -> RenderSpec {
+    /// Numeric entities → sparkline, binary/discrete → timeline, unless
+    /// `render_as` forces a view.
+    fn format_history_response(
+        &self,
+        value: &serde_json::Value,
+        render_as: Option<HistoryView>,
+    ) -> RenderSpec {
         let outer = match value.as_array() {
             Some(arr) => arr,
             None => return RenderSpec::error("Invalid history response format."),
@@ -1132,7 +2429,25 @@ impl ShellEngine {
                     .unwrap_or(false)
             });
 
-            if is_numeric {
+            let default_view = if is_numeric {
+                HistoryView::Sparkline
+            } else {
+                HistoryView::Timeline
+            };
+
+            if render_as == Some(HistoryView::Table) {
+                let rows: Vec<Vec<String>> = arr
+                    .iter()
+                    .map(|entry| {
+                        let get = |key: &str| {
+                            entry.get(key).and_then(|v| v.as_str()).unwrap_or("").to_string()
+                        };
+                        vec![format_timestamp(&get("last_changed")), get("state")]
+                    })
+                    .collect();
+                specs.push(RenderSpec::summary(format!("{name} — {} changes", rows.len())));
+                specs.push(RenderSpec::table(vec!["time".into(), "state".into()], rows));
+            } else if render_as.unwrap_or(default_view) == HistoryView::Sparkline {
                 // Build sparkline from numeric states.
                 let mut points: Vec<(f64, f64)> = Vec::new();
                 let unit = arr[0]
@@ -1146,18 +2461,37 @@ impl ShellEngine {
                         .get("state")
                         .and_then(|v| v.as_str())
                         .unwrap_or("");
-                    if let Ok(val) = state_str.parse::<f64>() {
-                        let ts = entry
-                            .get("last_changed")
-                            .and_then(|v| v.as_str())
-                            .and_then(parse_iso_to_ms)
-                            .unwrap_or(0.0);
-                        points.push((ts, val));
+                    let ts = entry
+                        .get("last_changed")
+                        .and_then(|v| v.as_str())
+                        .and_then(parse_iso_to_ms)
+                        .unwrap_or(0.0);
+                    match state_str.parse::<f64>() {
+                        Ok(val) => points.push((ts, val)),
+                        // "unavailable"/"unknown" — break the line with a gap
+                        // rather than drawing straight across the outage.
+                        Err(_) => {
+                            if points.last().is_some_and(|(_, v)| !v.is_nan()) {
+                                points.push((ts, f64::NAN));
+                            }
+                        }
                     }
                 }
 
-                if !points.is_empty() {
-                    specs.push(RenderSpec::sparkline(entity_id, name, unit, points));
+                let values: Vec<f64> = points.iter().map(|(_, v)| *v).filter(|v| !v.is_nan()).collect();
+                // One reading (or one value throughout) has no line to
+                // draw — say what the value was instead.
+                if let Some(&first) = values.first() {
+                    if values.iter().all(|v| *v == first) {
+                        let unit = unit.map(|u| format!(" {u}")).unwrap_or_default();
+                        let note = match values.len() {
+                            1 => "only one reading in this period".to_string(),
+                            n => format!("unchanged across {n} readings"),
+                        };
+                        specs.push(RenderSpec::summary(format!("{name}: {first}{unit} ({note})")));
+                    } else {
+                        specs.push(RenderSpec::sparkline(entity_id, name, unit, points));
+                    }
                 }
             } else {
                 // Build timeline from discrete states.
@@ -1195,7 +2529,7 @@ impl ShellEngine {
                         end_time
                     };
 
-                    let color = state_to_timeline_color(&state);
+                    let color = state_to_timeline_color(&state, self.session.theme());
                     segments.push((seg_start, seg_end, state, color));
                 }
 
@@ -1214,26 +2548,150 @@ impl ShellEngine {
         }
     }
 
-    /// Format a statistics API response into a sparkline.
-    ///
-    /// Statistics API returns `{entity_id: [{start, end, mean, min, max, ...}]}`.
-    fn format_statistics_response(&self, value: &serde_json::Value) -> RenderSpec {
-        let obj = match value.as_object() {
-            Some(o) => o,
-            None => return RenderSpec::error("Invalid statistics response format."),
-        };
+    /// Format a multi-entity history response as one line chart with a
+    /// series per entity. Falls back to the per-entity view when any
+    /// series isn't numeric.
+    fn format_history_overlay(&self, value: &serde_json::Value) -> RenderSpec {
+        let outer: Vec<&Vec<serde_json::Value>> = value
+            .as_array()
+            .map(|arr| arr.iter().filter_map(|h| h.as_array()).filter(|h| !h.is_empty()).collect())
+            .unwrap_or_default();
 
-        if obj.is_empty() {
-            return RenderSpec::text("No statistics data.");
+        let numeric = |entry: &serde_json::Value| {
+            entry.get("state").and_then(|v| v.as_str()).and_then(|s| s.parse::<f64>().ok())
+        };
+        if outer.len() < 2 || !outer.iter().all(|h| h.iter().take(5).any(|e| numeric(e).is_some())) {
+            return self.format_history_response(value, None);
         }
 
-        let mut specs = Vec::new();
+        let mut units = Vec::new();
+        let series: Vec<serde_json::Value> = outer
+            .iter()
+            .map(|history| {
+                let attrs = history[0].get("attributes");
+                let entity_id = history[0].get("entity_id").and_then(|v| v.as_str()).unwrap_or("?");
+                let name = attrs
+                    .and_then(|a| a.get("friendly_name"))
+                    .and_then(|v| v.as_str())
+                    .unwrap_or(entity_id);
+                units.push(attrs.and_then(|a| a.get("unit_of_measurement")).and_then(|v| v.as_str()));
+                // Non-numeric states become nulls so the line breaks.
+                let data: Vec<serde_json::Value> = history
+                    .iter()
+                    .filter_map(|entry| {
+                        let ts = entry.get("last_changed").and_then(|v| v.as_str()).and_then(parse_iso_to_ms)?;
+                        Some(serde_json::json!([ts, numeric(entry)]))
+                    })
+                    .collect();
+                serde_json::json!({
+                    "type": "line",
+                    "name": name,
+                    "data": data,
+                    "showSymbol": false,
+                    "connectNulls": false,
+                })
+            })
+            .collect();
 
-        for (entity_id, stats_value) in obj {
-            let stats = match stats_value.as_array() {
-                Some(a) if !a.is_empty() => a,
-                _ => continue,
-            };
+        // Label the y-axis only when every series shares a unit.
+        let mut y_axis = serde_json::json!({ "type": "value", "scale": true });
+        if let Some(&Some(unit)) = units.first().filter(|first| units.iter().all(|u| u == *first)) {
+            y_axis["name"] = serde_json::json!(unit);
+        }
+
+        let option = serde_json::json!({
+            "tooltip": { "trigger": "axis" },
+            "legend": { "show": true },
+            "grid": { "left": "12%", "right": "5%", "bottom": "15%", "top": "15%" },
+            "xAxis": { "type": "time" },
+            "yAxis": y_axis,
+            "series": series,
+        });
+        RenderSpec::echarts(option, None, None)
+    }
+
+    /// Overlay two `hours`-long history windows, shifting the earlier one
+    /// forward by `offset_hours` so both line up by time of day.
+    fn format_history_compare(
+        &self,
+        current: &serde_json::Value,
+        previous: &serde_json::Value,
+        hours: u32,
+        offset_hours: u32,
+    ) -> RenderSpec {
+        let offset_ms = f64::from(offset_hours) * 3_600_000.0;
+        let points = |value: &serde_json::Value, shift: f64| -> Vec<serde_json::Value> {
+            value
+                .get(0)
+                .and_then(|h| h.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|entry| {
+                    let ts = entry.get("last_changed").and_then(|v| v.as_str()).and_then(parse_iso_to_ms)?;
+                    let state = entry.get("state").and_then(|v| v.as_str())?.parse::<f64>().ok();
+                    Some(serde_json::json!([ts + shift, state]))
+                })
+                .collect()
+        };
+        let now = points(current, 0.0);
+        let before = points(previous, offset_ms);
+        let has_numbers = |pts: &[serde_json::Value]| pts.iter().any(|p| p[1].is_number());
+        if !has_numbers(&now) && !has_numbers(&before) {
+            return RenderSpec::error("--compare needs a numeric entity with history in both windows.");
+        }
+
+        let first = current.get(0).and_then(|h| h.get(0));
+        let entity_id = first.and_then(|e| e.get("entity_id")).and_then(|v| v.as_str()).unwrap_or("?");
+        let name = first
+            .and_then(|e| e.get("attributes"))
+            .and_then(|a| a.get("friendly_name"))
+            .and_then(|v| v.as_str())
+            .unwrap_or(entity_id);
+        let span = |hours: u32| {
+            if hours.is_multiple_of(24) {
+                format!("{}d", hours / 24)
+            } else {
+                format!("{hours}h")
+            }
+        };
+        let series = |label: String, data: Vec<serde_json::Value>| {
+            serde_json::json!({ "type": "line", "name": label, "data": data, "showSymbol": false })
+        };
+
+        let option = serde_json::json!({
+            "tooltip": { "trigger": "axis" },
+            "legend": { "show": true },
+            "grid": { "left": "12%", "right": "5%", "bottom": "15%", "top": "15%" },
+            "xAxis": { "type": "time" },
+            "yAxis": { "type": "value", "scale": true },
+            "series": [
+                series(format!("last {}", span(hours)), now),
+                series(format!("{} before", span(offset_hours)), before),
+            ],
+        });
+        RenderSpec::echarts(option, Some(name.to_string()), None)
+    }
+
+    /// Format a statistics API response into a sparkline.
+    ///
+    /// Statistics API returns `{entity_id: [{start, end, mean, min, max, ...}]}`.
+    fn format_statistics_response(&self, value: &serde_json::Value) -> RenderSpec {
+        let obj = match value.as_object() {
+            Some(o) => o,
+            None => return RenderSpec::error("Invalid statistics response format."),
+        };
+
+        if obj.is_empty() {
+            return RenderSpec::text("No statistics data.");
+        }
+
+        let mut specs = Vec::new();
+
+        for (entity_id, stats_value) in obj {
+            let stats = match stats_value.as_array() {
+                Some(a) if !a.is_empty() => a,
+                _ => continue,
+            };
 
             // Use mean if available, fall back to state.
             let mut points: Vec<(f64, f64)> = Vec::new();
@@ -1257,12 +2715,16 @@ impl ShellEngine {
             }
 
             if !points.is_empty() {
+                // Reference line at the mean over the whole period, so the
+                // current value reads as above or below average.
+                let mean = points.iter().map(|(_, v)| v).sum::<f64>() / points.len() as f64;
                 // For now, use entity_id as the name — we don't have friendly_name in statistics.
-                specs.push(RenderSpec::sparkline(
+                specs.push(RenderSpec::sparkline_with_baseline(
                     entity_id.clone(),
                     entity_id.clone(),
                     None,
                     points,
+                    mean,
                 ));
             }
         }
@@ -1477,6 +2939,7 @@ impl ShellEngine {
     /// Format a traces response (get_trace or list_traces) into a rich trace list.
     ///
     /// Input: JSON array of `{run_id, automation?, state, start, finish?, trigger?, last_step?, execution?, error?}`.
+    /// A `state` param (from `state="error"`) keeps only runs in that state.
     fn format_traces_response(
         &self,
         value: serde_json::Value,
@@ -1501,6 +2964,7 @@ impl ShellEngine {
                     format!("automation.{s}")
                 }
             });
+        let state_filter = params.get("state").and_then(|v| v.as_str());
 
         let entries: Vec<TraceEntry> = arr
             .iter()
@@ -1545,12 +3009,24 @@ impl ShellEngine {
                     .and_then(|v| v.as_str())
                     .map(|s| s.to_string()),
             })
+            .filter(|entry| state_filter.is_none_or(|state| entry.state == state))
             .collect();
 
+        if entries.is_empty() {
+            return RenderSpec::text(format!(
+                "No traces with state '{}'.",
+                state_filter.unwrap_or_default()
+            ));
+        }
+
         let count = entries.len();
+        let runs = match state_filter {
+            Some(state) => format!("{state} traces"),
+            None => "traces".to_string(),
+        };
         let title = match &automation_id {
-            Some(id) => format!("{count} traces for {id}"),
-            None => format!("{count} recent automation traces"),
+            Some(id) => format!("{count} {runs} for {id}"),
+            None => format!("{count} recent automation {runs}"),
         };
 
         RenderSpec::vstack(vec![
@@ -1559,17 +3035,99 @@ impl ShellEngine {
         ])
     }
 
+    /// Format a `get_areas` response as a table of areas.
+    ///
+    /// Input: JSON array of `{area_id, name, floor_id}`, optionally with an
+    /// `entity_count` per area. Floor and entity columns only appear when
+    /// some area has them.
+    fn format_areas_response(&self, value: &serde_json::Value) -> RenderSpec {
+        if let Some(err) = value.get("error").and_then(|v| v.as_str()) {
+            return RenderSpec::error(err);
+        }
+        let Some(areas) = value.as_array() else {
+            return RenderSpec::error("Invalid areas response format.");
+        };
+        if areas.is_empty() {
+            return RenderSpec::text("No areas defined.");
+        }
+
+        let text = |area: &serde_json::Value, key: &str| {
+            area.get(key).and_then(|v| v.as_str()).unwrap_or("-").to_string()
+        };
+        let has_floors = areas.iter().any(|a| a.get("floor_id").is_some_and(|f| !f.is_null()));
+        let has_counts = areas.iter().any(|a| a.get("entity_count").is_some());
+
+        let mut headers = vec!["area".to_string(), "area_id".to_string()];
+        let mut column_types = vec![ColumnType::Text, ColumnType::Text];
+        if has_floors {
+            headers.push("floor".into());
+            column_types.push(ColumnType::Text);
+        }
+        if has_counts {
+            headers.push("entities".into());
+            column_types.push(ColumnType::Number);
+        }
+
+        let rows = areas
+            .iter()
+            .map(|area| {
+                let mut row = vec![text(area, "name"), text(area, "area_id")];
+                if has_floors {
+                    row.push(text(area, "floor_id"));
+                }
+                if has_counts {
+                    row.push(
+                        area.get("entity_count")
+                            .and_then(|v| v.as_u64())
+                            .map_or_else(|| "-".to_string(), |n| n.to_string()),
+                    );
+                }
+                row
+            })
+            .collect();
+
+        RenderSpec::vstack(vec![
+            RenderSpec::summary(format!("{} areas", areas.len())),
+            RenderSpec::table_with_types(headers, rows, column_types),
+        ])
+    }
+
     /// Format a services list response into a table.
     ///
     /// Input: JSON array of `{domain, service, name, description, fields}`.
-    fn format_services_response(&self, value: serde_json::Value) -> RenderSpec {
-        let arr = match value.as_array() {
+    ///
+    /// With a `query`, only services whose domain, service, name or
+    /// description contain it (case-insensitive) are listed.
+    fn format_services_response(&self, value: serde_json::Value, query: Option<&str>) -> RenderSpec {
+        if let Some(err) = value.get("error").and_then(|v| v.as_str()) {
+            return RenderSpec::error(err);
+        }
+        let all = match value.as_array() {
             Some(a) => a,
             None => return RenderSpec::error("Invalid services response format."),
         };
 
+        let arr: Vec<&serde_json::Value> = match query {
+            Some(q) => {
+                let q = q.to_lowercase();
+                all.iter()
+                    .filter(|e| {
+                        ["domain", "service", "name", "description"].iter().any(|key| {
+                            e.get(key)
+                                .and_then(|v| v.as_str())
+                                .is_some_and(|s| s.to_lowercase().contains(&q))
+                        })
+                    })
+                    .collect()
+            }
+            None => all.iter().collect(),
+        };
+
         if arr.is_empty() {
-            return RenderSpec::text("No services found.");
+            return match query {
+                Some(q) => RenderSpec::text(format!("No services match '{q}'.")),
+                None => RenderSpec::text("No services found."),
+            };
         }
 
         let headers = vec![
@@ -1607,7 +3165,7 @@ impl ShellEngine {
         // Count by domain for summary.
         let mut domain_counts: std::collections::BTreeMap<String, usize> =
             std::collections::BTreeMap::new();
-        for item in arr {
+        for item in &arr {
             if let Some(d) = item.get("domain").and_then(|v| v.as_str()) {
                 *domain_counts.entry(d.to_string()).or_insert(0) += 1;
             }
@@ -1628,6 +3186,66 @@ impl ShellEngine {
         ])
     }
 
+    /// Format a `render_template` response.
+    ///
+    /// HA returns template output as text, so a bare string or number is
+    /// shown in a copyable block.  Output that looks like JSON (templates
+    /// ending in `| to_json`, list/dict literals) is parsed and shown as
+    /// structured data instead.
+    fn format_template_response(&self, value: serde_json::Value) -> RenderSpec {
+        if let Some(err) = value.get("error").and_then(|v| v.as_str()) {
+            return RenderSpec::error(err);
+        }
+        let result = value.get("result").cloned().unwrap_or(value);
+
+        let parsed = match &result {
+            serde_json::Value::String(s) => {
+                let t = s.trim();
+                if t.starts_with('{') || t.starts_with('[') {
+                    serde_json::from_str::<serde_json::Value>(t).ok()
+                } else {
+                    None
+                }
+            }
+            serde_json::Value::Object(_) | serde_json::Value::Array(_) => Some(result.clone()),
+            _ => None,
+        };
+
+        match parsed {
+            Some(serde_json::Value::Object(obj)) => {
+                if obj.is_empty() {
+                    return RenderSpec::text("{}");
+                }
+                let pairs = obj
+                    .iter()
+                    .map(|(k, v)| (k.clone(), format_json_value(v)))
+                    .collect();
+                RenderSpec::key_value(Some("Template result".into()), pairs)
+            }
+            Some(serde_json::Value::Array(arr)) => {
+                if arr.is_empty() {
+                    return RenderSpec::text("[]");
+                }
+                if arr[0].get("entity_id").is_some() {
+                    return self.format_entity_table(&arr);
+                }
+                let rows = arr
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| vec![i.to_string(), format_json_value(v)])
+                    .collect();
+                RenderSpec::table(vec!["#".into(), "value".into()], rows)
+            }
+            _ => {
+                let text = match &result {
+                    serde_json::Value::String(s) => s.trim().to_string(),
+                    other => format_json_value(other),
+                };
+                RenderSpec::copyable(text, Some("template result".into()))
+            }
+        }
+    }
+
     /// Format a datetime response into a key-value display.
     fn format_datetime_response(&self, value: serde_json::Value) -> RenderSpec {
         let mut pairs = Vec::new();
@@ -1641,13 +3259,13 @@ impl ShellEngine {
         if let Some(day) = value.get("day_of_week").and_then(|v| v.as_str()) {
             pairs.push(("day".to_string(), day.to_string()));
         }
-        if let Some(tz) = value.get("timezone").and_then(|v| v.as_str()) {
-            pairs.push(("timezone".to_string(), tz.to_string()));
+        let browser_tz = value.get("timezone").and_then(|v| v.as_str());
+        let ha_tz = value.get("ha_timezone").and_then(|v| v.as_str());
+        if let Some(tz) = browser_tz {
+            pairs.push(("browser tz".to_string(), tz.to_string()));
         }
-        if let Some(ha_tz) = value.get("ha_timezone").and_then(|v| v.as_str()) {
-            if ha_tz != value.get("timezone").and_then(|v| v.as_str()).unwrap_or("") {
-                pairs.push(("ha_timezone".to_string(), ha_tz.to_string()));
-            }
+        if let Some(tz) = ha_tz {
+            pairs.push(("HA tz".to_string(), tz.to_string()));
         }
         if let Some(iso) = value.get("iso").and_then(|v| v.as_str()) {
             pairs.push(("iso".to_string(), iso.to_string()));
@@ -1660,7 +3278,17 @@ impl ShellEngine {
             return RenderSpec::copyable(pretty, Some("datetime".into()));
         }
 
-        RenderSpec::key_value(Some("  now".to_string()), pairs)
+        let card = RenderSpec::key_value(Some("  now".to_string()), pairs);
+        match (browser_tz, ha_tz) {
+            (Some(browser), Some(ha)) if browser != ha => RenderSpec::vstack(vec![
+                card,
+                RenderSpec::summary(format!(
+                    "Browser ({browser}) and HA ({ha}) time zones differ — \
+                     timestamps may be interpreted in HA's zone."
+                )),
+            ]),
+            _ => card,
+        }
     }
 
     /// Format a single HA state object as a rich entity card.
@@ -1691,7 +3319,7 @@ impl ShellEngine {
             .and_then(|v| v.as_str())
             .unwrap_or("-");
 
-        let icon = icons::entity_icon(entity_id, device_class, Some(state));
+        let icon = icons::entity_icon(entity_id, device_class, Some(state), cover_position(value));
         let state_color = icons::state_color(state);
         let name = friendly_name.unwrap_or(entity_id);
         let time_str = format_timestamp(last_changed);
@@ -1738,6 +3366,63 @@ impl ShellEngine {
         )
     }
 
+    /// Format a single HA state object as a compact badge — icon, name and
+    /// a state-colored badge. Used by `%get <id> --badge`.
+    fn format_entity_badge(&self, value: &serde_json::Value) -> RenderSpec {
+        let entity_id = match value.get("entity_id").and_then(|v| v.as_str()) {
+            Some(id) => id,
+            // Not a state object (e.g. an error envelope) — use default formatting.
+            None => return self.format_host_response(value.clone()),
+        };
+        let state = value
+            .get("state")
+            .and_then(|v| v.as_str())
+            .unwrap_or("?");
+        let device_class = value
+            .get("attributes")
+            .and_then(|a| a.get("device_class"))
+            .and_then(|v| v.as_str());
+        let name = value
+            .get("attributes")
+            .and_then(|a| a.get("friendly_name"))
+            .and_then(|v| v.as_str())
+            .unwrap_or(entity_id);
+
+        let icon = icons::entity_icon(entity_id, device_class, Some(state), cover_position(value));
+        RenderSpec::hstack(vec![
+            RenderSpec::badge(state, icons::state_color(state)),
+            RenderSpec::text(format!("{icon} {name}")),
+        ])
+    }
+
+    /// Format one attribute of a HA state object (`%get <id> <attribute>`).
+    ///
+    /// The path is dotted and looked up under `attributes` (an explicit
+    /// `attributes.` prefix is allowed); top-level fields like `state` or
+    /// `last_changed` work too.
+    fn format_entity_attribute(&self, value: &serde_json::Value, path: &str) -> RenderSpec {
+        let entity_id = match value.get("entity_id").and_then(|v| v.as_str()) {
+            Some(id) => id,
+            None => return self.format_host_response(value.clone()),
+        };
+        let lookup = |root: &serde_json::Value, path: &str| {
+            path.split('.').try_fold(root.clone(), |node, key| match &node {
+                serde_json::Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i).cloned()),
+                _ => node.get(key).cloned(),
+            })
+        };
+        let attrs = value.get("attributes").cloned().unwrap_or(serde_json::Value::Null);
+        let found = match path.strip_prefix("attributes.") {
+            Some(rest) => lookup(&attrs, rest),
+            None => lookup(&attrs, path).or_else(|| lookup(value, path)),
+        };
+
+        match found {
+            Some(v) => RenderSpec::copyable(format_json_value(&v), Some(format!("{entity_id} · {path}"))),
+            None => RenderSpec::error(format!("{entity_id} has no attribute '{path}'")),
+        }
+    }
+
     /// Format an attrs-only response as a key-value table.
     fn format_attrs_response(&self, value: &serde_json::Value) -> RenderSpec {
         let entity = value.get("entity").unwrap_or(value);
@@ -1769,80 +3454,117 @@ impl ShellEngine {
             return RenderSpec::text(format!("{entity_id} has no attributes."));
         }
 
-        RenderSpec::key_value(
-            Some(format!("Attributes — {entity_id}")),
-            pairs,
-        )
+        let title = Some(format!("Attributes — {entity_id}"));
+        if pairs.len() > ATTR_SECTION_THRESHOLD {
+            return RenderSpec::key_value_sections(title, group_attributes(pairs));
+        }
+        RenderSpec::key_value(title, pairs)
     }
 
     /// Format a diff response comparing two entities.
-    fn format_diff_response(&self, value: &serde_json::Value) -> RenderSpec {
+    ///
+    /// `attrs_only` drops the state row; `changed_only` keeps only rows
+    /// whose two values differ.
+    fn format_diff_response(
+        &self,
+        value: &serde_json::Value,
+        attrs_only: bool,
+        changed_only: bool,
+    ) -> RenderSpec {
         let entity_a = value.get("entity_a").unwrap_or(&serde_json::Value::Null);
         let entity_b = value.get("entity_b").unwrap_or(&serde_json::Value::Null);
+        self.format_diff_table(&[entity_a, entity_b], attrs_only, changed_only)
+    }
 
-        let id_a = entity_a
-            .get("entity_id")
-            .and_then(|v| v.as_str())
-            .unwrap_or("?");
-        let id_b = entity_b
-            .get("entity_id")
-            .and_then(|v| v.as_str())
-            .unwrap_or("?");
-        let state_a = entity_a
-            .get("state")
-            .and_then(|v| v.as_str())
-            .unwrap_or("?");
-        let state_b = entity_b
-            .get("state")
-            .and_then(|v| v.as_str())
-            .unwrap_or("?");
+    /// `%diff a b c ...` — compare the states `get_states_by_id` returned,
+    /// in the order the IDs were given.
+    fn format_multi_diff(
+        &self,
+        value: &serde_json::Value,
+        entity_ids: &[String],
+        attrs_only: bool,
+        changed_only: bool,
+    ) -> RenderSpec {
+        let Some(states) = value.as_array() else {
+            return self.format_host_response(value.clone());
+        };
+        let mut entities = Vec::with_capacity(entity_ids.len());
+        for id in entity_ids {
+            match states
+                .iter()
+                .find(|s| s.get("entity_id").and_then(|v| v.as_str()) == Some(id))
+            {
+                Some(state) => entities.push(state),
+                None => return RenderSpec::error(format!("Entity not found: {id}")),
+            }
+        }
+        self.format_diff_table(&entities, attrs_only, changed_only)
+    }
+
+    /// Side-by-side comparison table: one row per state/attribute, one
+    /// value column per entity.
+    fn format_diff_table(
+        &self,
+        entities: &[&serde_json::Value],
+        attrs_only: bool,
+        changed_only: bool,
+    ) -> RenderSpec {
+        let ids: Vec<&str> = entities
+            .iter()
+            .map(|e| e.get("entity_id").and_then(|v| v.as_str()).unwrap_or("?"))
+            .collect();
 
         // Build comparison table.
         let mut rows: Vec<Vec<String>> = Vec::new();
-        rows.push(vec!["state".into(), state_a.to_string(), state_b.to_string()]);
-
-        // Collect all attribute keys from both entities.
-        let attrs_a = entity_a.get("attributes").and_then(|a| a.as_object());
-        let attrs_b = entity_b.get("attributes").and_then(|a| a.as_object());
-
-        let mut all_keys: Vec<String> = Vec::new();
-        if let Some(a) = attrs_a {
-            for k in a.keys() {
-                if !all_keys.contains(k) {
-                    all_keys.push(k.clone());
-                }
-            }
-        }
-        if let Some(b) = attrs_b {
-            for k in b.keys() {
-                if !all_keys.contains(k) {
-                    all_keys.push(k.clone());
-                }
-            }
+        if !attrs_only {
+            let mut row = vec!["state".to_string()];
+            row.extend(
+                entities
+                    .iter()
+                    .map(|e| e.get("state").and_then(|v| v.as_str()).unwrap_or("?").to_string()),
+            );
+            rows.push(row);
         }
+
+        // Collect all attribute keys from every entity.
+        let attrs: Vec<Option<&serde_json::Map<String, serde_json::Value>>> = entities
+            .iter()
+            .map(|e| e.get("attributes").and_then(|a| a.as_object()))
+            .collect();
+        let mut all_keys: Vec<&String> = attrs.iter().flatten().flat_map(|a| a.keys()).collect();
         all_keys.sort();
+        all_keys.dedup();
 
         let skip_keys = ["friendly_name", "icon", "entity_picture", "supported_features"];
-        for key in &all_keys {
+        for key in all_keys {
             if skip_keys.contains(&key.as_str()) {
                 continue;
             }
-            let val_a = attrs_a
-                .and_then(|a| a.get(key))
-                .map(|v| format_json_value(v))
-                .unwrap_or_else(|| "—".to_string());
-            let val_b = attrs_b
-                .and_then(|b| b.get(key))
-                .map(|v| format_json_value(v))
-                .unwrap_or_else(|| "—".to_string());
-            rows.push(vec![key.clone(), val_a, val_b]);
+            let values: Vec<Option<&serde_json::Value>> =
+                attrs.iter().map(|a| a.and_then(|a| a.get(key))).collect();
+            diff_rows(key, &values, &mut rows);
         }
 
-        let headers = vec!["attribute".into(), id_a.to_string(), id_b.to_string()];
+        let differs = |row: &Vec<String>| row[2..].iter().any(|v| *v != row[1]);
+        if changed_only {
+            rows.retain(differs);
+        }
+
+        // Differences stand out; rows that match everywhere fade back.
+        let cell_styles: Vec<Vec<Option<String>>> = rows
+            .iter()
+            .map(|row| {
+                let style = if differs(row) { "warning" } else { "dim" };
+                vec![Some(style.to_string()); row.len()]
+            })
+            .collect();
+
+        let mut headers = vec!["attribute".to_string()];
+        headers.extend(ids.iter().map(|id| id.to_string()));
 
         RenderSpec::vstack(vec![
-            RenderSpec::summary(format!("Comparing {id_a} ↔ {id_b}")),
-            RenderSpec::table(headers, rows),
+            RenderSpec::summary(format!("Comparing {}", ids.join(" ↔ "))),
+            RenderSpec::table_styled(headers, rows, Vec::new(), cell_styles),
         ])
     }
 
@@ -1852,14 +3574,64 @@ impl ShellEngine {
 
     /// Build a RenderSpec for a chart call (plot_line, plot_bar, plot_pie).
     /// Returns the chart spec directly — no host call needed.
-    fn build_chart(&self, function_name: &str, args: &[MontyObject]) -> RenderSpec {
-        match function_name {
-            "plot_line" => self.build_line_or_bar_chart("line", args),
-            "plot_bar" => self.build_line_or_bar_chart("bar", args),
-            "plot_pie" => self.build_pie_chart(args),
-            "plot_series" => self.build_series_chart(args),
-            _ => RenderSpec::error(format!("Unknown chart function: {function_name}")),
+    fn build_chart(
+        &self,
+        function_name: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> RenderSpec {
+        let mut spec = match function_name {
+            "plot_line" => self.build_line_or_bar_chart("line", args, kwargs),
+            "plot_bar" => self.build_line_or_bar_chart("bar", args, kwargs),
+            "plot_pie" => return self.build_pie_chart(args, kwargs),
+            "plot_series" => self.build_series_chart(args, kwargs),
+            _ => return RenderSpec::error(format!("Unknown chart function: {function_name}")),
+        };
+        // `unit="°C"` — the card turns this template into the tooltip's
+        // valueFormatter function (JSON can't carry functions).
+        if let (Some(unit), RenderSpec::ECharts { option, .. }) = (kwarg_str(kwargs, "unit"), &mut spec) {
+            option["tooltip"]["valueFormatter"] = serde_json::json!(format!("{{value}} {unit}"));
+        }
+        spec
+    }
+
+    /// Chart the last result for `%plot`: a list of numbers becomes a line
+    /// chart, a list of (x, y) pairs a series chart, and a `{name: number}`
+    /// dict a bar chart.
+    fn plot_last_result(&self) -> RenderSpec {
+        let Some(value) = self.session.last_result() else {
+            return RenderSpec::error("Nothing to plot — evaluate an expression first");
+        };
+
+        if let MontyObject::Dict(pairs) = value {
+            let (labels, values): (Vec<MontyObject>, Vec<MontyObject>) =
+                pairs.into_iter().cloned().unzip();
+            return self.build_line_or_bar_chart(
+                "bar",
+                &[MontyObject::List(labels), MontyObject::List(values)],
+                &[],
+            );
+        }
+
+        if let Some(items) = monty_sequence(value) {
+            if !items.is_empty() && items.iter().all(|i| self.monty_to_f64(i).is_some()) {
+                let labels = (0..items.len())
+                    .map(|i| MontyObject::String(i.to_string()))
+                    .collect();
+                return self.build_line_or_bar_chart(
+                    "line",
+                    &[MontyObject::List(labels), MontyObject::List(items.to_vec())],
+                    &[],
+                );
+            }
+            if self.monty_to_xy_points(value).is_some() {
+                return self.build_series_chart(std::slice::from_ref(value), &[]);
+            }
         }
+
+        RenderSpec::error(
+            "Can't plot the last result — expected a list of numbers, (x, y) pairs, or a {name: number} dict",
+        )
     }
 
     /// Build a line or bar chart from args:
@@ -1867,20 +3639,49 @@ impl ShellEngine {
     ///   plot_line(labels, {"Series A": [...], "Series B": [...]}, title?)
     /// or dict form:
     ///   plot_line({"labels": [...], "series": {...}}, title?)
-    fn build_line_or_bar_chart(&self, chart_type: &str, args: &[MontyObject]) -> RenderSpec {
-        let (labels, series_map, title) = match self.parse_xy_args(args) {
+    ///
+    /// Series keep the dict's insertion order (Monty dicts are ordered like
+    /// Python's), so legend order matches what the user wrote. Don't collect
+    /// them into a map.
+    fn build_line_or_bar_chart(
+        &self,
+        chart_type: &str,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> RenderSpec {
+        let (labels, mut series_map, title) = match self.parse_xy_args(args) {
             Ok(v) => v,
             Err(e) => return RenderSpec::error(e),
         };
+        // ECharts pairs values with labels by index, so a short or long
+        // series would silently shift against the axis.
+        if let Some((name, values)) = series_map.iter().find(|(_, v)| v.len() != labels.len()) {
+            return RenderSpec::error(format!(
+                "plot_{chart_type}: series '{name}' has {} values but there are {} labels",
+                values.len(),
+                labels.len(),
+            ));
+        }
+        let labels = shorten_iso_labels(labels);
+        if kwarg_bool(kwargs, "cumsum") {
+            for (_, values) in &mut series_map {
+                running_total(values.iter_mut());
+            }
+        }
 
+        let fill = chart_type == "line" && kwarg_bool(kwargs, "fill");
         let mut echarts_series = Vec::new();
         for (name, values) in &series_map {
-            echarts_series.push(serde_json::json!({
+            let mut series = serde_json::json!({
                 "name": name,
                 "type": chart_type,
                 "data": values,
                 "smooth": chart_type == "line",
-            }));
+            });
+            if fill {
+                series["areaStyle"] = serde_json::json!({ "opacity": 0.3 });
+            }
+            echarts_series.push(series);
         }
 
         let option = serde_json::json!({
@@ -1898,34 +3699,27 @@ impl ShellEngine {
     /// Build a pie chart from args:
     ///   plot_pie({"Living Room": 3, "Kitchen": 5, "Bedroom": 2}, title?)
     ///   plot_pie([("Living Room", 3), ("Kitchen", 5)], title?)
-    fn build_pie_chart(&self, args: &[MontyObject]) -> RenderSpec {
+    fn build_pie_chart(
+        &self,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> RenderSpec {
         let (data, title) = match self.parse_pie_args(args) {
             Ok(v) => v,
             Err(e) => return RenderSpec::error(e),
         };
-
-        let pie_data: Vec<serde_json::Value> = data
-            .iter()
-            .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
-            .collect();
-
-        let option = serde_json::json!({
-            "tooltip": { "trigger": "item", "formatter": "{b}: {c} ({d}%)" },
-            "legend": { "orient": "vertical", "left": "left" },
-            "series": [{
-                "type": "pie",
-                "radius": "60%",
-                "data": pie_data,
-                "emphasis": {
-                    "itemStyle": {
-                        "shadowBlur": 10,
-                        "shadowOffsetX": 0,
-                        "shadowColor": "rgba(0, 0, 0, 0.5)"
-                    }
+        let labels = match kwarg_str(kwargs, "labels") {
+            None => None,
+            Some(mode) => match pie_label_formatter(mode) {
+                Some(formatter) => Some(formatter),
+                None => {
+                    return RenderSpec::error(format!(
+                        "plot_pie: labels must be \"percent\", \"value\" or \"both\", not {mode:?}"
+                    ));
                 }
-            }],
-        });
-
+            },
+        };
+        let option = pie_chart_option(&data, kwarg_bool(kwargs, "donut"), labels);
         RenderSpec::echarts(option, title, None)
     }
 
@@ -1935,7 +3729,16 @@ impl ShellEngine {
     ///
     /// If x values look like epoch milliseconds (> 1_000_000_000_000), the x-axis
     /// is rendered as an ECharts `time` axis. Otherwise it's a `value` axis.
-    fn build_series_chart(&self, args: &[MontyObject]) -> RenderSpec {
+    ///
+    /// With `subplots=True`, each series gets its own stacked grid sharing
+    /// the x-axis instead of being overlaid on one set of axes.
+    ///
+    /// Dict series are charted in insertion order, as for line/bar charts.
+    fn build_series_chart(
+        &self,
+        args: &[MontyObject],
+        kwargs: &[(MontyObject, MontyObject)],
+    ) -> RenderSpec {
         if args.is_empty() {
             return RenderSpec::error(
                 "plot_series requires at least 1 argument: [(x,y),...] or {\"name\": [(x,y),...]}",
@@ -1945,7 +3748,7 @@ impl ShellEngine {
         let title = self.extract_title_from_args(args, 1);
 
         // Parse into named series of (x, y) pairs.
-        let named_series: Vec<(String, Vec<(f64, f64)>)> = match &args[0] {
+        let mut named_series: Vec<(String, Vec<(f64, f64)>)> = match &args[0] {
             // Dict form: {"name": [(x,y), ...], ...}
             MontyObject::Dict(pairs) => {
                 let mut series = Vec::new();
@@ -1982,13 +3785,23 @@ impl ShellEngine {
             return RenderSpec::error("plot_series: no data points provided");
         }
 
+        if kwarg_bool(kwargs, "cumsum") {
+            for (_, pts) in &mut named_series {
+                running_total(pts.iter_mut().map(|(_, y)| y));
+            }
+        }
+
         // Auto-detect time axis: if any x value > 1 trillion, treat as epoch ms.
         let is_time = named_series.iter().any(|(_, pts)| {
             pts.iter().any(|(x, _)| *x > 1_000_000_000_000.0)
         });
 
         let x_axis = if is_time {
-            serde_json::json!({ "type": "time" })
+            // Clock times within a day, dates across several.
+            let xs = named_series.iter().flat_map(|(_, pts)| pts.iter().map(|(x, _)| *x));
+            let (min, max) = xs.fold((f64::INFINITY, f64::NEG_INFINITY), |(lo, hi), x| (lo.min(x), hi.max(x)));
+            let formatter = if max - min < 86_400_000.0 { "{HH}:{mm}" } else { "{MM}-{dd}" };
+            serde_json::json!({ "type": "time", "axisLabel": { "formatter": formatter } })
         } else {
             serde_json::json!({ "type": "value" })
         };
@@ -2018,28 +3831,124 @@ impl ShellEngine {
             })
             .collect();
 
+        if kwarg_bool(kwargs, "subplots") && named_series.len() > 1 {
+            return self.build_subplot_chart(echarts_series, x_axis, title);
+        }
+
         let show_legend = named_series.len() > 1
             || (named_series.len() == 1 && named_series[0].0 != "value");
 
+        // `axes={"Humidity": "right"}` moves series onto a secondary y-axis
+        // so differently-scaled data isn't squashed flat.
+        let secondary = secondary_axis_series(kwargs);
+        let mut echarts_series = echarts_series;
+        let (y_axis, grid_right) = if named_series.iter().any(|(name, _)| secondary.contains(name)) {
+            let mut left = Vec::new();
+            let mut right = Vec::new();
+            for ((name, _), s) in named_series.iter().zip(echarts_series.iter_mut()) {
+                let index = if secondary.contains(name) {
+                    right.push(name.as_str());
+                    1
+                } else {
+                    left.push(name.as_str());
+                    0
+                };
+                s["yAxisIndex"] = serde_json::json!(index);
+            }
+            let y_axis = serde_json::json!([
+                { "type": "value", "name": left.join(", "), "scale": true },
+                { "type": "value", "name": right.join(", "), "scale": true, "position": "right" },
+            ]);
+            (y_axis, "12%")
+        } else {
+            (serde_json::json!({ "type": "value" }), "5%")
+        };
+
         let option = serde_json::json!({
             "tooltip": {
                 "trigger": "axis",
                 "axisPointer": { "type": "cross" },
             },
             "legend": { "show": show_legend },
-            "grid": { "left": "12%", "right": "5%", "bottom": "15%", "top": "12%" },
+            "grid": { "left": "12%", "right": grid_right, "bottom": "15%", "top": "12%" },
             "xAxis": x_axis,
-            "yAxis": { "type": "value" },
+            "yAxis": y_axis,
             "series": echarts_series,
         });
 
         RenderSpec::echarts(option, title, None)
     }
 
+    /// Lay out already-built series as vertically stacked subplots — one
+    /// grid (with its own x/y axis pair) per series, x-axes linked so the
+    /// crosshair moves together. Only the bottom grid shows x-axis labels.
+    fn build_subplot_chart(
+        &self,
+        echarts_series: Vec<serde_json::Value>,
+        x_axis: serde_json::Value,
+        title: Option<String>,
+    ) -> RenderSpec {
+        let count = echarts_series.len();
+        // Split the vertical space (8%..92%) evenly, leaving a small gap
+        // that shrinks with the slot so many subplots keep a positive height.
+        let slot = 84.0 / count as f64;
+        let gap = (slot * 0.3).min(6.0);
+
+        let mut grids = Vec::with_capacity(count);
+        let mut x_axes = Vec::with_capacity(count);
+        let mut y_axes = Vec::with_capacity(count);
+        let mut series = Vec::with_capacity(count);
+
+        for (i, mut s) in echarts_series.into_iter().enumerate() {
+            let name = s.get("name").cloned().unwrap_or(serde_json::Value::Null);
+            grids.push(serde_json::json!({
+                "left": "12%",
+                "right": "5%",
+                "top": format!("{:.1}%", 8.0 + slot * i as f64),
+                "height": format!("{:.1}%", slot - gap),
+            }));
+
+            let mut x = x_axis.clone();
+            x["gridIndex"] = serde_json::json!(i);
+            if i + 1 < count {
+                x["axisLabel"] = serde_json::json!({ "show": false });
+            }
+            x_axes.push(x);
+
+            y_axes.push(serde_json::json!({
+                "type": "value",
+                "gridIndex": i,
+                "name": name,
+                "scale": true,
+            }));
+
+            s["xAxisIndex"] = serde_json::json!(i);
+            s["yAxisIndex"] = serde_json::json!(i);
+            series.push(s);
+        }
+
+        let option = serde_json::json!({
+            "tooltip": {
+                "trigger": "axis",
+                "axisPointer": { "type": "cross" },
+            },
+            "axisPointer": { "link": [{ "xAxisIndex": "all" }] },
+            "legend": { "show": false },
+            "grid": grids,
+            "xAxis": x_axes,
+            "yAxis": y_axes,
+            "series": series,
+        });
+
+        // Give each subplot roughly 150px, never less than the default.
+        let height = (150 * count as u32).max(300);
+        RenderSpec::echarts(option, title, Some(height))
+    }
+
     /// Extract a list of (x, y) numeric pairs from a MontyObject.
-    /// Accepts List of Tuple([x, y]) or List([x, y]).
+    /// Accepts any sequence (list/tuple/set) of Tuple([x, y]) or List([x, y]).
     fn monty_to_xy_points(&self, obj: &MontyObject) -> Option<Vec<(f64, f64)>> {
-        if let MontyObject::List(items) = obj {
+        if let Some(items) = monty_sequence(obj) {
             let mut points = Vec::with_capacity(items.len());
             for item in items {
                 match item {
@@ -2154,7 +4063,10 @@ impl ShellEngine {
                 }
                 Ok((data, title))
             }
-            MontyObject::List(items) => {
+            other => {
+                let items = monty_sequence(other).ok_or_else(|| {
+                    "plot_pie requires a dict {name: value, ...} or list of (name, value) pairs".to_string()
+                })?;
                 let mut data = Vec::new();
                 for item in items {
                     match item {
@@ -2181,7 +4093,6 @@ impl ShellEngine {
                 }
                 Ok((data, title))
             }
-            _ => Err("plot_pie requires a dict {name: value, ...} or list of (name, value) pairs".into()),
         }
     }
 
@@ -2244,7 +4155,7 @@ impl ShellEngine {
     }
 
     fn monty_to_string_list(&self, obj: &MontyObject) -> Option<Vec<String>> {
-        if let MontyObject::List(items) = obj {
+        if let Some(items) = monty_sequence(obj) {
             items.iter().map(|item| match item {
                 MontyObject::String(s) => Some(s.clone()),
                 other => Some(format!("{other}")),
@@ -2255,7 +4166,7 @@ impl ShellEngine {
     }
 
     fn monty_to_number_list(&self, obj: &MontyObject) -> Option<Vec<f64>> {
-        if let MontyObject::List(items) = obj {
+        if let Some(items) = monty_sequence(obj) {
             items.iter().map(|item| self.monty_to_f64(item)).collect()
         } else {
             None
@@ -2272,6 +4183,70 @@ impl ShellEngine {
     }
 }
 
+/// `[{"name": ..., "value": ...}, ...]` as a table headed by the dict keys,
+/// or `None` unless every item is a dict with the first one's keys.
+fn dict_rows_table(items: &[MontyObject]) -> Option<RenderSpec> {
+    let dicts: Vec<&DictPairs> = items
+        .iter()
+        .map(|item| match item {
+            MontyObject::Dict(pairs) => Some(pairs),
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    let first = dicts.first()?;
+    let keys: Vec<&MontyObject> = first.into_iter().map(|(k, _)| k).collect();
+    if keys.is_empty() {
+        return None;
+    }
+    let cell = |v: &MontyObject| match v {
+        MontyObject::String(s) => s.clone(),
+        other => format!("{other}"),
+    };
+
+    let mut rows = Vec::with_capacity(dicts.len());
+    let mut numeric = vec![true; keys.len()];
+    for pairs in &dicts {
+        if pairs.into_iter().count() != keys.len() {
+            return None;
+        }
+        let mut row = Vec::with_capacity(keys.len());
+        for (i, key) in keys.iter().enumerate() {
+            let value = pairs.into_iter().find(|(k, _)| k == *key).map(|(_, v)| v)?;
+            numeric[i] &= matches!(value, MontyObject::Int(_) | MontyObject::Float(_));
+            row.push(cell(value));
+        }
+        rows.push(row);
+    }
+
+    let headers = keys.into_iter().map(cell).collect();
+    let column_types = numeric
+        .into_iter()
+        .map(|n| if n { ColumnType::Number } else { ColumnType::Text })
+        .collect();
+    Some(RenderSpec::table_with_types(headers, rows, column_types))
+}
+
+/// Drop repeated state objects for the same entity_id, keeping the last
+/// occurrence's data at the first occurrence's position.  Items without an
+/// entity_id are kept as-is.
+fn dedup_by_entity_id(arr: &[serde_json::Value]) -> Vec<&serde_json::Value> {
+    let mut out: Vec<&serde_json::Value> = Vec::with_capacity(arr.len());
+    let mut index: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
+    for item in arr {
+        match item.get("entity_id").and_then(|v| v.as_str()) {
+            Some(id) => match index.get(id) {
+                Some(&i) => out[i] = item,
+                None => {
+                    index.insert(id, out.len());
+                    out.push(item);
+                }
+            },
+            None => out.push(item),
+        }
+    }
+    out
+}
+
 /// Check whether a DictPairs has a key with the given name.
 fn dict_has_key(pairs: &DictPairs, key: &str) -> bool {
     for (k, _) in pairs {
@@ -2284,6 +4259,341 @@ fn dict_has_key(pairs: &DictPairs, key: &str) -> bool {
     false
 }
 
+/// The items of any iterable sequence — list, tuple, set or frozenset.
+/// Chart helpers accept all of them so `plot_bar(("a", "b"), (1, 2))` works.
+fn monty_sequence(obj: &MontyObject) -> Option<&[MontyObject]> {
+    match obj {
+        MontyObject::List(items)
+        | MontyObject::Tuple(items)
+        | MontyObject::Set(items)
+        | MontyObject::FrozenSet(items) => Some(items),
+        _ => None,
+    }
+}
+
+/// Replace each value with the running total up to and including it
+/// (`cumsum=True` on charts — e.g. per-hour energy into a daily total).
+fn running_total<'a>(values: impl Iterator<Item = &'a mut f64>) {
+    let mut total = 0.0;
+    for v in values {
+        total += *v;
+        *v = total;
+    }
+}
+
+/// The last [`SPARK_POINTS`] numeric readings per entity from a history
+/// response. With `minimal_response` only the first entry of each series
+/// carries the entity ID.
+fn spark_trends(value: &serde_json::Value) -> HashMap<String, Vec<f64>> {
+    let mut trends = HashMap::new();
+    for series in value.as_array().into_iter().flatten().filter_map(|s| s.as_array()) {
+        let Some(entity_id) = series.first().and_then(|e| e.get("entity_id")).and_then(|v| v.as_str()) else {
+            continue;
+        };
+        let values: Vec<f64> = series
+            .iter()
+            .filter_map(|e| e.get("state").and_then(|v| v.as_str())?.parse().ok())
+            .collect();
+        let skip = values.len().saturating_sub(SPARK_POINTS);
+        trends.insert(entity_id.to_string(), values[skip..].to_vec());
+    }
+    trends
+}
+
+/// Above this many entities, a bare `%ls` shows per-domain counts instead
+/// of the full table.
+const LS_OVERVIEW_MIN_ENTITIES: usize = 50;
+
+/// How far back `%ls --spark` fetches history, and how many of the most
+/// recent numeric readings each sparkline keeps.
+const SPARK_HOURS: u32 = 6;
+const SPARK_POINTS: usize = 24;
+
+/// How many of the most recent commands `%ask` sends as context.
+const ASK_CONTEXT_COMMANDS: usize = 10;
+
+/// Above this many rows, an EntityState list spanning several domains is
+/// split into one table per domain.
+const GROUP_BY_DOMAIN_MIN_ROWS: usize = 10;
+
+/// String results longer than this render as a copyable block instead of
+/// `→ '...'` text.
+const LONG_TEXT_THRESHOLD: usize = 500;
+
+/// Above this many attributes, `%attrs` groups them into sections.
+const ATTR_SECTION_THRESHOLD: usize = 8;
+
+/// Attributes describing what an entity supports or how it's configured.
+const CONFIG_ATTRIBUTES: &[&str] = &[
+    "supported_features", "supported_color_modes", "icon", "entity_picture",
+    "options", "step", "mode", "editable", "initial", "pattern",
+];
+
+/// Bookkeeping attributes that rarely matter day to day.
+const DIAGNOSTIC_ATTRIBUTES: &[&str] = &[
+    "attribution", "last_reset", "restored", "id", "user_id", "device_id",
+    "entity_id", "integration", "assumed_state", "last_triggered", "current",
+];
+
+/// Group attribute pairs into Primary / Config / Diagnostic sections,
+/// keeping each section's pairs in their original order. Known config and
+/// diagnostic keys (plus `*_modes`, `*_list`, `min_*`, `max_*` ranges) are
+/// split out; everything else is primary. Empty sections are omitted.
+fn group_attributes(pairs: Vec<(String, String)>) -> Vec<(String, Vec<(String, String)>)> {
+    let mut primary = Vec::new();
+    let mut config = Vec::new();
+    let mut diagnostic = Vec::new();
+    for (key, value) in pairs {
+        let k = key.as_str();
+        if DIAGNOSTIC_ATTRIBUTES.contains(&k) {
+            diagnostic.push((key, value));
+        } else if CONFIG_ATTRIBUTES.contains(&k)
+            || k.ends_with("_modes")
+            || k.ends_with("_list")
+            || k.starts_with("min_")
+            || k.starts_with("max_")
+        {
+            config.push((key, value));
+        } else {
+            primary.push((key, value));
+        }
+    }
+    [("Primary", primary), ("Config", config), ("Diagnostic", diagnostic)]
+        .into_iter()
+        .filter(|(_, pairs)| !pairs.is_empty())
+        .map(|(name, pairs)| (name.to_string(), pairs))
+        .collect()
+}
+
+/// An error, preceded by any print output the snippet produced before it
+/// failed so partial progress isn't lost.
+fn error_with_output(output: &str, message: String) -> RenderSpec {
+    if output.is_empty() {
+        RenderSpec::error(message)
+    } else {
+        RenderSpec::vstack(vec![RenderSpec::text(output.to_string()), RenderSpec::error(message)])
+    }
+}
+
+/// Comparison rows for one attribute, one value per entity. When every
+/// side is an object, each sub-key gets its own dotted row
+/// (`forecast.temperature`) so a change deep inside shows up on its own
+/// instead of as differing JSON blobs.
+fn diff_rows(key: &str, values: &[Option<&serde_json::Value>], rows: &mut Vec<Vec<String>>) {
+    let objects: Option<Vec<&serde_json::Map<String, serde_json::Value>>> =
+        values.iter().map(|v| v.and_then(|v| v.as_object())).collect();
+    if let Some(objects) = objects {
+        let mut sub_keys: Vec<&String> = objects.iter().flat_map(|o| o.keys()).collect();
+        sub_keys.sort();
+        sub_keys.dedup();
+        for sub in sub_keys {
+            let sub_values: Vec<Option<&serde_json::Value>> = objects.iter().map(|o| o.get(sub)).collect();
+            diff_rows(&format!("{key}.{sub}"), &sub_values, rows);
+        }
+        return;
+    }
+    let mut row = vec![key.to_string()];
+    row.extend(values.iter().map(|v| v.map_or_else(|| "—".to_string(), format_json_value)));
+    rows.push(row);
+}
+
+/// `12345678` → `12,345,678`. `None` unless `state` is a whole number
+/// long enough to need grouping.
+fn group_thousands(state: &str) -> Option<String> {
+    let (sign, digits) = match state.strip_prefix('-') {
+        Some(rest) => ("-", rest),
+        None => ("", state),
+    };
+    if digits.len() <= 3 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let mut grouped = String::from(sign);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            grouped.push(',');
+        }
+        grouped.push(c);
+    }
+    Some(grouped)
+}
+
+/// Trend of a numeric state against its previous value, as shown by the
+/// entity card arrow.
+fn trend_direction(previous: f64, current: f64) -> &'static str {
+    if current > previous {
+        "up"
+    } else if current < previous {
+        "down"
+    } else {
+        "flat"
+    }
+}
+
+/// Question keywords → the Signal Deck command that answers them, for
+/// `%ask` without a conversation agent. `{id}` is the entity the question
+/// mentions.
+const ASK_SUGGESTIONS: &[(&[&str], &str)] = &[
+    (&["history", "over time", "graph", "trend"], "%hist {id}"),
+    (&["attribute"], "%attrs {id}"),
+    (&["compare", "differ", "diff"], "%diff {id} <other_id>"),
+    (&["state", "status", "current", "value"], "%get {id}"),
+    (&["find", "search", "which", "list"], "%find <pattern>"),
+    (&["service", "action", "turn on", "turn off"], "%services"),
+    (&["everything", "inspect", "details"], "%inspect {id}"),
+];
+
+/// Local fallback for `%ask` when the host has no conversation agent:
+/// suggest commands matching keywords in the question.
+fn suggest_commands(question: &str) -> RenderSpec {
+    let lower = question.to_lowercase();
+    let id = question
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c: char| !c.is_alphanumeric() && c != '_' && c != '.'))
+        .map(|w| w.trim_end_matches('.'))
+        .find(|w| looks_like_entity_id(w))
+        .unwrap_or("<entity_id>");
+    let mut suggestions: Vec<String> = ASK_SUGGESTIONS
+        .iter()
+        .filter(|(keywords, _)| keywords.iter().any(|k| lower.contains(k)))
+        .map(|(_, command)| command.replace("{id}", id))
+        .collect();
+    if suggestions.is_empty() {
+        suggestions.push(":help".into());
+    }
+    RenderSpec::vstack(vec![
+        RenderSpec::summary("No conversation agent is configured — these commands may help:"),
+        RenderSpec::text(suggestions.join("\n")),
+    ])
+}
+
+/// ECharts formatter for `plot_pie(..., labels=...)`.
+fn pie_label_formatter(mode: &str) -> Option<&'static str> {
+    match mode {
+        "percent" => Some("{b}: {d}%"),
+        "value" => Some("{b}: {c}"),
+        "both" => Some(PIE_FORMATTER),
+        _ => None,
+    }
+}
+
+/// Default pie tooltip: name, value and percentage.
+const PIE_FORMATTER: &str = "{b}: {c} ({d}%)";
+
+/// ECharts option for a pie (or donut) chart of `(name, value)` slices.
+/// An explicit `labels` formatter applies to the slice labels as well as
+/// the tooltip; otherwise slices are labelled by name only.
+fn pie_chart_option(data: &[(String, f64)], donut: bool, labels: Option<&str>) -> serde_json::Value {
+    let radius = if donut {
+        serde_json::json!(["40%", "70%"])
+    } else {
+        serde_json::json!("60%")
+    };
+
+    let pie_data: Vec<serde_json::Value> = data
+        .iter()
+        .map(|(name, value)| serde_json::json!({ "name": name, "value": value }))
+        .collect();
+
+    let mut option = serde_json::json!({
+        "tooltip": { "trigger": "item", "formatter": labels.unwrap_or(PIE_FORMATTER) },
+        "legend": { "orient": "vertical", "left": "left" },
+        "series": [{
+            "type": "pie",
+            "radius": radius,
+            "data": pie_data,
+            "emphasis": {
+                "itemStyle": {
+                    "shadowBlur": 10,
+                    "shadowOffsetX": 0,
+                    "shadowColor": "rgba(0, 0, 0, 0.5)"
+                }
+            }
+        }],
+    });
+    if let Some(formatter) = labels {
+        option["series"][0]["label"] = serde_json::json!({ "formatter": formatter });
+    }
+    option
+}
+
+/// Count entities per domain (the part of the entity_id before the dot).
+fn count_by_domain(arr: &[&serde_json::Value]) -> std::collections::BTreeMap<String, usize> {
+    let mut domain_counts = std::collections::BTreeMap::new();
+    for item in arr {
+        if let Some(eid) = item.get("entity_id").and_then(|v| v.as_str()) {
+            let domain = eid.split('.').next().unwrap_or("?");
+            *domain_counts.entry(domain.to_string()).or_insert(0) += 1;
+        }
+    }
+    domain_counts
+}
+
+/// Series names the `axes` keyword puts on the secondary (right) y-axis —
+/// `axes={"Humidity": "right"}` or `axes={"Humidity": 1}`.
+fn secondary_axis_series(kwargs: &[(MontyObject, MontyObject)]) -> Vec<String> {
+    let Some((_, MontyObject::Dict(pairs))) = kwargs
+        .iter()
+        .find(|(k, _)| matches!(k, MontyObject::String(s) if s == "axes"))
+    else {
+        return Vec::new();
+    };
+    pairs
+        .into_iter()
+        .filter_map(|(k, v)| {
+            let on_right = match v {
+                MontyObject::Int(n) => *n == 1,
+                MontyObject::String(s) => s == "right",
+                _ => false,
+            };
+            match k {
+                MontyObject::String(name) if on_right => Some(name.clone()),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// Check whether a keyword argument is present and truthy (e.g. `subplots=True`).
+fn kwarg_bool(kwargs: &[(MontyObject, MontyObject)], name: &str) -> bool {
+    kwargs.iter().any(|(k, v)| {
+        matches!(k, MontyObject::String(s) if s == name)
+            && match v {
+                MontyObject::Bool(b) => *b,
+                MontyObject::Int(n) => *n != 0,
+                _ => false,
+            }
+    })
+}
+
+/// Carry a `state="error"` keyword through to trace calls so the host can
+/// narrow the list; [`ShellEngine::format_traces_response`] filters again
+/// in case it doesn't.
+fn with_trace_state_filter(
+    method: &'static str,
+    mut params: serde_json::Value,
+    kwargs: &[(MontyObject, MontyObject)],
+) -> (&'static str, serde_json::Value) {
+    if matches!(method, "get_trace" | "list_traces")
+        && let Some(state) = kwarg_str(kwargs, "state")
+    {
+        params["state"] = serde_json::json!(state);
+    }
+    (method, params)
+}
+
+/// A state object's `current_position` attribute (covers), for the icon.
+fn cover_position(state: &serde_json::Value) -> Option<f64> {
+    state.get("attributes")?.get("current_position")?.as_f64()
+}
+
+/// A string keyword argument (e.g. `labels="percent"`), if given.
+fn kwarg_str<'a>(kwargs: &'a [(MontyObject, MontyObject)], name: &str) -> Option<&'a str> {
+    kwargs.iter().find_map(|(k, v)| match (k, v) {
+        (MontyObject::String(k), MontyObject::String(v)) if k == name => Some(v.as_str()),
+        _ => None,
+    })
+}
+
 /// Format a serde_json::Value to a compact display string.
 fn format_json_value(v: &serde_json::Value) -> String {
     match v {
@@ -2295,6 +4605,66 @@ fn format_json_value(v: &serde_json::Value) -> String {
     }
 }
 
+/// Relevance of a `%find` match, lowest first: the exact entity ID, then
+/// an object ID or friendly name starting with the query, then one
+/// containing it, then anything else the glob matched. The query is the
+/// pattern less its leading/trailing wildcards.
+fn find_rank(item: &serde_json::Value, pattern: &str) -> u8 {
+    let query = pattern.trim_matches(['*', '?']).to_lowercase();
+    let id = item
+        .get("entity_id")
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_lowercase();
+    let object_id = id.split_once('.').map_or(id.as_str(), |(_, o)| o);
+    let name = item
+        .get("attributes")
+        .and_then(|a| a.get("friendly_name"))
+        .and_then(|v| v.as_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    if query.is_empty() {
+        3
+    } else if id == query {
+        0
+    } else if object_id.starts_with(&query) || name.starts_with(&query) {
+        1
+    } else if id.contains(&query) || name.contains(&query) {
+        2
+    } else {
+        3
+    }
+}
+
+/// Case-insensitive glob match supporting `*` (any run) and `?` (any one
+/// character) — the same syntax the host uses for `find_entities`.
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.to_lowercase().chars().collect();
+    let t: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    // Position of the last `*` and the text index it was tried at.
+    let mut star: Option<(usize, usize)> = None;
+
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi, ti));
+            pi += 1;
+        } else if let Some((sp, st)) = star {
+            // Backtrack: let the last `*` swallow one more character.
+            pi = sp + 1;
+            ti = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
 /// Format an ISO timestamp to a shorter display string.
 /// If it's today, show just the time. Otherwise show date + time.
 fn format_timestamp(ts: &str) -> String {
@@ -2308,6 +4678,60 @@ fn format_timestamp(ts: &str) -> String {
     ts.to_string()
 }
 
+/// Shorten category labels that are all ISO timestamps — just the time
+/// when they fall on one day, else `MM-DD HH:MM`. Other labels are kept.
+fn shorten_iso_labels(labels: Vec<String>) -> Vec<String> {
+    let all_iso = !labels.is_empty()
+        && labels.iter().all(|l| l.contains('T') && parse_iso_to_ms(l).is_some());
+    if !all_iso {
+        return labels;
+    }
+    let one_day = labels.iter().all(|l| l.get(..10) == labels[0].get(..10));
+    labels
+        .iter()
+        .map(|l| {
+            if one_day {
+                format_timestamp(l)
+            } else {
+                let time = format_timestamp(l);
+                format!("{} {}", l.get(5..10).unwrap_or(""), time.get(..5).unwrap_or(&time))
+            }
+        })
+        .collect()
+}
+
+/// Abort a snippet that has chained too many host calls.
+///
+/// The paused snapshot is dropped along with the REPL it owns, so the next
+/// snippet starts from a fresh REPL.
+fn host_call_limit_error(output: &str) -> RenderSpec {
+    let error = RenderSpec::error(format!(
+        "Too many host calls in one expression (limit {MAX_HOST_CALLS_PER_SNIPPET}) — \
+         execution stopped and the REPL was reset. Fetch in bulk with states() instead."
+    ));
+    if output.is_empty() {
+        error
+    } else {
+        RenderSpec::vstack(vec![RenderSpec::text(output.to_string()), error])
+    }
+}
+
+/// Abort a snippet whose host call has unusable arguments, before the
+/// host sees them. As with [`host_call_limit_error`], the REPL is reset.
+fn invalid_call_error(output: &str, message: String) -> RenderSpec {
+    let error = RenderSpec::error(format!("{message} — execution stopped and the REPL was reset."));
+    if output.is_empty() {
+        error
+    } else {
+        RenderSpec::vstack(vec![RenderSpec::text(output.to_string()), error])
+    }
+}
+
+/// The error for a host method `%caps` reported as unsupported.
+fn unsupported_method_message(method: &str) -> String {
+    format!("Your Home Assistant setup doesn't support {method} (see %caps)")
+}
+
 /// Combine prefix output with new output, avoiding empty concatenation.
 fn combine_output(prefix: &str, new: &str) -> String {
     if prefix.is_empty() {
@@ -2323,8 +4747,11 @@ fn combine_output(prefix: &str, new: &str) -> String {
 /// representing the number of hours (for use with history/statistics).
 ///
 /// Supported suffixes: m (minutes), h (hours), d (days), w (weeks).
+/// A clock time like "08:00" means "since then" — the most recent 08:00 —
+/// measured from `now` (the cached `get_datetime` response); it needs an
+/// earlier `now()` call in the snippet.
 /// Returns the value in hours (rounded). Falls back to 6 for unparseable input.
-fn parse_ago_to_monty(args: &[monty::MontyObject]) -> monty::MontyObject {
+fn parse_ago_to_monty(args: &[monty::MontyObject], now: Option<&serde_json::Value>) -> monty::MontyObject {
     let input = match args.first() {
         Some(monty::MontyObject::String(s)) => s.clone(),
         Some(monty::MontyObject::Int(n)) => return monty::MontyObject::Int(*n),
@@ -2337,6 +4764,21 @@ fn parse_ago_to_monty(args: &[monty::MontyObject]) -> monty::MontyObject {
         return monty::MontyObject::Int(6);
     }
 
+    if let Some(since) = parse_clock_minutes(&trimmed) {
+        let now_minutes = now
+            .and_then(|n| n.get("time"))
+            .and_then(|t| t.as_str())
+            .and_then(parse_clock_minutes);
+        return match now_minutes {
+            Some(current) => {
+                // A time later than now means that time yesterday.
+                let elapsed = (current - since).rem_euclid(24 * 60);
+                monty::MontyObject::Int(((elapsed as f64 / 60.0).round() as i64).max(1))
+            }
+            None => monty::MontyObject::Int(6),
+        };
+    }
+
     // Try to parse as number + suffix.
     let (num_str, suffix) = if trimmed.chars().last().map(|c| c.is_alphabetic()).unwrap_or(false) {
         let split = trimmed.len() - 1;
@@ -2361,13 +4803,29 @@ fn parse_ago_to_monty(args: &[monty::MontyObject]) -> monty::MontyObject {
     monty::MontyObject::Int(hours.round() as i64)
 }
 
+/// Parse a clock time "HH:MM" (or "HH:MM:SS") into minutes since midnight.
+fn parse_clock_minutes(s: &str) -> Option<i64> {
+    let mut parts = s.split(':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    if !(0..24).contains(&hours) || !(0..60).contains(&minutes) {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
 /// Map a state string to a timeline segment color.
-fn state_to_timeline_color(state: &str) -> String {
+fn state_to_timeline_color(state: &str, theme: Theme) -> String {
+    // The greys are the only colours that wash out against a light card.
+    let (off, unknown) = match theme {
+        Theme::Dark => ("#969696", "#606060"),
+        Theme::Light => ("#6e6e6e", "#424242"),
+    };
     match state {
         "on" | "home" | "open" | "playing" | "active" => "#44b556".to_string(),
-        "off" | "not_home" | "closed" | "idle" | "paused" | "standby" => "#969696".to_string(),
+        "off" | "not_home" | "closed" | "idle" | "paused" | "standby" => off.to_string(),
         "unavailable" => "#c74848".to_string(),
-        "unknown" => "#606060".to_string(),
+        "unknown" => unknown.to_string(),
         _ => "#2196f3".to_string(),
     }
 }
@@ -2427,6 +4885,59 @@ fn is_leap_year(year: i64) -> bool {
     (year % 4 == 0 && year % 100 != 0) || (year % 400 == 0)
 }
 
+/// Top-level names a snippet assigns — `x = ...`, `a, b = ...`,
+/// `def f(...)`, `class C`, `for x in ...`. Indented lines are skipped so
+/// function locals don't leak into autocomplete.
+fn defined_names(code: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    for line in code.lines() {
+        if line.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let targets = if let Some(rest) = line.strip_prefix("def ").or_else(|| line.strip_prefix("class ")) {
+            rest.split(['(', ':']).next().unwrap_or("")
+        } else if let Some(rest) = line.strip_prefix("for ") {
+            rest.split(" in ").next().unwrap_or("")
+        } else {
+            match assignment_target(line) {
+                Some(lhs) => lhs,
+                None => continue,
+            }
+        };
+        for target in targets.split(',') {
+            let name = target.trim().trim_matches(|c| c == '(' || c == ')');
+            if is_identifier(name) && !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// The left-hand side of a plain `=` assignment (annotation stripped), or
+/// `None` for comparisons, augmented assignments, subscripts and keyword
+/// arguments.
+fn assignment_target(line: &str) -> Option<&str> {
+    let bytes = line.as_bytes();
+    let pos = (0..bytes.len()).find(|&i| {
+        bytes[i] == b'='
+            && bytes.get(i + 1) != Some(&b'=')
+            && !(i > 0 && b"=!<>+-*/%&|^@".contains(&bytes[i - 1]))
+    })?;
+    let lhs = &line[..pos];
+    if lhs.contains(['(', '[', '.', '"', '\'']) {
+        return None;
+    }
+    lhs.split(':').next()
+}
+
+/// Check if `s` is a valid Python identifier.
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
 /// Known HA domains for auto-resolve.
 const HA_DOMAINS: &[&str] = &[
     "alarm_control_panel", "automation", "binary_sensor", "button", "calendar",
@@ -2487,6 +4998,40 @@ mod tests {
         assert!(json.contains("[clear]"));
     }
 
+    #[test]
+    fn test_clear_last_command() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval(":clear last")).unwrap();
+        assert_eq!(spec["content"], "\u{1b}[clear:last]");
+    }
+
+    #[test]
+    fn test_info_reports_session_state() {
+        let mut engine = ShellEngine::new();
+        engine.eval("40 + 2");
+        engine.eval("%get sensor.temp");
+        engine.eval("x = 1");
+        engine.eval("%fmt json");
+        let spec = serde_json::to_value(engine.eval(":info")).unwrap();
+        assert_eq!(spec["type"], "key_value", "Expected key_value: {spec}");
+        let pairs = spec["pairs"].as_array().unwrap();
+        let get = |key: &str| {
+            pairs
+                .iter()
+                .find(|p| p[0] == key)
+                .map(|p| p[1].as_str().unwrap().to_string())
+                .unwrap()
+        };
+        assert_eq!(get("history"), "5");
+        assert_eq!(get("context lines"), "5");
+        assert_eq!(get("host calls"), "1");
+        assert_eq!(get("output format"), "json");
+        assert_eq!(get("theme"), "dark");
+        assert_eq!(get("defined names"), "1");
+        assert_eq!(get("last result"), "42");
+        assert_eq!(get("last entity"), "sensor.temp");
+    }
+
     #[test]
     fn test_ls_produces_host_call() {
         let mut engine = ShellEngine::new();
@@ -2515,6 +5060,15 @@ mod tests {
         assert!(json.contains("attrs_only"));
     }
 
+    #[test]
+    fn test_get_attrs_flag_produces_attrs_host_call() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%get sensor.temp --attrs")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        assert_eq!(spec["params"]["entity_id"], "sensor.temp");
+        assert_eq!(spec["params"]["attrs_only"], true);
+    }
+
     #[test]
     fn test_diff_produces_host_call() {
         let mut engine = ShellEngine::new();
@@ -2525,6 +5079,78 @@ mod tests {
         assert!(json.contains("entity_b"));
     }
 
+    #[test]
+    fn test_diff_changed_only_keeps_differing_rows() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%diff light.a light.b --changed-only")).unwrap();
+        assert_eq!(spec["method"], "get_diff");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"__diff": true,
+            "entity_a": {"entity_id": "light.a", "state": "on", "attributes": {"brightness": 200, "color_mode": "hs"}},
+            "entity_b": {"entity_id": "light.b", "state": "on", "attributes": {"brightness": 120, "color_mode": "hs"}}}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let table = &json["children"][1];
+        assert_eq!(table["headers"], serde_json::json!(["attribute", "light.a", "light.b"]));
+        assert_eq!(table["rows"], serde_json::json!([["brightness", "200", "120"]]));
+    }
+
+    #[test]
+    fn test_diff_three_entities() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%diff light.a light.b light.c")).unwrap();
+        assert_eq!(spec["method"], "get_states_by_id");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        // The host may return the states in any order.
+        let data = r#"[
+            {"entity_id": "light.c", "state": "off", "attributes": {"brightness": 10}},
+            {"entity_id": "light.a", "state": "on", "attributes": {"brightness": 200}},
+            {"entity_id": "light.b", "state": "on", "attributes": {"brightness": 120}}
+        ]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let table = &json["children"][1];
+        assert_eq!(
+            table["headers"],
+            serde_json::json!(["attribute", "light.a", "light.b", "light.c"])
+        );
+        assert_eq!(
+            table["rows"],
+            serde_json::json!([["state", "on", "on", "off"], ["brightness", "200", "120", "10"]])
+        );
+    }
+
+    #[test]
+    fn test_diff_marks_changed_and_equal_rows() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%diff light.a light.b --attrs-only")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"__diff": true,
+            "entity_a": {"entity_id": "light.a", "state": "on", "attributes": {"brightness": 200, "color_mode": "hs"}},
+            "entity_b": {"entity_id": "light.b", "state": "on", "attributes": {"brightness": 120, "color_mode": "hs"}}}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let table = &json["children"][1];
+        assert_eq!(table["rows"][0][0], "brightness");
+        assert_eq!(table["cell_styles"][0], serde_json::json!(["warning", "warning", "warning"]));
+        assert_eq!(table["rows"][1][0], "color_mode");
+        assert_eq!(table["cell_styles"][1], serde_json::json!(["dim", "dim", "dim"]));
+    }
+
+    #[test]
+    fn test_diff_attrs_only_skips_state() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%diff light.a light.b --attrs-only")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"__diff": true,
+            "entity_a": {"entity_id": "light.a", "state": "on", "attributes": {"brightness": 200}},
+            "entity_b": {"entity_id": "light.b", "state": "off", "attributes": {"brightness": 120}}}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let rows = json["children"][1]["rows"].as_array().unwrap();
+        assert!(rows.iter().all(|r| r[0] != "state"), "State row should be skipped: {json}");
+        assert_eq!(rows.len(), 1);
+    }
+
     #[test]
     fn test_python_arithmetic() {
         let mut engine = ShellEngine::new();
@@ -2588,169 +5214,1727 @@ mod tests {
     }
 
     #[test]
-    fn test_python_syntax_error() {
+    fn test_plot_series_subplots() {
         let mut engine = ShellEngine::new();
-        let result = engine.eval("def f(:");
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"error""#), "Expected error in: {json}");
+        let result = engine.eval(
+            "plot_series({\"Temp\": [(1, 20), (2, 21)], \"Humidity\": [(1, 40), (2, 45)]}, \"Climate\", subplots=True)",
+        );
+        let json: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["type"], "echarts", "Expected echarts: {json}");
+        let option = &json["option"];
+        assert_eq!(option["grid"].as_array().map(|g| g.len()), Some(2), "Expected 2 grids: {option}");
+        assert_eq!(option["xAxis"][1]["gridIndex"], 1);
+        assert_eq!(option["yAxis"][1]["gridIndex"], 1);
+        assert_eq!(option["series"][0]["xAxisIndex"], 0);
+        assert_eq!(option["series"][1]["yAxisIndex"], 1);
     }
 
     #[test]
-    fn test_python_state_produces_host_call() {
-        let mut engine = ShellEngine::new();
-        let result = engine.eval("state('sensor.temp')");
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"host_call""#), "Expected host_call in: {json}");
-        assert!(json.contains(r#""method":"get_state""#), "Expected get_state method in: {json}");
-        assert!(json.contains("sensor.temp"), "Expected entity_id in: {json}");
+    fn test_plot_series_many_subplots_keep_positive_heights() {
+        let engine = ShellEngine::new();
+        let series = (0..20)
+            .map(|i| serde_json::json!({ "type": "line", "name": format!("s{i}"), "data": [[1, i]] }))
+            .collect();
+        let spec = engine.build_subplot_chart(series, serde_json::json!({ "type": "value" }), None);
+        let json = serde_json::to_value(&spec).unwrap();
+        let grids = json["option"]["grid"].as_array().unwrap();
+        assert_eq!(grids.len(), 20);
+        for grid in grids {
+            let height: f64 = grid["height"].as_str().unwrap().trim_end_matches('%').parse().unwrap();
+            assert!(height > 0.0, "{grid}");
+        }
     }
 
     #[test]
-    fn test_python_states_produces_host_call() {
+    fn test_plot_series_secondary_axis() {
         let mut engine = ShellEngine::new();
-        let result = engine.eval("states('light')");
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"host_call""#), "Expected host_call in: {json}");
-        assert!(json.contains(r#""method":"get_states""#), "Expected get_states method in: {json}");
+        let result = engine.eval(
+            "plot_series({\"Temp\": [(1, 20), (2, 21)], \"Humidity\": [(1, 40), (2, 45)]}, \"Climate\", axes={\"Humidity\": \"right\"})",
+        );
+        let json: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["type"], "echarts", "Expected echarts: {json}");
+        let option = &json["option"];
+        assert_eq!(option["yAxis"].as_array().map(|a| a.len()), Some(2), "Expected 2 y-axes: {option}");
+        assert_eq!(option["yAxis"][1]["name"], "Humidity");
+        assert_eq!(option["series"][0]["yAxisIndex"], 0);
+        assert_eq!(option["series"][1]["yAxisIndex"], 1);
     }
 
     #[test]
-    fn test_python_state_resume() {
-        let mut engine = ShellEngine::new();
-        // Start a Python snippet that calls state().
-        let result = engine.eval("state('sensor.temp')");
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"host_call""#));
-
-        // Extract the call_id.
-        let spec: serde_json::Value = serde_json::from_str(&json).unwrap();
-        let call_id = spec["call_id"].as_str().unwrap();
+    fn test_chart_series_keep_dict_order() {
+        let series_names = |json: &serde_json::Value| -> Vec<String> {
+            json["option"]["series"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|s| s["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+        for _ in 0..3 {
+            let mut engine = ShellEngine::new();
+            let result = engine.eval(
+                "plot_series({\"Zeta\": [(1, 1)], \"Alpha\": [(1, 2)], \"Mid\": [(1, 3)]})",
+            );
+            let json = serde_json::to_value(&result).unwrap();
+            assert_eq!(series_names(&json), vec!["Zeta", "Alpha", "Mid"], "{json}");
 
-        // Fulfill with state data — the Monty execution should resume and return the value.
-        let state_data = r#"{"entity_id": "sensor.temp", "state": "22.5"}"#;
-        let result = engine.fulfill_host_call(call_id, state_data);
-        let json = serde_json::to_string(&result).unwrap();
-        // Should contain the returned dict value.
-        assert!(!json.contains(r#""type":"error""#), "Unexpected error in: {json}");
+            let result = engine.eval(
+                "plot_line([\"a\", \"b\"], {\"Zeta\": [1, 2], \"Alpha\": [3, 4], \"Mid\": [5, 6]})",
+            );
+            let json = serde_json::to_value(&result).unwrap();
+            assert_eq!(series_names(&json), vec!["Zeta", "Alpha", "Mid"], "{json}");
+            assert_eq!(json["option"]["legend"]["data"], serde_json::json!(["Zeta", "Alpha", "Mid"]));
+        }
     }
 
     #[test]
-    fn test_auto_resolve_entity_id() {
+    fn test_plot_bar_iso_labels_shortened() {
         let mut engine = ShellEngine::new();
-        let result = engine.eval("sensor.temp");
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""method":"get_state""#));
-        assert!(json.contains("sensor.temp"));
+        let result = engine.eval(
+            "plot_bar([\"2026-02-15T08:00:00Z\", \"2026-02-15T09:00:00Z\"], [1, 2])",
+        );
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["option"]["xAxis"]["data"], serde_json::json!(["08:00:00", "09:00:00"]), "{json}");
     }
 
     #[test]
-    fn test_auto_resolve_domain() {
+    fn test_plot_series_overlay_by_default() {
         let mut engine = ShellEngine::new();
-        let result = engine.eval("light");
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""method":"get_states""#));
-        assert!(json.contains(r#""domain":"light""#));
+        let result = engine.eval("plot_series({\"A\": [(1, 10)], \"B\": [(1, 5)]})");
+        let json: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert!(json["option"]["grid"].is_object(), "Expected single grid: {json}");
     }
 
     #[test]
-    fn test_auto_resolve_not_random_word() {
+    fn test_plot_pie_donut() {
         let mut engine = ShellEngine::new();
-        let result = engine.eval("foobar");
-        let json = serde_json::to_string(&result).unwrap();
-        // Should be treated as Python, not auto-resolved.
-        // Monty will try to run it as Python (likely a NameError).
-        assert!(!json.contains(r#""method":"get_state""#), "Should not auto-resolve: {json}");
-        assert!(!json.contains(r#""method":"get_states""#), "Should not auto-resolve: {json}");
+        let result = engine.eval("plot_pie({\"on\": 3, \"off\": 5}, \"Lights\", donut=True)");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["type"], "echarts", "Expected echarts: {spec}");
+        assert_eq!(spec["option"]["series"][0]["radius"], serde_json::json!(["40%", "70%"]));
     }
 
     #[test]
-    fn test_history_recorded() {
+    fn test_plot_pie_default_radius() {
         let mut engine = ShellEngine::new();
-        engine.eval("%ls");
-        engine.eval("state('x')");
-        assert_eq!(engine.session.history().len(), 2);
+        let result = engine.eval("plot_pie({\"on\": 3, \"off\": 5})");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["series"][0]["radius"], "60%");
     }
 
     #[test]
-    fn test_prompt() {
+    fn test_plot_pie_labels() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_pie({\"on\": 3, \"off\": 5}, labels=\"percent\")");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["tooltip"]["formatter"], "{b}: {d}%");
+        assert_eq!(spec["option"]["series"][0]["label"]["formatter"], "{b}: {d}%");
+
+        // Default keeps the value and the percentage.
+        let result = engine.eval("plot_pie({\"on\": 3, \"off\": 5})");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["tooltip"]["formatter"], "{b}: {c} ({d}%)");
+
+        let result = engine.eval("plot_pie({\"on\": 3}, labels=\"pct\")");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["type"], "error", "{spec}");
+    }
+
+    #[test]
+    fn test_plot_pie_tuple_of_pairs() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_pie(((\"on\", 3), (\"off\", 5)), \"Lights\")");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"echarts""#), "Expected echarts: {json}");
+        assert!(json.contains(r#""name":"off""#), "Expected slice: {json}");
+    }
+
+    #[test]
+    fn test_plot_bar_tuple_of_numbers() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_bar((\"Mon\", \"Tue\"), (4, 7))");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"echarts""#), "Expected echarts: {json}");
+        assert!(json.contains("Tue"), "Expected label: {json}");
+    }
+
+    #[test]
+    fn test_copy_last_result_as_json() {
+        let mut engine = ShellEngine::new();
+        engine.eval("{\"room\": \"kitchen\", \"lights\": 3}");
+        let result = engine.eval("%copy");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["type"], "copyable", "Expected copyable: {spec}");
+        let content: serde_json::Value =
+            serde_json::from_str(spec["content"].as_str().unwrap()).unwrap();
+        assert_eq!(content, serde_json::json!({ "room": "kitchen", "lights": 3 }));
+    }
+
+    #[test]
+    fn test_copy_without_result() {
+        let mut engine = ShellEngine::new();
+        let json = serde_json::to_string(&engine.eval("%copy")).unwrap();
+        assert!(json.contains(r#""type":"error""#), "Expected error: {json}");
+    }
+
+    #[test]
+    fn test_plot_last_numbers_line_chart() {
+        let mut engine = ShellEngine::new();
+        engine.eval("[3, 1, 4, 1, 5]");
+        let spec = serde_json::to_value(engine.eval("%plot")).unwrap();
+        assert_eq!(spec["type"], "echarts", "Expected echarts: {spec}");
+        assert_eq!(spec["option"]["series"][0]["type"], "line");
+    }
+
+    #[test]
+    fn test_plot_last_dict_bar_chart() {
+        let mut engine = ShellEngine::new();
+        engine.eval("{\"kitchen\": 3, \"hall\": 1}");
+        let spec = serde_json::to_value(engine.eval("%plot")).unwrap();
+        assert_eq!(spec["type"], "echarts", "Expected echarts: {spec}");
+        assert_eq!(spec["option"]["series"][0]["type"], "bar");
+        assert!(spec.to_string().contains("kitchen"));
+    }
+
+    #[test]
+    fn test_plot_last_unplottable() {
+        let mut engine = ShellEngine::new();
+        engine.eval("\"hello\"");
+        let json = serde_json::to_string(&engine.eval("%plot")).unwrap();
+        assert!(json.contains(r#""type":"error""#), "Expected error: {json}");
+    }
+
+    #[test]
+    fn test_plot_line_cumsum() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_line([\"a\", \"b\", \"c\"], [1, 2, 3], cumsum=True)");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["series"][0]["data"], serde_json::json!([1.0, 3.0, 6.0]));
+    }
+
+    #[test]
+    fn test_plot_line_fill() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_line([\"a\", \"b\"], [1, 2], fill=True)");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert!(spec["option"]["series"][0]["areaStyle"].is_object(), "{spec}");
+
+        let result = engine.eval("plot_line([\"a\", \"b\"], [1, 2])");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert!(spec["option"]["series"][0].get("areaStyle").is_none());
+    }
+
+    #[test]
+    fn test_plot_line_length_mismatch() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_line([\"a\", \"b\", \"c\"], {\"temp\": [1, 2, 3], \"hum\": [4, 5]})");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["type"], "error");
+        assert_eq!(spec["message"], "plot_line: series 'hum' has 2 values but there are 3 labels");
+    }
+
+    #[test]
+    fn test_plot_line_unit_tooltip() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_line([\"a\", \"b\"], [20, 21], unit=\"°C\")");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["tooltip"]["valueFormatter"], "{value} °C");
+        assert_eq!(spec["option"]["tooltip"]["trigger"], "axis");
+    }
+
+    #[test]
+    fn test_plot_series_time_axis_label_format() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_series([(1771149600000, 1), (1771160400000, 2)])");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["xAxis"]["type"], "time");
+        assert_eq!(spec["option"]["xAxis"]["axisLabel"]["formatter"], "{HH}:{mm}");
+
+        let result = engine.eval("plot_series([(1771149600000, 1), (1771408800000, 2)])");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["option"]["xAxis"]["axisLabel"]["formatter"], "{MM}-{dd}");
+    }
+
+    #[test]
+    fn test_plot_series_cumsum() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("plot_series([(1, 2), (2, 2), (3, 5)], cumsum=True)");
+        let spec = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            spec["option"]["series"][0]["data"],
+            serde_json::json!([[1.0, 2.0], [2.0, 4.0], [3.0, 9.0]])
+        );
+    }
+
+    #[test]
+    fn test_python_error_keeps_prior_output() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("print('before')\nundefined_name");
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["type"], "vstack", "Expected output + error: {json}");
+        assert_eq!(json["children"][0]["type"], "text");
+        assert!(json["children"][0]["content"].as_str().unwrap().contains("before"), "{json}");
+        assert_eq!(json["children"][1]["type"], "error");
+    }
+
+    #[test]
+    fn test_yaml_exports_last_service_call() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval(
+            "call_service(\"light\", \"turn_on\", {\"entity_id\": \"light.kitchen\", \"brightness\": 200})",
+        ))
+        .unwrap();
+        assert_eq!(spec["method"], "call_service");
+        let call_id = spec["call_id"].as_str().unwrap();
+        engine.fulfill_host_call(call_id, r#"{"success": true}"#);
+
+        let json = serde_json::to_value(engine.eval("%yaml service")).unwrap();
+        assert_eq!(json["type"], "copyable", "{json}");
+        assert_eq!(
+            json["content"],
+            "action: light.turn_on\ndata:\n  brightness: 200\n  entity_id: light.kitchen\n"
+        );
+    }
+
+    #[test]
+    fn test_call_service_result_rendering() {
+        let mut engine = ShellEngine::new();
+        let snippet = "call_service(\"light\", \"turn_on\", {\"entity_id\": \"light.kitchen\"})";
+        let spec = serde_json::to_value(engine.eval(snippet)).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, r#"{"success": true}"#)).unwrap();
+        assert_eq!(json["type"], "hstack", "{json}");
+        assert_eq!(
+            json["children"][0],
+            serde_json::json!({"type": "badge", "label": "✓ light.turn_on", "color": "success"})
+        );
+        assert_eq!(json["children"][1]["content"], "→ light.kitchen");
+
+        let spec = serde_json::to_value(engine.eval(snippet)).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"success": true, "states": [
+            {"entity_id": "light.kitchen", "state": "on", "last_changed": "2026-02-15T10:00:00Z", "attributes": {}}
+        ]}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["type"], "vstack", "{json}");
+        assert_eq!(json["children"][0]["type"], "hstack");
+        let table = &json["children"][1]["children"][1];
+        assert_eq!(table["type"], "table");
+        assert_eq!(table["rows"][0][1], "light.kitchen");
+        assert_eq!(table["rows"][0][2], "on");
+
+        let spec = serde_json::to_value(engine.eval(snippet)).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"error": "Service call failed: boom", "domain": "light", "service": "turn_on"}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["type"], "error", "{json}");
+        assert!(json["message"].as_str().unwrap().contains("boom"), "{json}");
+    }
+
+    #[test]
+    fn test_yaml_without_result_errors() {
+        let mut engine = ShellEngine::new();
+        let json = serde_json::to_value(engine.eval("%yaml")).unwrap();
+        assert_eq!(json["type"], "error");
+        let json = serde_json::to_value(engine.eval("%yaml service")).unwrap();
+        assert_eq!(json["type"], "error");
+    }
+
+    #[test]
+    fn test_python_invalid_host_data_aborts() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("print('before')\nx = state('sensor.temp')")).unwrap();
+        assert_eq!(spec["type"], "host_call", "{spec}");
+        let call_id = spec["call_id"].as_str().unwrap().to_string();
+
+        let json = serde_json::to_value(engine.fulfill_host_call(&call_id, "not json {")).unwrap();
+        let text = json.to_string();
+        assert!(text.contains("invalid data for get_state"), "{text}");
+        assert!(text.contains("Execution aborted"), "{text}");
+        assert!(text.contains("before"), "Output before the pause is kept: {text}");
+        assert!(!engine.session.has_pending_monty(&call_id));
+
+        // The REPL comes back for the next snippet.
+        let json = serde_json::to_value(engine.eval("1 + 1")).unwrap();
+        assert_ne!(json["type"], "error", "{json}");
+    }
+
+    #[test]
+    fn test_python_show_with_label() {
+        let mut engine = ShellEngine::new();
+        let json = serde_json::to_value(engine.eval("show(['kitchen', 'hall'], 'Lights')")).unwrap();
+        assert_eq!(json["type"], "vstack", "{json}");
+        assert_eq!(json["children"][0], serde_json::json!({"type": "summary", "content": "Lights"}));
+        assert!(json["children"][1].to_string().contains("kitchen"), "{json}");
+
+        // Without a label, just the value.
+        let json = serde_json::to_value(engine.eval("show(['kitchen', 'hall'])")).unwrap();
+        assert_eq!(json["type"], "text", "{json}");
+    }
+
+    #[test]
+    fn test_python_bool_result_renders_badge() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("2 > 1")).unwrap();
+        assert_eq!(spec["type"], "badge");
+        assert_eq!(spec["label"], "True");
+        assert_eq!(spec["color"], "success");
+
+        let spec = serde_json::to_value(engine.eval("1 > 2")).unwrap();
+        assert_eq!(spec["label"], "False");
+        assert_eq!(spec["color"], "dim");
+    }
+
+    #[test]
+    fn test_python_refuses_to_shadow_external_function() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("state = 5")).unwrap();
+        assert_eq!(spec["type"], "error");
+        assert!(spec["message"].as_str().unwrap().contains("`state` is a built-in function"));
+
+        let spec = serde_json::to_value(engine.eval("for show in [1]: pass")).unwrap();
+        assert_eq!(spec["type"], "error");
+
+        // state() still reaches the host afterwards.
+        let spec = serde_json::to_value(engine.eval("state('sensor.x')")).unwrap();
+        assert_eq!(spec["type"], "host_call");
+        assert_eq!(spec["method"], "get_state");
+    }
+
+    #[test]
+    fn test_python_show_list_of_dicts_as_table() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("show([{\"a\": 1, \"b\": 2}, {\"a\": 3, \"b\": 4}])");
+        let json = serde_json::to_value(&result).unwrap();
+        let table = if json["type"] == "vstack" { &json["children"][0] } else { &json };
+        assert_eq!(table["type"], "table", "{json}");
+        assert_eq!(table["headers"], serde_json::json!(["a", "b"]));
+        assert_eq!(table["rows"], serde_json::json!([["1", "2"], ["3", "4"]]));
+        assert_eq!(table["column_types"], serde_json::json!(["number", "number"]));
+    }
+
+    #[test]
+    fn test_python_get_areas_renders_table() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("get_areas()")).unwrap();
+        assert_eq!(spec["method"], "get_areas");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"area_id": "kitchen", "name": "Kitchen", "floor_id": null, "entity_count": 12},
+            {"area_id": "office", "name": "Office", "floor_id": null, "entity_count": 4}
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(spec["children"][0]["content"], "2 areas");
+        let table = &spec["children"][1];
+        assert_eq!(table["headers"], serde_json::json!(["area", "area_id", "entities"]));
+        assert_eq!(
+            table["rows"],
+            serde_json::json!([["Kitchen", "kitchen", "12"], ["Office", "office", "4"]])
+        );
+    }
+
+    #[test]
+    fn test_python_long_string_renders_copyable() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("'ab' * 300")).unwrap();
+        assert_eq!(spec["type"], "copyable", "{spec}");
+        assert_eq!(spec["content"].as_str().unwrap().len(), 600);
+
+        let spec = serde_json::to_value(engine.eval("'ab' * 3")).unwrap();
+        assert_eq!(spec["type"], "text");
+    }
+
+    #[test]
+    fn test_python_history_multiple_entities_overlays() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("history(['sensor.a', 'sensor.b'], 2)")).unwrap();
+        assert_eq!(spec["method"], "get_history");
+        assert_eq!(spec["params"]["entity_ids"], serde_json::json!(["sensor.a", "sensor.b"]));
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            [{"entity_id": "sensor.a", "state": "20.0", "last_changed": "2026-02-15T10:00:00Z",
+              "attributes": {"friendly_name": "Lounge", "unit_of_measurement": "°C"}},
+             {"entity_id": "sensor.a", "state": "unavailable", "last_changed": "2026-02-15T11:00:00Z"}],
+            [{"entity_id": "sensor.b", "state": "18.5", "last_changed": "2026-02-15T10:30:00Z",
+              "attributes": {"unit_of_measurement": "°C"}}]
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(spec["type"], "echarts");
+        let series = &spec["option"]["series"];
+        assert_eq!(series[0]["name"], "Lounge");
+        assert_eq!(series[1]["name"], "sensor.b");
+        assert_eq!(series[0]["data"][1][1], serde_json::Value::Null);
+        assert_eq!(spec["option"]["yAxis"]["name"], "°C");
+    }
+
+    #[test]
+    fn test_python_show_returns_its_value() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("y = show(5)")).unwrap();
+        assert_eq!(spec["type"], "text");
+        assert_eq!(monty_runtime::monty_obj_to_json(engine.session.last_result().unwrap()), 5);
+        engine.eval("y + 1");
+        assert_eq!(monty_runtime::monty_obj_to_json(engine.session.last_result().unwrap()), 6);
+
+        // After a host call, show() is handled on resume and still sets `_`.
+        let spec = serde_json::to_value(engine.eval("s = state('sensor.a')\nshow(7)")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        engine.fulfill_host_call(call_id, r#"{"entity_id": "sensor.a", "state": "1"}"#);
+        assert_eq!(monty_runtime::monty_obj_to_json(engine.session.last_result().unwrap()), 7);
+    }
+
+    #[test]
+    fn test_py_magic_skips_auto_resolve() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("light")).unwrap();
+        assert_eq!(spec["method"], "get_states");
+
+        let spec = serde_json::to_value(engine.eval("%py light")).unwrap();
+        assert_eq!(spec["type"], "error", "{spec}");
+        assert!(spec["message"].as_str().unwrap().contains("light"), "{spec}");
+
+        engine.eval("%py light = 3");
+        engine.eval("%py light + 1");
+        assert_eq!(monty_runtime::monty_obj_to_json(engine.session.last_result().unwrap()), 4);
+    }
+
+    #[test]
+    fn test_python_state_rejects_malformed_entity_id() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("state('foo bar')")).unwrap();
+        assert_eq!(spec["type"], "error", "{spec}");
+        assert!(spec["message"].as_str().unwrap().contains("\"foo bar\" is not an entity ID"), "{spec}");
+        assert!(!engine.session.has_pending_monty("call_1"));
+    }
+
+    #[test]
+    fn test_python_statistics_accepts_external_statistic_id() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("statistics('opower:elec_cost')")).unwrap();
+        assert_eq!(spec["type"], "host_call", "{spec}");
+        assert_eq!(spec["method"], "get_statistics");
+        assert_eq!(spec["params"]["entity_id"], "opower:elec_cost");
+    }
+
+    #[test]
+    fn test_traces_state_filter() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("traces(state=\"error\")")).unwrap();
+        assert_eq!(spec["method"], "list_traces", "{spec}");
+        assert_eq!(spec["params"]["state"], "error");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let traces = r#"[
+            {"run_id": "1", "automation": "automation.a", "state": "stopped", "start": "2026-01-01T08:00:00Z"},
+            {"run_id": "2", "automation": "automation.b", "state": "error", "start": "2026-01-01T09:00:00Z", "error": "boom"},
+            {"run_id": "3", "automation": "automation.a", "state": "error", "start": "2026-01-01T10:00:00Z"}
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, traces)).unwrap();
+        assert_eq!(spec["children"][0]["content"], "2 recent automation error traces");
+        let run_ids: Vec<&str> = spec["children"][1]["entries"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["run_id"].as_str().unwrap())
+            .collect();
+        assert_eq!(run_ids, ["2", "3"]);
+    }
+
+    #[test]
+    fn test_python_syntax_error() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("def f(:");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"error""#), "Expected error in: {json}");
+    }
+
+    #[test]
+    fn test_python_state_produces_host_call() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("state('sensor.temp')");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"host_call""#), "Expected host_call in: {json}");
+        assert!(json.contains(r#""method":"get_state""#), "Expected get_state method in: {json}");
+        assert!(json.contains("sensor.temp"), "Expected entity_id in: {json}");
+    }
+
+    #[test]
+    fn test_python_states_produces_host_call() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("states('light')");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"host_call""#), "Expected host_call in: {json}");
+        assert!(json.contains(r#""method":"get_states""#), "Expected get_states method in: {json}");
+    }
+
+    #[test]
+    fn test_python_state_resume() {
+        let mut engine = ShellEngine::new();
+        // Start a Python snippet that calls state().
+        let result = engine.eval("state('sensor.temp')");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"host_call""#));
+
+        // Extract the call_id.
+        let spec: serde_json::Value = serde_json::from_str(&json).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        // Fulfill with state data — the Monty execution should resume and return the value.
+        let state_data = r#"{"entity_id": "sensor.temp", "state": "22.5"}"#;
+        let result = engine.fulfill_host_call(call_id, state_data);
+        let json = serde_json::to_string(&result).unwrap();
+        // Should contain the returned dict value.
+        assert!(!json.contains(r#""type":"error""#), "Unexpected error in: {json}");
+    }
+
+    #[test]
+    fn test_auto_resolve_entity_id() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("sensor.temp");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""method":"get_state""#));
+        assert!(json.contains("sensor.temp"));
+    }
+
+    #[test]
+    fn test_auto_resolve_domain() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("light");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""method":"get_states""#));
+        assert!(json.contains(r#""domain":"light""#));
+    }
+
+    #[test]
+    fn test_auto_resolve_not_random_word() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("foobar");
+        let json = serde_json::to_string(&result).unwrap();
+        // Should be treated as Python, not auto-resolved.
+        // Monty will try to run it as Python (likely a NameError).
+        assert!(!json.contains(r#""method":"get_state""#), "Should not auto-resolve: {json}");
+        assert!(!json.contains(r#""method":"get_states""#), "Should not auto-resolve: {json}");
+    }
+
+    #[test]
+    fn test_history_recorded() {
+        let mut engine = ShellEngine::new();
+        engine.eval("%ls");
+        engine.eval("state('x')");
+        assert_eq!(engine.session.history().len(), 2);
+    }
+
+    #[test]
+    fn test_prompt() {
         let engine = ShellEngine::new();
         assert_eq!(engine.prompt(), "≫ ");
     }
 
     #[test]
-    fn test_fulfill_state_list_with_summary() {
+    fn test_fulfill_state_list_with_summary() {
+        let mut engine = ShellEngine::new();
+        let data = r#"[
+            {"entity_id": "sensor.temp", "state": "22.5", "last_changed": "2026-02-15T10:00:00Z", "attributes": {"device_class": "temperature", "unit_of_measurement": "°C"}},
+            {"entity_id": "sensor.humidity", "state": "45", "last_changed": "2026-02-15T10:00:00Z", "attributes": {"device_class": "humidity", "unit_of_measurement": "%"}}
+        ]"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_string(&result).unwrap();
+        // Should be a vstack with summary + table.
+        assert!(json.contains(r#""type":"vstack""#));
+        assert!(json.contains(r#""type":"summary""#));
+        assert!(json.contains(r#""type":"table""#));
+        assert!(json.contains("2 entities"));
+        assert!(json.contains("sensor: 2"));
+        // Units should be appended.
+        assert!(json.contains("22.5 °C"));
+        assert!(json.contains("45 %"));
+    }
+
+    #[test]
+    fn test_fulfill_state_list_dedups_entity_ids() {
+        let mut engine = ShellEngine::new();
+        let data = r#"[
+            {"entity_id": "sensor.temp", "state": "20", "attributes": {}},
+            {"entity_id": "light.porch", "state": "off", "attributes": {}},
+            {"entity_id": "sensor.temp", "state": "23", "attributes": {}}
+        ]"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(json.matches(r#""sensor.temp""#).count(), 1, "Expected one row: {json}");
+        assert!(json.contains("2 entities"), "Expected deduped count: {json}");
+        assert!(json.contains("sensor: 1"), "Expected deduped domain count: {json}");
+        // The last occurrence wins.
+        assert!(json.contains(r#""23""#), "Expected latest state: {json}");
+        assert!(!json.contains(r#""20""#), "Stale state should be dropped: {json}");
+    }
+
+    #[test]
+    fn test_state_table_column_types() {
+        let mut engine = ShellEngine::new();
+        let data = r#"[
+            {"entity_id": "sensor.temp", "state": "22.5", "attributes": {}},
+            {"entity_id": "sensor.humidity", "state": "45", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_string(&engine.fulfill_host_call("call_1", data)).unwrap();
+        assert!(
+            json.contains(r#""column_types":["icon","text","number","text"]"#),
+            "Expected numeric state column: {json}"
+        );
+
+        let data = r#"[
+            {"entity_id": "sensor.temp", "state": "22.5", "attributes": {}},
+            {"entity_id": "light.porch", "state": "off", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_string(&engine.fulfill_host_call("call_2", data)).unwrap();
+        assert!(
+            json.contains(r#""column_types":["icon","text","text","text"]"#),
+            "Expected text state column: {json}"
+        );
+    }
+
+    #[test]
+    fn test_fulfill_state_list_with_binary_sensors() {
+        let mut engine = ShellEngine::new();
+        let data = r#"[
+            {"entity_id": "binary_sensor.front_door", "state": "off", "last_changed": "2026-02-15T09:30:00Z", "attributes": {"device_class": "door"}},
+            {"entity_id": "binary_sensor.motion", "state": "on", "last_changed": "2026-02-15T09:45:00Z", "attributes": {"device_class": "motion"}}
+        ]"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("󰷚")); // closed door icon
+        assert!(json.contains("○"));  // off indicator
+        assert!(json.contains("󰒲")); // motion detected icon
+        assert!(json.contains("●"));  // on indicator
+    }
+
+    #[test]
+    fn test_fulfill_single_state_entity_card() {
+        let mut engine = ShellEngine::new();
+        let data = r#"{"entity_id": "sensor.temp", "state": "22.5", "last_changed": "2026-02-15T10:30:00Z", "attributes": {"unit_of_measurement": "°C", "device_class": "temperature", "friendly_name": "Living Room Temperature"}}"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"entity_card""#));
+        assert!(json.contains("sensor.temp"));
+        assert!(json.contains("22.5"));
+        assert!(json.contains("󰔏")); // temperature icon
+        assert!(json.contains("Living Room Temperature"));
+        assert!(json.contains("accent")); // state color for numeric
+        assert!(json.contains("°C"));
+        assert!(json.contains("temperature")); // device_class
+    }
+
+    #[test]
+    fn test_get_badge_renders_badge() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("%get binary_sensor.front_door --badge");
+        let spec: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"entity_id": "binary_sensor.front_door", "state": "on", "attributes": {"device_class": "door", "friendly_name": "Front Door"}}"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"badge""#), "Expected badge: {json}");
+        assert!(json.contains(r#""color":"success""#), "Expected on color: {json}");
+        assert!(json.contains("Front Door"), "Expected name: {json}");
+        assert!(!json.contains("entity_card"), "Should not render a card: {json}");
+    }
+
+    #[test]
+    fn test_get_without_badge_renders_card() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("%get binary_sensor.front_door");
+        let spec: serde_json::Value = serde_json::to_value(&result).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"entity_id": "binary_sensor.front_door", "state": "on", "attributes": {}}"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"entity_card""#), "Expected card: {json}");
+    }
+
+    #[test]
+    fn test_get_single_attribute() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%get light.kitchen brightness")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"entity_id": "light.kitchen", "state": "on", "attributes": {"brightness": 128, "hs_color": [30.0, 80.0]}}"#;
+        let result = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(result["type"], "copyable", "Expected copyable: {result}");
+        assert_eq!(result["content"], "128");
+
+        let spec = serde_json::to_value(engine.eval("%get light.kitchen attributes.hs_color.1")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let result = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(result["content"], "80.0");
+
+        let spec = serde_json::to_value(engine.eval("%get light.kitchen color_temp")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_string(&engine.fulfill_host_call(call_id, data)).unwrap();
+        assert!(json.contains(r#""type":"error""#), "Expected missing attribute error: {json}");
+    }
+
+    #[test]
+    fn test_refresh_reissues_last_get() {
+        let mut engine = ShellEngine::new();
+        let first = serde_json::to_value(engine.eval("%get sensor.temp")).unwrap();
+        engine.fulfill_host_call(
+            first["call_id"].as_str().unwrap(),
+            r#"{"entity_id": "sensor.temp", "state": "21", "attributes": {}}"#,
+        );
+
+        let again = serde_json::to_value(engine.eval("%refresh")).unwrap();
+        assert_eq!(again["type"], "host_call");
+        assert_eq!(again["method"], "get_state");
+        assert_eq!(again["params"], first["params"]);
+        assert_ne!(again["call_id"], first["call_id"], "Refresh needs a fresh call ID");
+    }
+
+    #[test]
+    fn test_refresh_keeps_badge_view() {
+        let mut engine = ShellEngine::new();
+        engine.eval("%get binary_sensor.door --badge");
+        let again = serde_json::to_value(engine.eval("%refresh")).unwrap();
+        let result = engine.fulfill_host_call(
+            again["call_id"].as_str().unwrap(),
+            r#"{"entity_id": "binary_sensor.door", "state": "off", "attributes": {}}"#,
+        );
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"badge""#), "Expected badge: {json}");
+    }
+
+    #[test]
+    fn test_refresh_without_prior_entity() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("%refresh");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"error""#), "Expected error: {json}");
+    }
+
+    #[test]
+    fn test_fulfill_attrs_only() {
+        let mut engine = ShellEngine::new();
+        let data = r#"{"__attrs_only": true, "entity": {"entity_id": "sensor.temp", "state": "22.5", "attributes": {"device_class": "temperature", "unit_of_measurement": "°C"}}}"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"key_value""#));
+        assert!(json.contains("sensor.temp"));
+        assert!(json.contains("device_class"));
+        assert!(json.contains("temperature"));
+    }
+
+    #[test]
+    fn test_fulfill_diff() {
+        let mut engine = ShellEngine::new();
+        let data = r#"{"__diff": true, "entity_a": {"entity_id": "sensor.temp", "state": "22.5", "attributes": {"device_class": "temperature", "unit_of_measurement": "°C"}}, "entity_b": {"entity_id": "sensor.humidity", "state": "45", "attributes": {"device_class": "humidity", "unit_of_measurement": "%"}}}"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"vstack""#));
+        assert!(json.contains("Comparing"));
+        assert!(json.contains("sensor.temp"));
+        assert!(json.contains("sensor.humidity"));
+        assert!(json.contains("device_class"));
+    }
+
+    #[test]
+    fn test_find_filters_unmatched_entities() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%find *motion*")).unwrap();
+        assert_eq!(spec["method"], "find_entities");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        // Host ignored the pattern and returned everything.
+        let data = r#"[
+            {"entity_id": "binary_sensor.hall_motion", "state": "on", "attributes": {}},
+            {"entity_id": "sensor.temperature", "state": "21", "attributes": {}},
+            {"entity_id": "binary_sensor.Kitchen_Motion", "state": "off", "attributes": {}},
+            {"entity_id": "light.porch", "state": "off", "attributes": {}}
+        ]"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("binary_sensor.hall_motion"), "Expected hall motion: {json}");
+        assert!(json.contains("binary_sensor.Kitchen_Motion"), "Expected kitchen motion: {json}");
+        assert!(!json.contains("sensor.temperature"), "Unexpected temperature: {json}");
+        assert!(!json.contains("light.porch"), "Unexpected porch light: {json}");
+    }
+
+    #[test]
+    fn test_find_ranks_exact_match_first() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%find *light.kitchen*")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "light.kitchen_cabinets", "state": "on", "attributes": {}},
+            {"entity_id": "light.kitchen", "state": "off", "attributes": {}},
+            {"entity_id": "light.kitchen_island", "state": "on", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let rows = json["children"][1]["rows"].as_array().unwrap();
+        let ids: Vec<&str> = rows.iter().map(|r| r[1].as_str().unwrap()).collect();
+        assert_eq!(ids, ["light.kitchen", "light.kitchen_cabinets", "light.kitchen_island"]);
+
+        let item = serde_json::json!({"entity_id": "sensor.t1", "attributes": {"friendly_name": "Hall temp"}});
+        assert_eq!(find_rank(&item, "*hall*"), 1);
+        assert_eq!(find_rank(&item, "*temp*"), 2);
+        assert_eq!(find_rank(&item, "sensor.?1"), 3);
+    }
+
+    #[test]
+    fn test_find_no_matches() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%find *garage*")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[{"entity_id": "light.porch", "state": "off", "attributes": {}}]"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("No entities match"), "Expected no-match text: {json}");
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*motion*", "binary_sensor.hall_motion"));
+        assert!(glob_match("light.*", "light.porch"));
+        assert!(glob_match("sensor.temp_?", "sensor.temp_1"));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("LIGHT.*", "light.porch"));
+        assert!(!glob_match("light.*", "switch.light"));
+        assert!(!glob_match("sensor.temp_?", "sensor.temp_10"));
+        assert!(!glob_match("*motion", "binary_sensor.motion_2"));
+    }
+
+    #[test]
+    fn test_ls_count_renders_summary_only() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls light --count")).unwrap();
+        assert_eq!(spec["method"], "get_states");
+        assert_eq!(spec["params"]["domain"], "light");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"[
+            {"entity_id": "light.kitchen", "state": "on", "attributes": {}},
+            {"entity_id": "light.porch", "state": "off", "attributes": {}}
+        ]"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert_eq!(
+            serde_json::to_value(&result).unwrap(),
+            serde_json::json!({ "type": "summary", "content": "2 light entities" })
+        );
+        assert!(!json.contains(r#""type":"table""#), "Should not render table: {json}");
+    }
+
+    #[test]
+    fn test_ls_chart_renders_domain_pie() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls --chart")).unwrap();
+        assert_eq!(spec["method"], "get_states");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"[
+            {"entity_id": "light.kitchen", "state": "on", "attributes": {}},
+            {"entity_id": "light.porch", "state": "off", "attributes": {}},
+            {"entity_id": "sensor.temp", "state": "21", "attributes": {}},
+            {"entity_id": "light.hall", "state": "on", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert!(!json.to_string().contains(r#""type":"table""#), "Should not render table: {json}");
+        let chart = json["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["type"] == "echarts")
+            .expect("Expected echarts pie");
+        let slices = &chart["option"]["series"][0];
+        assert_eq!(slices["type"], "pie");
+        assert_eq!(
+            slices["data"],
+            serde_json::json!([
+                { "name": "light", "value": 3.0 },
+                { "name": "sensor", "value": 1.0 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_inspect_chains_state_attrs_history() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%inspect sensor.temp")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap().to_string();
+
+        let state = r#"{"entity_id": "sensor.temp", "state": "21.5", "last_changed": "2026-02-15T10:00:00Z", "attributes": {"unit_of_measurement": "°C", "friendly_name": "Temp"}}"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(&call_id, state)).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        assert_eq!(spec["params"]["attrs_only"], true);
+        let call_id = spec["call_id"].as_str().unwrap().to_string();
+
+        let attrs = format!(r#"{{"__attrs_only": true, "entity": {state}}}"#);
+        let spec = serde_json::to_value(engine.fulfill_host_call(&call_id, &attrs)).unwrap();
+        assert_eq!(spec["method"], "get_history");
+        assert_eq!(spec["params"]["hours"], 6);
+        let call_id = spec["call_id"].as_str().unwrap().to_string();
+
+        let history = r#"[[
+            {"entity_id": "sensor.temp", "state": "20.0", "last_changed": "2026-02-15T08:00:00Z"},
+            {"entity_id": "sensor.temp", "state": "21.5", "last_changed": "2026-02-15T10:00:00Z"}
+        ]]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(&call_id, history)).unwrap();
+        assert_eq!(json["type"], "vstack", "Expected inspection panel: {json}");
+        let types: Vec<&str> = json["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| c["type"].as_str().unwrap())
+            .collect();
+        assert_eq!(types, vec!["entity_card", "key_value", "sparkline"]);
+    }
+
+    #[test]
+    fn test_inspect_missing_entity_stops_chain() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%inspect sensor.nope")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(
+            engine.fulfill_host_call(call_id, r#"{"error": "Entity not found: sensor.nope"}"#),
+        )
+        .unwrap();
+        assert_ne!(json["type"], "host_call", "Chain should stop: {json}");
+    }
+
+    #[test]
+    fn test_attrs_many_attributes_grouped() {
+        let mut engine = ShellEngine::new();
+        let data = r#"{"__attrs_only": true, "entity": {"entity_id": "climate.living", "state": "heat", "attributes": {
+            "current_temperature": 20.5, "temperature": 21, "hvac_action": "heating",
+            "hvac_modes": ["off", "heat"], "min_temp": 7, "max_temp": 35,
+            "supported_features": 387, "friendly_name": "Living", "attribution": "Acme",
+            "restored": true
+        }}}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call("call_1", data)).unwrap();
+        assert_eq!(json["type"], "key_value", "{json}");
+        let sections = json["sections"].as_array().expect("Expected sections");
+        let names: Vec<&str> = sections.iter().map(|s| s[0].as_str().unwrap()).collect();
+        assert_eq!(names, vec!["Primary", "Config", "Diagnostic"]);
+        let keys = |i: usize| -> Vec<String> {
+            sections[i][1].as_array().unwrap().iter().map(|p| p[0].as_str().unwrap().to_string()).collect()
+        };
+        assert!(keys(0).contains(&"current_temperature".to_string()));
+        assert!(keys(1).contains(&"hvac_modes".to_string()));
+        assert!(keys(1).contains(&"min_temp".to_string()));
+        assert_eq!(keys(2), vec!["attribution", "restored"]);
+    }
+
+    #[test]
+    fn test_attrs_few_attributes_flat() {
+        let mut engine = ShellEngine::new();
+        let data = r#"{"__attrs_only": true, "entity": {"entity_id": "light.x", "state": "on", "attributes": {"brightness": 200}}}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call("call_1", data)).unwrap();
+        assert_eq!(json["pairs"][0][0], "brightness");
+        assert_eq!(json["sections"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn test_count_last_states() {
+        let mut engine = ShellEngine::new();
+        let json = serde_json::to_value(engine.eval("%count")).unwrap();
+        assert_eq!(json["type"], "error", "Nothing listed yet: {json}");
+
+        let spec = serde_json::to_value(engine.eval("%ls binary_sensor")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "binary_sensor.a", "state": "off", "attributes": {}},
+            {"entity_id": "binary_sensor.b", "state": "on", "attributes": {}},
+            {"entity_id": "binary_sensor.c", "state": "off", "attributes": {}},
+            {"entity_id": "binary_sensor.d", "state": "unavailable", "attributes": {}}
+        ]"#;
+        engine.fulfill_host_call(call_id, data);
+
+        let json = serde_json::to_value(engine.eval("%count")).unwrap();
+        assert_eq!(json["type"], "key_value", "{json}");
+        assert_eq!(json["title"], "States — 4 entities");
+        assert_eq!(
+            json["pairs"],
+            serde_json::json!([["off", "2"], ["on", "1"], ["unavailable", "1"]])
+        );
+    }
+
+    #[test]
+    fn test_get_expand_group_fetches_members() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%get group.lights --expand")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"entity_id": "group.lights", "state": "on", "attributes": {
+            "friendly_name": "Lights",
+            "entity_id": ["light.kitchen", "light.hall", "light.porch"]
+        }}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["type"], "host_call", "{json}");
+        assert_eq!(json["method"], "get_states_by_id");
+        assert_eq!(
+            json["params"]["entity_ids"],
+            serde_json::json!(["light.kitchen", "light.hall", "light.porch"])
+        );
+
+        // Members come back as a plain state list → entity table.
+        let call_id = json["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "light.kitchen", "state": "on", "attributes": {}},
+            {"entity_id": "light.hall", "state": "off", "attributes": {}},
+            {"entity_id": "light.porch", "state": "on", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["children"][1]["type"], "table", "{json}");
+        assert_eq!(json["children"][1]["rows"].as_array().unwrap().len(), 3);
+
+        // Not a group — nothing to expand, shown like a normal %get.
+        let spec = serde_json::to_value(engine.eval("%get light.kitchen --expand")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"entity_id": "light.kitchen", "state": "on", "attributes": {}}"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_ne!(json["type"], "host_call", "{json}");
+    }
+
+    #[test]
+    fn test_ls_json_renders_copyable() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls sensor --json")).unwrap();
+        assert_eq!(spec["method"], "get_states");
+        assert_eq!(spec["params"]["domain"], "sensor");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "sensor.a", "state": "1", "attributes": {}},
+            {"entity_id": "sensor.b", "state": "2", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["type"], "copyable", "{json}");
+        let exported: serde_json::Value = serde_json::from_str(json["content"].as_str().unwrap()).unwrap();
+        assert_eq!(exported, serde_json::from_str::<serde_json::Value>(data).unwrap());
+    }
+
+    #[test]
+    fn test_diff_against_snapshot() {
+        let mut engine = ShellEngine::new();
+        let json = serde_json::to_value(engine.eval("%diff light.kitchen @snapshot")).unwrap();
+        assert_eq!(json["type"], "error", "No snapshot yet: {json}");
+
+        let saved = r#"{"entity_id": "light.kitchen", "state": "on",
+            "attributes": {"brightness": 200, "color_mode": "brightness"}}"#;
+        let spec = serde_json::to_value(engine.eval("%snapshot light.kitchen")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, saved)).unwrap();
+        assert_eq!(json["type"], "summary", "{json}");
+
+        // Unchanged — no rows differ.
+        let spec = serde_json::to_value(engine.eval("%diff light.kitchen @snapshot --changed-only")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, saved)).unwrap();
+        assert_eq!(json["children"][1]["rows"], serde_json::json!([]), "{json}");
+
+        // Changed since the snapshot — the differing rows show up.
+        let live = r#"{"entity_id": "light.kitchen", "state": "off",
+            "attributes": {"brightness": null, "color_mode": "brightness"}}"#;
+        let spec = serde_json::to_value(engine.eval("%diff light.kitchen @snapshot --changed-only")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, live)).unwrap();
+        let table = &json["children"][1];
+        assert_eq!(
+            table["headers"],
+            serde_json::json!(["attribute", "light.kitchen @snapshot", "light.kitchen"])
+        );
+        let changed: Vec<&str> = table["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r[0].as_str().unwrap())
+            .collect();
+        assert_eq!(changed, vec!["state", "brightness"]);
+    }
+
+    #[test]
+    fn test_entity_card_trend() {
+        let mut engine = ShellEngine::new();
+        let mut fetch = |state: &str| {
+            let spec = serde_json::to_value(engine.eval("%get sensor.temp")).unwrap();
+            let call_id = spec["call_id"].as_str().unwrap().to_string();
+            let data = format!(r#"{{"entity_id": "sensor.temp", "state": "{state}", "attributes": {{}}}}"#);
+            serde_json::to_value(engine.fulfill_host_call(&call_id, &data)).unwrap()
+        };
+        assert!(fetch("20.5")["trend"].is_null(), "No previous value yet");
+        assert_eq!(fetch("21.0")["trend"], "up");
+        assert_eq!(fetch("21.0")["trend"], "flat");
+        assert_eq!(fetch("19.0")["trend"], "down");
+        assert!(fetch("unavailable")["trend"].is_null());
+    }
+
+    #[test]
+    fn test_ask_with_agent() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ask @claude why")).unwrap();
+        assert_eq!(spec["method"], "conversation_process");
+        assert_eq!(spec["params"]["agent_id"], "claude");
+        assert_eq!(spec["params"]["text"], "why");
+
+        let spec = serde_json::to_value(engine.eval("%ask why")).unwrap();
+        assert!(spec["params"].get("agent_id").is_none(), "{spec}");
+    }
+
+    #[test]
+    fn test_ask_without_agent_suggests_commands() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ask show me history of sensor.x")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"__conversation": true, "no_agent": true, "response": "", "agent_id": "none"}"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(spec["type"], "vstack");
+        assert_eq!(spec["children"][1]["content"], "%hist sensor.x");
+
+        // A normal reply still renders as an assistant response.
+        let spec = serde_json::to_value(engine.eval("%ask why")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"__conversation": true, "response": "Because.", "agent_id": "conversation.claude"}"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(spec["type"], "assistant");
+    }
+
+    #[test]
+    fn test_get_without_domain_falls_back_to_find() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%get kitchen")).unwrap();
+        assert_eq!(spec["method"], "find_entities");
+        assert_eq!(spec["params"]["pattern"], "*kitchen*");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"[{"entity_id": "light.kitchen", "state": "on", "attributes": {}}]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(spec["children"][1]["rows"][0][1], "light.kitchen", "{spec}");
+    }
+
+    #[test]
+    fn test_get_glob_finds_then_fetches() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%get light.*")).unwrap();
+        assert_eq!(spec["method"], "find_entities");
+        assert_eq!(spec["params"]["pattern"], "light.*");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "light.kitchen", "state": "on", "attributes": {}},
+            {"entity_id": "light.hall", "state": "off", "attributes": {}}
+        ]"#;
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["method"], "get_states_by_id", "{json}");
+        assert_eq!(json["params"]["entity_ids"], serde_json::json!(["light.kitchen", "light.hall"]));
+
+        let call_id = json["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(json["children"][1]["type"], "table", "{json}");
+
+        let spec = serde_json::to_value(engine.eval("%get switch.*")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, "[]")).unwrap();
+        assert_eq!(json["content"], "No entities match 'switch.*'.");
+    }
+
+    #[test]
+    fn test_diff_nested_attributes() {
+        let mut engine = ShellEngine::new();
+        let data = r#"{"__diff": true,
+            "entity_a": {"entity_id": "weather.a", "state": "sunny",
+                "attributes": {"forecast": {"temperature": 20, "condition": "sunny", "wind": {"speed": 5}}}},
+            "entity_b": {"entity_id": "weather.b", "state": "sunny",
+                "attributes": {"forecast": {"temperature": 23, "condition": "sunny", "wind": {"speed": 5}}}}
+        }"#;
+        let json = serde_json::to_value(engine.fulfill_host_call("call_1", data)).unwrap();
+        let rows = &json["children"][1]["rows"];
+        assert_eq!(
+            *rows,
+            serde_json::json!([
+                ["state", "sunny", "sunny"],
+                ["forecast.condition", "sunny", "sunny"],
+                ["forecast.temperature", "20", "23"],
+                ["forecast.wind.speed", "5", "5"]
+            ]),
+            "{json}"
+        );
+
+        let spec = serde_json::to_value(engine.eval("%diff weather.a weather.b --changed-only")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(
+            json["children"][1]["rows"],
+            serde_json::json!([["forecast.temperature", "20", "23"]])
+        );
+    }
+
+    #[test]
+    fn test_caps_blocks_unsupported_methods() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%caps")).unwrap();
+        assert_eq!(spec["method"], "get_capabilities");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_value(
+            engine.fulfill_host_call(call_id, r#"["get_state", "get_states", "get_history"]"#),
+        )
+        .unwrap();
+        assert_eq!(json["children"][0]["content"], "3 host methods available", "{json}");
+
+        let json = serde_json::to_value(engine.eval("statistics('sensor.energy')")).unwrap();
+        assert_eq!(json["type"], "error", "{json}");
+        assert!(json["message"].as_str().unwrap().contains("doesn't support get_statistics"));
+
+        // Output printed before the blocked call survives, and the REPL
+        // reset is reported rather than silent.
+        let json = serde_json::to_value(engine.eval("print('before')\nstatistics('sensor.energy')")).unwrap();
+        assert_eq!(json["children"][0]["content"], "before\n", "{json}");
+        let message = json["children"][1]["message"].as_str().unwrap();
+        assert!(message.contains("doesn't support get_statistics"), "{message}");
+        assert!(message.contains("the REPL was reset"), "{message}");
+
+        // Supported methods still go through.
+        let json = serde_json::to_value(engine.eval("state('sensor.temp')")).unwrap();
+        assert_eq!(json["method"], "get_state", "{json}");
+    }
+
+    #[test]
+    fn test_fmt_grouped_numbers() {
+        assert_eq!(group_thousands("12345678").as_deref(), Some("12,345,678"));
+        assert_eq!(group_thousands("-1234").as_deref(), Some("-1,234"));
+        assert_eq!(group_thousands("999"), None);
+        assert_eq!(group_thousands("1234.5"), None);
+        assert_eq!(group_thousands("on"), None);
+
+        let mut engine = ShellEngine::new();
+        let data = r#"[{"entity_id": "sensor.energy", "state": "12345678",
+            "attributes": {"unit_of_measurement": "Wh"}}]"#;
+        let ls = |engine: &mut ShellEngine| {
+            let spec = serde_json::to_value(engine.eval("%ls sensor")).unwrap();
+            let call_id = spec["call_id"].as_str().unwrap().to_string();
+            serde_json::to_value(engine.fulfill_host_call(&call_id, data)).unwrap()
+        };
+        assert_eq!(ls(&mut engine)["children"][1]["rows"][0][2], "12345678 Wh");
+        engine.eval("%fmt grouped");
+        assert_eq!(ls(&mut engine)["children"][1]["rows"][0][2], "12,345,678 Wh");
+        engine.eval("%fmt ungrouped");
+        assert_eq!(ls(&mut engine)["children"][1]["rows"][0][2], "12345678 Wh");
+    }
+
+    #[test]
+    fn test_hist_single_point_renders_summary() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%hist sensor.temp")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[[
+            {"entity_id": "sensor.temp", "state": "21.5", "last_changed": "2026-02-15T10:00:00Z",
+             "attributes": {"friendly_name": "Temp", "unit_of_measurement": "°C"}}
+        ]]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(spec["type"], "summary");
+        assert_eq!(spec["content"], "Temp: 21.5 °C (only one reading in this period)");
+    }
+
+    #[test]
+    fn test_hist_as_table_renders_rows() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%hist sensor.temp --as table")).unwrap();
+        assert_eq!(spec["method"], "get_history");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"[[
+            {"entity_id": "sensor.temp", "state": "20.5", "last_changed": "2026-02-15T10:00:00Z", "attributes": {}},
+            {"entity_id": "sensor.temp", "state": "21.0", "last_changed": "2026-02-15T11:00:00Z", "attributes": {}}
+        ]]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let table = &spec["children"][1];
+        assert_eq!(table["type"], "table");
+        assert_eq!(table["headers"], serde_json::json!(["time", "state"]));
+        assert_eq!(
+            table["rows"],
+            serde_json::json!([["10:00:00", "20.5"], ["11:00:00", "21.0"]])
+        );
+    }
+
+    #[test]
+    fn test_states_table_colours_state_cells() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls light")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "light.kitchen", "state": "on", "attributes": {}},
+            {"entity_id": "light.porch", "state": "unavailable", "attributes": {}}
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let styles = &spec["children"][1]["cell_styles"];
+        assert_eq!(styles[0], serde_json::json!([null, null, "success", null]));
+        assert_eq!(styles[1][2], "error");
+    }
+
+    #[test]
+    fn test_hist_compare_fetches_both_windows() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%hist sensor.temp --compare 1d")).unwrap();
+        assert_eq!(spec["method"], "get_history");
+        assert_eq!(spec["params"]["hours"], 24);
+        assert!(spec["params"].get("offset_hours").is_none());
+        let call_id = spec["call_id"].as_str().unwrap();
+        let today = r#"[[
+            {"entity_id": "sensor.temp", "state": "21.0", "last_changed": "2026-02-15T10:00:00Z"},
+            {"entity_id": "sensor.temp", "state": "22.0", "last_changed": "2026-02-15T11:00:00Z"}
+        ]]"#;
+
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, today)).unwrap();
+        assert_eq!(spec["method"], "get_history");
+        assert_eq!(spec["params"]["offset_hours"], 24);
+        let call_id = spec["call_id"].as_str().unwrap();
+        let yesterday = r#"[[
+            {"entity_id": "sensor.temp", "state": "19.5", "last_changed": "2026-02-14T10:00:00Z"}
+        ]]"#;
+
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, yesterday)).unwrap();
+        assert_eq!(spec["type"], "echarts");
+        let series = &spec["option"]["series"];
+        assert_eq!(series.as_array().unwrap().len(), 2);
+        assert_eq!(series[0]["name"], "last 1d");
+        assert_eq!(series[1]["name"], "1d before");
+        // Yesterday's 10:00 reading lines up with today's.
+        assert_eq!(series[1]["data"][0][0], series[0]["data"][0][0]);
+    }
+
+    #[test]
+    fn test_hist_compare_honours_hours() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%hist sensor.temp -h 6 --compare 1d")).unwrap();
+        assert_eq!(spec["params"]["hours"], 6);
+        let call_id = spec["call_id"].as_str().unwrap();
+        let today = r#"[[{"entity_id": "sensor.temp", "state": "21.0", "last_changed": "2026-02-15T10:00:00Z"}]]"#;
+
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, today)).unwrap();
+        assert_eq!(spec["params"]["hours"], 6);
+        assert_eq!(spec["params"]["offset_hours"], 24);
+        let call_id = spec["call_id"].as_str().unwrap();
+        let yesterday = r#"[[{"entity_id": "sensor.temp", "state": "19.5", "last_changed": "2026-02-14T10:00:00Z"}]]"#;
+
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, yesterday)).unwrap();
+        assert_eq!(spec["option"]["series"][0]["name"], "last 6h");
+        assert_eq!(spec["option"]["series"][1]["name"], "1d before");
+    }
+
+    #[test]
+    fn test_theme_light_darkens_timeline_greys() {
+        let mut engine = ShellEngine::new();
+        let data = r#"[[
+            {"entity_id": "binary_sensor.door", "state": "off", "last_changed": "2026-02-15T10:00:00Z"},
+            {"entity_id": "binary_sensor.door", "state": "unknown", "last_changed": "2026-02-15T11:00:00Z"}
+        ]]"#;
+        let colors = |engine: &mut ShellEngine| {
+            let spec = serde_json::to_value(engine.eval("%hist binary_sensor.door")).unwrap();
+            let call_id = spec["call_id"].as_str().unwrap().to_string();
+            let spec = serde_json::to_value(engine.fulfill_host_call(&call_id, data)).unwrap();
+            assert_eq!(spec["type"], "timeline");
+            (spec["segments"][0][3].clone(), spec["segments"][1][3].clone())
+        };
+        assert_eq!(colors(&mut engine), ("#969696".into(), "#606060".into()));
+
+        engine.eval("%theme light");
+        assert_eq!(colors(&mut engine), ("#6e6e6e".into(), "#424242".into()));
+        let spec = serde_json::to_value(engine.eval("%theme")).unwrap();
+        assert_eq!(spec["content"], "Theme: light");
+    }
+
+    #[test]
+    fn test_ls_attrs_adds_column() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls sensor --attrs battery_level")).unwrap();
+        assert_eq!(spec["params"]["domain"], "sensor");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"[
+            {"entity_id": "sensor.door", "state": "off", "attributes": {"battery_level": 87}},
+            {"entity_id": "sensor.window", "state": "on", "attributes": {}}
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let table = &spec["children"][1];
+        assert_eq!(table["headers"][4], "battery_level");
+        assert_eq!(table["rows"][0][4], "87");
+        assert_eq!(table["rows"][1][4], "-");
+        assert_eq!(table["column_types"][4], "number");
+    }
+
+    #[test]
+    fn test_ls_spark_batches_history_into_sparkline_cells() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls sensor --spark")).unwrap();
+        assert_eq!(spec["method"], "get_states");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let states = r#"[
+            {"entity_id": "sensor.temp", "state": "21.5", "attributes": {}},
+            {"entity_id": "sensor.power", "state": "140", "attributes": {}},
+            {"entity_id": "sensor.mode", "state": "eco", "attributes": {}}
+        ]"#;
+
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, states)).unwrap();
+        assert_eq!(spec["method"], "get_history");
+        assert_eq!(spec["params"]["entity_ids"], serde_json::json!(["sensor.temp", "sensor.power"]));
+        let call_id = spec["call_id"].as_str().unwrap();
+        let history = r#"[
+            [{"entity_id": "sensor.temp", "state": "20.0"}, {"state": "unavailable"}, {"state": "21.5"}],
+            [{"entity_id": "sensor.power", "state": "90"}, {"state": "140"}]
+        ]"#;
+
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, history)).unwrap();
+        let table = &spec["children"][1];
+        assert_eq!(table["headers"][4], "trend");
+        assert_eq!(table["column_types"][4], "sparkline");
+        assert_eq!(
+            table["sparklines"],
+            serde_json::json!([[20.0, 21.5], [90.0, 140.0], []])
+        );
+    }
+
+    #[test]
+    fn test_examples_are_runnable_snippets() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%examples")).unwrap();
+        assert_eq!(spec["type"], "snippets", "{spec}");
+        let snippets = spec["snippets"].as_array().unwrap();
+        assert!(snippets.len() > 5, "{spec}");
+
+        for snippet in snippets {
+            let snippet = snippet.as_str().unwrap();
+            let spec = serde_json::to_value(ShellEngine::new().eval(snippet)).unwrap();
+            assert_ne!(spec["type"], "error", "{snippet}: {spec}");
+        }
+    }
+
+    #[test]
+    fn test_presence_badges() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%presence")).unwrap();
+        assert_eq!(spec["method"], "get_states");
+        assert_eq!(spec["params"]["domain"], "person");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let people = r#"[
+            {"entity_id": "person.sam", "state": "not_home", "attributes": {"friendly_name": "Sam"}},
+            {"entity_id": "person.alex", "state": "home", "attributes": {"friendly_name": "Alex"}},
+            {"entity_id": "person.kim", "state": "Work", "attributes": {"friendly_name": "Kim"}}
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, people)).unwrap();
+        assert_eq!(spec["children"][0]["content"], "1 of 3 home");
+        let badges: Vec<(&str, &str)> = spec["children"][1]["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| (b["label"].as_str().unwrap(), b["color"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            badges,
+            [("Alex · home", "home"), ("Kim · Work", "away"), ("Sam · away", "away")]
+        );
+    }
+
+    #[test]
+    fn test_streamed_states_grow_the_table() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls light")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let light = |id: &str| serde_json::json!({"entity_id": id, "state": "on", "attributes": {}});
+        let rows = |spec: &serde_json::Value| spec["children"][0]["children"][1]["rows"].as_array().unwrap().len();
+
+        let first = serde_json::json!([light("light.a"), light("light.b")]).to_string();
+        let spec = serde_json::to_value(engine.fulfill_host_call_chunk(call_id, &first)).unwrap();
+        assert_eq!(rows(&spec), 2, "{spec}");
+
+        let second = serde_json::json!([light("light.c")]).to_string();
+        let spec = serde_json::to_value(engine.fulfill_host_call_chunk(call_id, &second)).unwrap();
+        assert_eq!(rows(&spec), 3, "{spec}");
+
+        let last = serde_json::json!([light("light.d")]).to_string();
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, &last)).unwrap();
+        assert_eq!(spec["children"][1]["rows"].as_array().unwrap().len(), 4, "{spec}");
+        assert!(engine.session.take_partial_states(call_id).is_none());
+    }
+
+    #[test]
+    fn test_streamed_states_follow_the_pending_view() {
+        let mut engine = ShellEngine::new();
+        let light = serde_json::json!([{"entity_id": "light.a", "state": "on", "attributes": {}}]).to_string();
+
+        // No call in flight — the chunk is refused and nothing is kept.
+        let spec = serde_json::to_value(engine.fulfill_host_call_chunk("call_9", &light)).unwrap();
+        assert_eq!(spec["type"], "error", "{spec}");
+
+        // `%ls --json` shows the JSON so far, not a table.
+        let spec = serde_json::to_value(engine.eval("%ls light --json")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap().to_string();
+        let spec = serde_json::to_value(engine.fulfill_host_call_chunk(&call_id, &light)).unwrap();
+        assert_eq!(spec["children"][0]["type"], "copyable", "{spec}");
+
+        // An error instead of the last chunk drops the buffered rows, saying so.
+        let spec = serde_json::to_value(engine.fulfill_host_call(&call_id, r#"{"error": "timeout"}"#)).unwrap();
+        assert!(spec.to_string().contains("timeout"), "{spec}");
+        assert!(spec.to_string().contains("Discarded 1 entity streamed before this response"), "{spec}");
+        assert!(engine.session.take_partial_states(&call_id).is_none());
+
+        // A paused snippet only sees the list once it's complete.
+        let spec = serde_json::to_value(engine.eval("states('light')")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap().to_string();
+        let spec = serde_json::to_value(engine.fulfill_host_call_chunk(&call_id, &light)).unwrap();
+        assert_eq!(spec["type"], "text", "{spec}");
+        assert!(engine.session.has_pending_monty(&call_id));
+
+        // A new call abandons the unfinished stream.
+        engine.eval("%ls switch");
+        assert!(engine.session.take_partial_states(&call_id).is_none());
+    }
+
+    #[test]
+    fn test_ls_cover_icon_follows_position() {
         let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls cover")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
         let data = r#"[
-            {"entity_id": "sensor.temp", "state": "22.5", "last_changed": "2026-02-15T10:00:00Z", "attributes": {"device_class": "temperature", "unit_of_measurement": "°C"}},
-            {"entity_id": "sensor.humidity", "state": "45", "last_changed": "2026-02-15T10:00:00Z", "attributes": {"device_class": "humidity", "unit_of_measurement": "%"}}
+            {"entity_id": "cover.a", "state": "open", "attributes": {"current_position": 100}},
+            {"entity_id": "cover.b", "state": "open", "attributes": {"current_position": 50}},
+            {"entity_id": "cover.c", "state": "closed", "attributes": {"current_position": 0}}
         ]"#;
-        let result = engine.fulfill_host_call("call_1", data);
-        let json = serde_json::to_string(&result).unwrap();
-        // Should be a vstack with summary + table.
-        assert!(json.contains(r#""type":"vstack""#));
-        assert!(json.contains(r#""type":"summary""#));
-        assert!(json.contains(r#""type":"table""#));
-        assert!(json.contains("2 entities"));
-        assert!(json.contains("sensor: 2"));
-        // Units should be appended.
-        assert!(json.contains("22.5 °C"));
-        assert!(json.contains("45 %"));
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        let rows = spec["children"][1]["rows"].as_array().unwrap();
+        let icon = |row: usize| rows[row][0].as_str().unwrap().to_string();
+        assert_ne!(icon(1), icon(0), "{spec}");
+        assert_ne!(icon(1), icon(2), "{spec}");
+        assert!(icon(1).starts_with(icons::entity_icon("cover.b", None, Some("open"), Some(50.0))));
     }
 
     #[test]
-    fn test_fulfill_state_list_with_binary_sensors() {
+    fn test_get_with_services() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%get light.kitchen --services")).unwrap();
+        assert_eq!(spec["method"], "get_state");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let state = r#"{"entity_id": "light.kitchen", "state": "on", "attributes": {"friendly_name": "Kitchen"}}"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, state)).unwrap();
+        assert_eq!(spec["method"], "get_services");
+        assert_eq!(spec["params"]["domain"], "light");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let services = r#"[
+            {"domain": "light", "service": "turn_on", "name": "Turn on", "fields": ["brightness"]},
+            {"domain": "light", "service": "toggle", "name": "Toggle", "fields": []}
+        ]"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, services)).unwrap();
+        assert_eq!(spec["children"][0]["type"], "entity_card", "{spec}");
+        assert_eq!(spec["children"][0]["entity_id"], "light.kitchen");
+        let table = spec["children"][1].to_string();
+        assert!(table.contains("turn_on") && table.contains("toggle"), "{spec}");
+    }
+
+    #[test]
+    fn test_ls_overview_for_large_setups() {
+        let states: Vec<serde_json::Value> = (0..60)
+            .map(|i| {
+                let domain = if i < 40 { "sensor" } else { "light" };
+                serde_json::json!({ "entity_id": format!("{domain}.e{i}"), "state": "on", "attributes": {} })
+            })
+            .collect();
+        let data = serde_json::to_string(&states).unwrap();
+
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%ls")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, &data)).unwrap();
+        let table = &spec["children"][1];
+        assert_eq!(table["headers"], serde_json::json!(["domain", "entities"]));
+        assert_eq!(table["rows"], serde_json::json!([["sensor", "40"], ["light", "20"]]));
+
+        let spec = serde_json::to_value(engine.eval("%ls --all")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, &data)).unwrap();
+        assert!(spec.to_string().contains("sensor.e0"), "{spec}");
+        assert!(spec.to_string().contains("light.e59"), "{spec}");
+    }
+
+    #[test]
+    fn test_services_keyword_filter() {
         let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("%services ~turn")).unwrap();
+        assert_eq!(spec["method"], "get_services");
+        let call_id = spec["call_id"].as_str().unwrap();
+
         let data = r#"[
-            {"entity_id": "binary_sensor.front_door", "state": "off", "last_changed": "2026-02-15T09:30:00Z", "attributes": {"device_class": "door"}},
-            {"entity_id": "binary_sensor.motion", "state": "on", "last_changed": "2026-02-15T09:45:00Z", "attributes": {"device_class": "motion"}}
+            {"domain": "light", "service": "turn_on", "name": "Turn on", "description": "Turn on a light", "fields": []},
+            {"domain": "light", "service": "toggle", "name": "Toggle", "description": "Toggle a light", "fields": []},
+            {"domain": "switch", "service": "turn_off", "name": "Turn off", "description": "", "fields": []},
+            {"domain": "scene", "service": "apply", "name": "Apply", "description": "Apply a scene", "fields": []}
         ]"#;
-        let result = engine.fulfill_host_call("call_1", data);
+        let result = engine.fulfill_host_call(call_id, data);
         let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains("󰷚")); // closed door icon
-        assert!(json.contains("○"));  // off indicator
-        assert!(json.contains("󰒲")); // motion detected icon
-        assert!(json.contains("●"));  // on indicator
+        assert!(json.contains("turn_on"), "Expected turn_on: {json}");
+        assert!(json.contains("turn_off"), "Expected turn_off: {json}");
+        assert!(!json.contains("toggle"), "Unexpected toggle: {json}");
+        assert!(!json.contains("apply"), "Unexpected apply: {json}");
+        assert!(json.contains("2 services"), "Expected filtered count: {json}");
     }
 
     #[test]
-    fn test_fulfill_single_state_entity_card() {
+    fn test_python_get_services_query_param() {
         let mut engine = ShellEngine::new();
-        let data = r#"{"entity_id": "sensor.temp", "state": "22.5", "last_changed": "2026-02-15T10:30:00Z", "attributes": {"unit_of_measurement": "°C", "device_class": "temperature", "friendly_name": "Living Room Temperature"}}"#;
-        let result = engine.fulfill_host_call("call_1", data);
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"entity_card""#));
-        assert!(json.contains("sensor.temp"));
-        assert!(json.contains("22.5"));
-        assert!(json.contains("󰔏")); // temperature icon
-        assert!(json.contains("Living Room Temperature"));
-        assert!(json.contains("accent")); // state color for numeric
-        assert!(json.contains("°C"));
-        assert!(json.contains("temperature")); // device_class
+        let spec = serde_json::to_value(engine.eval("get_services(None, \"turn\")")).unwrap();
+        assert_eq!(spec["method"], "get_services");
+        assert_eq!(spec["params"]["query"], "turn");
     }
 
     #[test]
-    fn test_fulfill_attrs_only() {
+    fn test_raw_mode_skips_auto_viz() {
         let mut engine = ShellEngine::new();
-        let data = r#"{"__attrs_only": true, "entity": {"entity_id": "sensor.temp", "state": "22.5", "attributes": {"device_class": "temperature", "unit_of_measurement": "°C"}}}"#;
-        let result = engine.fulfill_host_call("call_1", data);
+        engine.eval("%raw on");
+        let spec = serde_json::to_value(engine.eval("history('sensor.temp', 6)")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"[[
+            {"entity_id": "sensor.temp", "state": "20.0", "last_changed": "2026-02-15T10:00:00Z"},
+            {"entity_id": "sensor.temp", "state": "21.5", "last_changed": "2026-02-15T11:00:00Z"}
+        ]]"#;
+        let result = engine.fulfill_host_call(call_id, data);
         let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"key_value""#));
-        assert!(json.contains("sensor.temp"));
-        assert!(json.contains("device_class"));
-        assert!(json.contains("temperature"));
+        assert!(json.contains(r#""type":"copyable""#), "Expected raw JSON: {json}");
+        assert!(!json.contains(r#""type":"sparkline""#), "Unexpected sparkline: {json}");
+
+        engine.eval("%raw off");
+        let spec = serde_json::to_value(engine.eval("history('sensor.temp', 6)")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let json = serde_json::to_string(&engine.fulfill_host_call(call_id, data)).unwrap();
+        assert!(json.contains(r#""type":"sparkline""#), "Expected sparkline: {json}");
     }
 
     #[test]
-    fn test_fulfill_diff() {
+    fn test_fmt_json_skips_auto_viz() {
         let mut engine = ShellEngine::new();
-        let data = r#"{"__diff": true, "entity_a": {"entity_id": "sensor.temp", "state": "22.5", "attributes": {"device_class": "temperature", "unit_of_measurement": "°C"}}, "entity_b": {"entity_id": "sensor.humidity", "state": "45", "attributes": {"device_class": "humidity", "unit_of_measurement": "%"}}}"#;
-        let result = engine.fulfill_host_call("call_1", data);
-        let json = serde_json::to_string(&result).unwrap();
-        assert!(json.contains(r#""type":"vstack""#));
-        assert!(json.contains("Comparing"));
-        assert!(json.contains("sensor.temp"));
-        assert!(json.contains("sensor.humidity"));
-        assert!(json.contains("device_class"));
+        let data = r#"[[
+            {"entity_id": "sensor.temp", "state": "20.0", "last_changed": "2026-02-15T10:00:00Z"},
+            {"entity_id": "sensor.temp", "state": "21.5", "last_changed": "2026-02-15T11:00:00Z"}
+        ]]"#;
+        let history = |engine: &mut ShellEngine| {
+            let spec = serde_json::to_value(engine.eval("history('sensor.temp', 6)")).unwrap();
+            let call_id = spec["call_id"].as_str().unwrap().to_string();
+            serde_json::to_value(engine.fulfill_host_call(&call_id, data)).unwrap()
+        };
+
+        engine.eval("%fmt json");
+        let spec = history(&mut engine);
+        assert_eq!(spec["type"], "copyable", "{spec}");
+        assert!(spec["content"].as_str().unwrap().contains("\"21.5\""), "{spec}");
+
+        engine.eval("%fmt table");
+        assert_eq!(history(&mut engine)["type"], "sparkline");
+
+        let spec = serde_json::to_value(engine.eval("%fmt yaml")).unwrap();
+        assert_eq!(spec["type"], "error");
+        let spec = serde_json::to_value(engine.eval("%fmt text")).unwrap();
+        assert_eq!(spec["type"], "error");
     }
 
     #[test]
@@ -2760,10 +6944,22 @@ mod tests {
         assert_eq!(format_timestamp("not-a-timestamp"), "not-a-timestamp");
     }
 
+    #[test]
+    fn test_shorten_iso_labels() {
+        let same_day = vec!["2026-02-15T08:00:00Z".to_string(), "2026-02-15T09:30:00Z".to_string()];
+        assert_eq!(shorten_iso_labels(same_day), vec!["08:00:00", "09:30:00"]);
+
+        let two_days = vec!["2026-02-15T23:00:00Z".to_string(), "2026-02-16T01:15:00+00:00".to_string()];
+        assert_eq!(shorten_iso_labels(two_days), vec!["02-15 23:00", "02-16 01:15"]);
+
+        let mixed = vec!["Mon".to_string(), "2026-02-15T08:00:00Z".to_string()];
+        assert_eq!(shorten_iso_labels(mixed.clone()), mixed);
+    }
+
     #[test]
     fn test_parse_ago_hours() {
         let args = vec![monty::MontyObject::String("6h".into())];
-        match parse_ago_to_monty(&args) {
+        match parse_ago_to_monty(&args, None) {
             monty::MontyObject::Int(n) => assert_eq!(n, 6),
             other => panic!("Expected Int, got: {other:?}"),
         }
@@ -2772,7 +6968,7 @@ mod tests {
     #[test]
     fn test_parse_ago_minutes() {
         let args = vec![monty::MontyObject::String("30m".into())];
-        match parse_ago_to_monty(&args) {
+        match parse_ago_to_monty(&args, None) {
             monty::MontyObject::Int(n) => assert_eq!(n, 1), // 30m → 1h (rounded, min 1)
             other => panic!("Expected Int, got: {other:?}"),
         }
@@ -2781,7 +6977,7 @@ mod tests {
     #[test]
     fn test_parse_ago_days() {
         let args = vec![monty::MontyObject::String("2d".into())];
-        match parse_ago_to_monty(&args) {
+        match parse_ago_to_monty(&args, None) {
             monty::MontyObject::Int(n) => assert_eq!(n, 48),
             other => panic!("Expected Int, got: {other:?}"),
         }
@@ -2790,7 +6986,7 @@ mod tests {
     #[test]
     fn test_parse_ago_weeks() {
         let args = vec![monty::MontyObject::String("1w".into())];
-        match parse_ago_to_monty(&args) {
+        match parse_ago_to_monty(&args, None) {
             monty::MontyObject::Int(n) => assert_eq!(n, 168),
             other => panic!("Expected Int, got: {other:?}"),
         }
@@ -2799,16 +6995,38 @@ mod tests {
     #[test]
     fn test_parse_ago_bare_number() {
         let args = vec![monty::MontyObject::String("12".into())];
-        match parse_ago_to_monty(&args) {
+        match parse_ago_to_monty(&args, None) {
             monty::MontyObject::Int(n) => assert_eq!(n, 12), // defaults to hours
             other => panic!("Expected Int, got: {other:?}"),
         }
     }
 
+    #[test]
+    fn test_parse_ago_clock_time() {
+        let args = vec![monty::MontyObject::String("08:00".into())];
+        let now = serde_json::json!({ "time": "10:00:00" });
+        match parse_ago_to_monty(&args, Some(&now)) {
+            monty::MontyObject::Int(n) => assert_eq!(n, 2),
+            other => panic!("Expected Int, got: {other:?}"),
+        }
+        // Later than now → since that time yesterday.
+        let args = vec![monty::MontyObject::String("22:00".into())];
+        match parse_ago_to_monty(&args, Some(&now)) {
+            monty::MontyObject::Int(n) => assert_eq!(n, 12),
+            other => panic!("Expected Int, got: {other:?}"),
+        }
+        // No cached now → default.
+        let args = vec![monty::MontyObject::String("08:00".into())];
+        match parse_ago_to_monty(&args, None) {
+            monty::MontyObject::Int(n) => assert_eq!(n, 6),
+            other => panic!("Expected Int, got: {other:?}"),
+        }
+    }
+
     #[test]
     fn test_parse_ago_int_passthrough() {
         let args = vec![monty::MontyObject::Int(24)];
-        match parse_ago_to_monty(&args) {
+        match parse_ago_to_monty(&args, None) {
             monty::MontyObject::Int(n) => assert_eq!(n, 24),
             other => panic!("Expected Int, got: {other:?}"),
         }
@@ -2826,11 +7044,11 @@ mod tests {
 
     #[test]
     fn test_state_to_timeline_color() {
-        assert_eq!(state_to_timeline_color("on"), "#44b556");
-        assert_eq!(state_to_timeline_color("off"), "#969696");
-        assert_eq!(state_to_timeline_color("unavailable"), "#c74848");
-        assert_eq!(state_to_timeline_color("unknown"), "#606060");
-        assert_eq!(state_to_timeline_color("22.5"), "#2196f3");
+        assert_eq!(state_to_timeline_color("on", Theme::Dark), "#44b556");
+        assert_eq!(state_to_timeline_color("off", Theme::Dark), "#969696");
+        assert_eq!(state_to_timeline_color("unavailable", Theme::Dark), "#c74848");
+        assert_eq!(state_to_timeline_color("unknown", Theme::Dark), "#606060");
+        assert_eq!(state_to_timeline_color("22.5", Theme::Dark), "#2196f3");
     }
 
     #[test]
@@ -2863,6 +7081,25 @@ mod tests {
         assert!(json.contains("°C"), "Expected unit: {json}");
     }
 
+    #[test]
+    fn test_fulfill_history_unavailable_is_gap() {
+        let mut engine = ShellEngine::new();
+        let data = r#"[[
+            {"entity_id": "sensor.temp", "state": "20.0", "last_changed": "2026-02-15T08:00:00Z"},
+            {"entity_id": "sensor.temp", "state": "unavailable", "last_changed": "2026-02-15T09:00:00Z"},
+            {"entity_id": "sensor.temp", "state": "unknown", "last_changed": "2026-02-15T09:30:00Z"},
+            {"entity_id": "sensor.temp", "state": "22.0", "last_changed": "2026-02-15T10:00:00Z"}
+        ]]"#;
+        let result = engine.fulfill_host_call("call_1", data);
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["type"], "sparkline", "Expected sparkline: {json}");
+        let points = json["points"].as_array().unwrap();
+        assert_eq!(points.len(), 3, "Expected one gap point: {json}");
+        assert!(points[1][1].is_null(), "Expected gap at the outage: {json}");
+        assert_eq!(json["min"], 20.0);
+        assert_eq!(json["current"], 22.0);
+    }
+
     #[test]
     fn test_fulfill_history_binary_timeline() {
         let mut engine = ShellEngine::new();
@@ -2891,6 +7128,7 @@ mod tests {
         let json = serde_json::to_string(&result).unwrap();
         assert!(json.contains(r#""type":"sparkline""#), "Expected sparkline: {json}");
         assert!(json.contains("sensor.temp"), "Expected entity_id: {json}");
+        assert!(json.contains(r#""baseline":21.0"#), "Expected mean baseline: {json}");
     }
 
     #[test]
@@ -3014,6 +7252,238 @@ mod tests {
         assert!(json.contains(r#""method":"get_states""#), "Expected get_states in: {json}");
     }
 
+    #[test]
+    fn test_template_produces_host_call() {
+        let mut engine = ShellEngine::new();
+        let result = engine.eval("template('{{ 40 + 2 }}')");
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""method":"render_template""#), "Expected render_template in: {json}");
+    }
+
+    #[test]
+    fn test_template_scalar_result_copyable() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("template('{{ 40 + 2 }}')")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let result = engine.fulfill_host_call(call_id, r#"{"result": "42"}"#);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"copyable""#), "Expected copyable: {json}");
+        assert!(json.contains(r#""content":"42""#), "Expected 42: {json}");
+        assert!(json.contains("template result"), "Expected label: {json}");
+    }
+
+    #[test]
+    fn test_template_json_result_structured() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("template('{{ x | to_json }}')")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"result": "{\"lights_on\": 3, \"doors_open\": 0}"}"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"key_value""#), "Expected key_value: {json}");
+        assert!(json.contains("lights_on"), "Expected parsed key: {json}");
+        assert!(!json.contains(r#""type":"copyable""#), "Should not dump raw JSON: {json}");
+    }
+
+    #[test]
+    fn test_template_error() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("template('{{ bad')")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let result = engine.fulfill_host_call(call_id, r#"{"error": "Template render failed: boom"}"#);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains(r#""type":"error""#), "Expected error: {json}");
+    }
+
+    #[test]
+    fn test_datetime_labels_timezones() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("get_datetime()")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"date": "2025-01-15", "time": "09:30:00", "timezone": "America/New_York", "ha_timezone": "Europe/London"}"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.contains("browser tz"), "Expected browser tz label: {json}");
+        assert!(json.contains("HA tz"), "Expected HA tz label: {json}");
+        assert!(json.contains(r#""type":"summary""#), "Expected tz warning: {json}");
+        assert!(json.contains("time zones differ"), "Expected tz warning: {json}");
+    }
+
+    #[test]
+    fn test_datetime_same_timezone_no_warning() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("get_datetime()")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"date": "2025-01-15", "timezone": "Europe/London", "ha_timezone": "Europe/London"}"#;
+        let result = engine.fulfill_host_call(call_id, data);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(!json.contains("time zones differ"), "Unexpected tz warning: {json}");
+    }
+
+    #[test]
+    fn test_host_call_chain_capped() {
+        let mut engine = ShellEngine::new();
+        let snippet = vec!["state('sensor.temp')"; MAX_HOST_CALLS_PER_SNIPPET as usize + 1].join("\n");
+        let mut spec = serde_json::to_value(engine.eval(&snippet)).unwrap();
+        let state_data = r#"{"entity_id": "sensor.temp", "state": "21", "attributes": {}}"#;
+
+        for _ in 0..MAX_HOST_CALLS_PER_SNIPPET {
+            assert_eq!(spec["type"], "host_call", "Expected host call: {spec}");
+            let call_id = spec["call_id"].as_str().unwrap().to_string();
+            spec = serde_json::to_value(engine.fulfill_host_call(&call_id, state_data)).unwrap();
+        }
+
+        let json = spec.to_string();
+        assert!(json.contains(r#""type":"error""#), "Expected cap error: {json}");
+        assert!(json.contains("Too many host calls"), "Expected cap message: {json}");
+    }
+
+    #[test]
+    fn test_now_cached_within_eval() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("a = now()\nb = now()\nprint('done')")).unwrap();
+        assert_eq!(spec["method"], "get_datetime");
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let data = r#"{"date": "2025-01-15", "time": "09:30:00", "iso": "2025-01-15T09:30:00Z"}"#;
+        let result = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_ne!(result["type"], "host_call", "Second now() should reuse the cache: {result}");
+        assert!(result.to_string().contains("done"), "Expected completion: {result}");
+
+        // A new eval fetches the time again.
+        let spec = serde_json::to_value(engine.eval("now()")).unwrap();
+        assert_eq!(spec["method"], "get_datetime");
+    }
+
+    #[test]
+    fn test_functions_lists_external_and_local() {
+        let engine = ShellEngine::new();
+        let functions = engine.functions();
+        let names: Vec<&str> = functions
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| f["name"].as_str().unwrap())
+            .collect();
+        for expected in ["state", "plot_series", "show", "ago", "now"] {
+            assert!(names.contains(&expected), "Missing {expected}: {names:?}");
+        }
+        let state = functions.as_array().unwrap().iter().find(|f| f["name"] == "state").unwrap();
+        assert_eq!(state["signature"], "state(entity_id)");
+    }
+
+    #[test]
+    fn test_complete_magic_commands() {
+        let engine = ShellEngine::new();
+        let candidates = engine.complete("%l");
+        assert!(candidates.contains(&"%ls".to_string()), "{candidates:?}");
+        assert!(!candidates.contains(&"%get".to_string()));
+        assert_eq!(engine.complete(":i"), vec![":info"]);
+    }
+
+    #[test]
+    fn test_complete_domains_and_defined_names() {
+        let mut engine = ShellEngine::new();
+        assert!(engine.complete("sen").contains(&"sensor".to_string()));
+
+        engine.eval("sensors_on = 3");
+        let candidates = engine.complete("sen");
+        assert!(candidates.contains(&"sensor".to_string()), "{candidates:?}");
+        assert!(candidates.contains(&"sensors_on".to_string()), "{candidates:?}");
+    }
+
+    #[test]
+    fn test_failed_snippets_do_not_define_names() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("broken = 1 / 0")).unwrap();
+        assert_eq!(spec["type"], "error", "{spec}");
+
+        let spec = serde_json::to_value(engine.eval("fetched = state('sensor.temp')\nfetched.nope")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let state = r#"{"entity_id": "sensor.temp", "state": "21", "attributes": {}}"#;
+        let spec = serde_json::to_value(engine.fulfill_host_call(call_id, state)).unwrap();
+        assert_eq!(spec["type"], "error", "{spec}");
+
+        assert!(engine.session.defined_names().is_empty(), "{:?}", engine.session.defined_names());
+    }
+
+    #[test]
+    fn test_defined_names() {
+        let code = "x = 1\na, b = 2, 3\ny: int = 4\nx == 1\nx += 1\nd[\"k\"] = 5\nprint(sep=\"\")\ndef f(n):\n    inner = n\nclass C:\n    pass\nfor i in range(3):\n    pass";
+        assert_eq!(defined_names(code), vec!["x", "a", "b", "y", "f", "C", "i"]);
+    }
+
+    #[test]
+    fn test_ago_clock_time_uses_cached_now() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("t = now()\nh = ago(\"08:00\")\nprint(h)")).unwrap();
+        assert_eq!(spec["method"], "get_datetime");
+        let call_id = spec["call_id"].as_str().unwrap();
+        let data = r#"{"date": "2025-01-15", "time": "10:00:00"}"#;
+        let result = serde_json::to_value(engine.fulfill_host_call(call_id, data)).unwrap();
+        assert_eq!(result["type"], "text", "{result}");
+        assert_eq!(result["content"].as_str().unwrap().trim(), "2");
+    }
+
+    #[test]
+    fn test_states_mixed_domains_grouped_tables() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("states()")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+
+        let mut entities = Vec::new();
+        for i in 0..6 {
+            entities.push(serde_json::json!({ "entity_id": format!("sensor.s{i}"), "state": "1", "attributes": {} }));
+            entities.push(serde_json::json!({ "entity_id": format!("light.l{i}"), "state": "on", "attributes": {} }));
+        }
+        let data = serde_json::Value::Array(entities).to_string();
+        let json = serde_json::to_value(engine.fulfill_host_call(call_id, &data)).unwrap();
+        assert_eq!(json["type"], "vstack", "{json}");
+        let tables: Vec<&serde_json::Value> = json["children"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|c| c["type"] == "table")
+            .collect();
+        assert_eq!(tables.len(), 2, "Expected one table per domain: {json}");
+        assert!(tables[0]["rows"].as_array().unwrap().iter().all(|r| r[1].as_str().unwrap().starts_with("light.")));
+        assert!(tables[1]["rows"].as_array().unwrap().iter().all(|r| r[1].as_str().unwrap().starts_with("sensor.")));
+    }
+
+    #[test]
+    fn test_state_missing_entity_not_strict() {
+        let mut engine = ShellEngine::new();
+        let spec = serde_json::to_value(engine.eval("e = state(\"sensor.nope\")")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        let result = engine.fulfill_host_call(call_id, r#"{"error": "Entity not found: sensor.nope"}"#);
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(!json.contains(r#""type":"error""#), "Unexpected error: {json}");
+
+        engine.eval("e");
+        let last = monty_runtime::monty_obj_to_json(engine.session.last_result().unwrap());
+        assert_eq!(last["exists"], false, "{last}");
+        assert_eq!(last["state"], "unknown");
+        assert_eq!(last["entity_id"], "sensor.nope");
+    }
+
+    #[test]
+    fn test_state_missing_entity_strict() {
+        let mut engine = ShellEngine::new();
+        engine.eval("%strict on");
+        let spec = serde_json::to_value(engine.eval("e = state(\"sensor.nope\")")).unwrap();
+        let call_id = spec["call_id"].as_str().unwrap();
+        engine.fulfill_host_call(call_id, r#"{"error": "Entity not found: sensor.nope"}"#);
+
+        engine.eval("e");
+        let last = monty_runtime::monty_obj_to_json(engine.session.last_result().unwrap());
+        assert_eq!(last, serde_json::json!({ "error": "Entity not found: sensor.nope" }));
+    }
+
     // ── EntityState dataclass integration tests ──────────────────────
 
     #[test]
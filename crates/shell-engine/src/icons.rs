@@ -3,22 +3,57 @@
 /// Uses Nerd Font glyphs — requires a Nerd Font (e.g. Iosevka Nerd Font) to render.
 
 /// Get a Nerd Font icon for an entity based on its domain, device_class, and state.
-pub fn entity_icon(entity_id: &str, device_class: Option<&str>, state: Option<&str>) -> &'static str {
+///
+/// `position` is a cover's `current_position` attribute (0 = closed,
+/// 100 = open), so a half-open cover gets its own glyph.
+pub fn entity_icon(
+    entity_id: &str,
+    device_class: Option<&str>,
+    state: Option<&str>,
+    position: Option<f64>,
+) -> &'static str {
     let domain = entity_id.split('.').next().unwrap_or("");
     let st = state.unwrap_or("");
 
+    let opening = position.map(Opening::from_position);
+
     // Try device_class-specific icon first, then fall back to domain.
     if let Some(dc) = device_class {
-        if let Some(icon) = device_class_icon(domain, dc, st) {
+        if let Some(icon) = device_class_icon(domain, dc, st, opening) {
             return icon;
         }
     }
 
-    domain_icon(domain, st)
+    domain_icon(domain, st, opening)
+}
+
+/// How far open a cover is, from its `current_position`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Opening {
+    Closed,
+    Partial,
+    Open,
+}
+
+impl Opening {
+    fn from_position(position: f64) -> Self {
+        if position <= 0.0 {
+            Self::Closed
+        } else if position >= 100.0 {
+            Self::Open
+        } else {
+            Self::Partial
+        }
+    }
 }
 
 /// Icon based on device_class (more specific).
-fn device_class_icon(domain: &str, device_class: &str, state: &str) -> Option<&'static str> {
+fn device_class_icon(
+    domain: &str,
+    device_class: &str,
+    state: &str,
+    opening: Option<Opening>,
+) -> Option<&'static str> {
     match (domain, device_class) {
         // Binary sensor device classes
         ("binary_sensor", "door") => Some(if state == "on" { "󰷛" } else { "󰷚" }),       // door open/closed
@@ -58,16 +93,24 @@ fn device_class_icon(domain: &str, device_class: &str, state: &str) -> Option<&'
         ("sensor", "monetary") => Some("󰗹"),
 
         // Cover device classes
-        ("cover", "garage") => Some(if state == "open" { "󰿘" } else { "󰿗" }),
-        ("cover", "blind") | ("cover", "shade") => Some("󰦗"),
-        ("cover", "curtain") => Some("󰦗"),
+        ("cover", "garage") => Some(match opening {
+            Some(Opening::Open) => "󰿘",
+            Some(Opening::Partial) => "󱋔", // garage part-open
+            Some(Opening::Closed) => "󰿗",
+            None => if state == "open" { "󰿘" } else { "󰿗" },
+        }),
+        ("cover", "blind") | ("cover", "shade") | ("cover", "curtain") => Some(match opening {
+            Some(Opening::Open) => "󱀑", // blinds open
+            Some(Opening::Partial) => "󰂫", // blinds part-way
+            Some(Opening::Closed) | None => "󰦗",
+        }),
 
         _ => None,
     }
 }
 
 /// Icon based on domain (fallback).
-fn domain_icon(domain: &str, state: &str) -> &'static str {
+fn domain_icon(domain: &str, state: &str, opening: Option<Opening>) -> &'static str {
     match domain {
         "light" => if state == "on" { "󰌵" } else { "󰌶" },           // lightbulb on/off
         "switch" => if state == "on" { "󰔡" } else { "󰔢" },         // toggle on/off
@@ -75,7 +118,12 @@ fn domain_icon(domain: &str, state: &str) -> &'static str {
         "sensor" => "󰗠",                                              // gauge
         "climate" => "󰃮",                                             // thermostat
         "fan" => "󰈐",                                                 // fan
-        "cover" => "󰦗",                                               // blinds
+        "cover" => match opening {
+            Some(Opening::Open) => "󱄜",                              // shutter open
+            Some(Opening::Partial) => "󰂫",                           // blinds part-way
+            Some(Opening::Closed) => "󱄛",                            // shutter closed
+            None => "󰦗",                                              // blinds
+        },
         "lock" => if state == "locked" { "󰍁" } else { "󰌿" },       // lock/unlock
         "camera" => "󰄀",                                              // camera
         "media_player" => "󰕾",                                        // speaker
@@ -162,52 +210,73 @@ mod tests {
 
     #[test]
     fn test_sensor_temperature_icon() {
-        let icon = entity_icon("sensor.living_room_temp", Some("temperature"), Some("22.5"));
+        let icon = entity_icon("sensor.living_room_temp", Some("temperature"), Some("22.5"), None);
         assert_eq!(icon, "󰔏");
     }
 
     #[test]
     fn test_binary_sensor_door_on() {
-        let icon = entity_icon("binary_sensor.front_door", Some("door"), Some("on"));
+        let icon = entity_icon("binary_sensor.front_door", Some("door"), Some("on"), None);
         assert_eq!(icon, "󰷛"); // open door
     }
 
     #[test]
     fn test_binary_sensor_door_off() {
-        let icon = entity_icon("binary_sensor.front_door", Some("door"), Some("off"));
+        let icon = entity_icon("binary_sensor.front_door", Some("door"), Some("off"), None);
         assert_eq!(icon, "󰷚"); // closed door
     }
 
     #[test]
     fn test_light_on() {
-        let icon = entity_icon("light.living_room", None, Some("on"));
+        let icon = entity_icon("light.living_room", None, Some("on"), None);
         assert_eq!(icon, "󰌵");
     }
 
     #[test]
     fn test_light_off() {
-        let icon = entity_icon("light.living_room", None, Some("off"));
+        let icon = entity_icon("light.living_room", None, Some("off"), None);
         assert_eq!(icon, "󰌶");
     }
 
     #[test]
     fn test_unknown_domain() {
-        let icon = entity_icon("foobar.something", None, None);
+        let icon = entity_icon("foobar.something", None, None, None);
         assert_eq!(icon, "󰘦");
     }
 
     #[test]
     fn test_binary_sensor_fallback() {
-        let icon = entity_icon("binary_sensor.something", None, Some("on"));
+        let icon = entity_icon("binary_sensor.something", None, Some("on"), None);
         assert_eq!(icon, "󰐾");
     }
 
     #[test]
     fn test_switch_on() {
-        let icon = entity_icon("switch.pump", None, Some("on"));
+        let icon = entity_icon("switch.pump", None, Some("on"), None);
         assert_eq!(icon, "󰔡");
     }
 
+    #[test]
+    fn test_cover_position_icons() {
+        let at = |position| entity_icon("cover.office", Some("shutter"), Some("open"), Some(position));
+        let (closed, partial, open) = (at(0.0), at(50.0), at(100.0));
+        assert_ne!(partial, closed);
+        assert_ne!(partial, open);
+        assert_ne!(open, closed);
+        // Without a position the device class / domain glyph still applies.
+        assert_eq!(entity_icon("cover.garage", Some("garage"), Some("open"), None), "󰿘");
+    }
+
+    #[test]
+    fn test_garage_cover_keeps_garage_icon_with_position() {
+        let at = |position| entity_icon("cover.garage", Some("garage"), Some("open"), Some(position));
+        assert_eq!(at(100.0), "󰿘");
+        assert_eq!(at(0.0), "󰿗");
+        assert_ne!(at(50.0), at(100.0));
+        assert_ne!(at(50.0), at(0.0));
+        assert_ne!(at(50.0), entity_icon("cover.office", None, Some("open"), Some(50.0)));
+    }
+
     #[test]
     fn test_state_indicator_on() {
         assert_eq!(state_indicator("on"), "●");
@@ -230,25 +299,25 @@ mod tests {
 
     #[test]
     fn test_occupancy_on() {
-        let icon = entity_icon("binary_sensor.lr_occupied", Some("occupancy"), Some("on"));
+        let icon = entity_icon("binary_sensor.lr_occupied", Some("occupancy"), Some("on"), None);
         assert_eq!(icon, "󱁝");
     }
 
     #[test]
     fn test_motion_on() {
-        let icon = entity_icon("binary_sensor.hallway_motion", Some("motion"), Some("on"));
+        let icon = entity_icon("binary_sensor.hallway_motion", Some("motion"), Some("on"), None);
         assert_eq!(icon, "󰒲");
     }
 
     #[test]
     fn test_person_icon() {
-        let icon = entity_icon("person.robin", None, Some("home"));
+        let icon = entity_icon("person.robin", None, Some("home"), None);
         assert_eq!(icon, "󰋑");
     }
 
     #[test]
     fn test_automation_icon() {
-        let icon = entity_icon("automation.lights_off", None, Some("on"));
+        let icon = entity_icon("automation.lights_off", None, Some("on"), None);
         assert_eq!(icon, "󰁪");
     }
 
@@ -4,6 +4,7 @@ mod magic;
 mod monty_runtime;
 mod render;
 mod session;
+mod yaml;
 
 pub use engine::ShellEngine;
 pub use render::RenderSpec;
@@ -51,6 +52,17 @@ impl WasmShellEngine {
         })
     }
 
+    /// Feed one chunk of a host call response that arrives in pieces (a
+    /// large state list). Returns the table so far; the last chunk goes
+    /// through `fulfill_host_call`.
+    #[wasm_bindgen]
+    pub fn fulfill_host_call_chunk(&mut self, call_id: &str, partial: &str) -> String {
+        let spec = self.inner.fulfill_host_call_chunk(call_id, partial);
+        serde_json::to_string(&spec).unwrap_or_else(|e| {
+            serde_json::to_string(&RenderSpec::error(format!("Serialization error: {e}"))).unwrap()
+        })
+    }
+
     /// Get the current prompt string (e.g. ">>> " or "... ").
     #[wasm_bindgen]
     pub fn prompt(&self) -> String {
@@ -62,4 +74,18 @@ impl WasmShellEngine {
     pub fn history(&self) -> String {
         serde_json::to_string(&self.inner.session.history()).unwrap()
     }
+
+    /// Get the available Python functions as a JSON array of
+    /// `{name, signature}` objects, for autocomplete.
+    #[wasm_bindgen]
+    pub fn functions(&self) -> String {
+        serde_json::to_string(&self.inner.functions()).unwrap()
+    }
+
+    /// Get completion candidates for `prefix` as a JSON array of strings —
+    /// magic commands for `%`/`:` prefixes, else domains and defined names.
+    #[wasm_bindgen]
+    pub fn complete(&self, prefix: &str) -> String {
+        serde_json::to_string(&self.inner.complete(prefix)).unwrap()
+    }
 }
@@ -1,28 +1,111 @@
 use crate::render::RenderSpec;
+use crate::session::{HistoryView, Theme};
 
 /// A parsed magic command.
 #[derive(Debug, PartialEq)]
 pub enum MagicCommand {
-    /// %ls [domain] — list entities
-    Ls(Option<String>),
+    /// %ls [domain] [--count] [--chart] [--json] [--all] [--attrs key] [--spark] — list entities
+    Ls {
+        domain: Option<String>,
+        /// Render only the number of matching entities.
+        count: bool,
+        /// Render a pie chart of entity counts per domain instead of the table.
+        chart: bool,
+        /// Render the raw states list as copyable JSON instead of the table.
+        json: bool,
+        /// With no domain, list every entity instead of a per-domain overview.
+        all: bool,
+        /// Attribute to show as an extra table column.
+        attr: Option<String>,
+        /// Add a column with a mini sparkline of each entity's recent history.
+        spark: bool,
+    },
 
-    /// %get entity_id — show entity state
-    Get(String),
+    /// %get entity_id [attribute] [--badge] [--expand] — show entity state
+    Get {
+        entity_id: String,
+        /// Render a compact state badge instead of the full card.
+        badge: bool,
+        /// Show only this attribute (dotted path, e.g. `brightness`).
+        attribute: Option<String>,
+        /// For groups, show a table of the member entities.
+        expand: bool,
+        /// Also list the services of the entity's domain under the card.
+        services: bool,
+    },
 
     /// %find pattern — glob search entities
     Find(String),
 
-    /// %hist entity_id [-h hours] — show history
+    /// %hist entity_id [-h hours] [--as sparkline|timeline|table] [--compare 1d]
+    /// — show history
     Hist {
         entity_id: String,
         hours: Option<u32>,
+        render_as: Option<HistoryView>,
+        /// Overlay the window this many hours earlier (`--compare 1d`).
+        compare: Option<u32>,
     },
 
     /// %attrs entity_id — show all attributes
     Attrs(String),
 
-    /// %diff entity_a entity_b — compare two entities
-    Diff(String, String),
+    /// %inspect entity_id — state card, attributes and 6h history together
+    Inspect(String),
+
+    /// %snapshot entity_id — save the current state for `%diff <id> @snapshot`
+    Snapshot(String),
+
+    /// %diff entity_a entity_b [entity_c ...] [--attrs-only] [--changed-only]
+    /// — compare two or more entities
+    Diff {
+        /// At least two entity IDs. The second may be `@snapshot` for the
+        /// first one's saved snapshot.
+        entity_ids: Vec<String>,
+        /// Skip the state row and compare attributes only.
+        attrs_only: bool,
+        /// Show only rows where the two values differ.
+        changed_only: bool,
+    },
+
+    /// %services [domain] [~keyword] — list or search services
+    Services {
+        domain: Option<String>,
+        query: Option<String>,
+    },
+
+    /// %refresh — re-fetch the last viewed entity
+    Refresh,
+
+    /// %caps — ask the host which host methods it supports
+    Caps,
+
+    /// %copy — show the last result as copyable JSON
+    Copy,
+
+    /// %strict [on|off] — missing entities error instead of `exists=False` (toggles)
+    Strict(Option<bool>),
+
+    /// %presence — who's home, one badge per person
+    Presence,
+
+    /// %count — how many entities in the last state list are in each state
+    StateCounts,
+
+    /// %yaml [service] — show the last result (or last service call) as YAML
+    Yaml {
+        /// Export the last `call_service` as an HA action instead.
+        service: bool,
+    },
+
+    /// %plot — chart the last result
+    Plot,
+
+    /// %raw [on|off] — toggle raw JSON output for Python host calls
+    Raw(Option<bool>),
+
+    /// %theme [dark|light] — state colours for the card background
+    Theme(Option<Theme>),
 
     /// %bundle name — run a named bundle
     Bundle(String),
@@ -30,16 +113,37 @@ pub enum MagicCommand {
     /// %fmt format — set output format
     Fmt(String),
 
-    /// %ask question — ask the AI assistant (via HA Conversation)
-    Ask(String),
+    /// %ask [@agent] question — ask the AI assistant (via HA Conversation)
+    Ask {
+        question: String,
+        /// Conversation agent to use instead of the host's default.
+        agent: Option<String>,
+    },
+
+    /// %py code — run code as Python, skipping magic and auto-resolve
+    /// (for a variable that shares a domain's name, such as `light`)
+    Py(String),
 
     /// :help — show help
     Help,
 
-    /// :clear — clear the output
-    Clear,
+    /// %examples — runnable example commands
+    Examples,
+
+    /// :clear [all|last] — clear every output block, or just the last one
+    Clear { last: bool },
+
+    /// :info — show session state
+    Info,
 }
 
+/// Every magic command and `:` command, for autocomplete.
+pub const MAGIC_COMMANDS: &[&str] = &[
+    "%ls", "%get", "%find", "%hist", "%attrs", "%inspect", "%snapshot", "%diff", "%services",
+    "%refresh", "%count", "%presence", "%copy", "%yaml", "%plot", "%raw", "%theme", "%strict", "%caps", "%bundle", "%fmt", "%ask", "%py",
+    "%examples", ":help", ":clear", ":info",
+];
+
 /// Try to parse a line as a magic command.
 /// Returns None if the line is not a magic/command.
 pub fn parse_magic(input: &str) -> Option<MagicCommand> {
@@ -49,27 +153,80 @@ pub fn parse_magic(input: &str) -> Option<MagicCommand> {
         return Some(MagicCommand::Help);
     }
 
-    if trimmed == ":clear" || trimmed == ":cls" {
-        return Some(MagicCommand::Clear);
+    let mut words = trimmed.split_whitespace();
+    if matches!(words.next(), Some(":clear" | ":cls")) {
+        return match (words.next(), words.next()) {
+            (None | Some("all"), None) => Some(MagicCommand::Clear { last: false }),
+            (Some("last"), None) => Some(MagicCommand::Clear { last: true }),
+            _ => None,
+        };
     }
 
-    if !trimmed.starts_with('%') {
+    if trimmed == ":info" {
+        return Some(MagicCommand::Info);
+    }
+
+    // Only `%word` is a magic — `% 2` or `%(x)` fall through to Python.
+    let body = trimmed.strip_prefix('%')?;
+    if !body.starts_with(|c: char| c.is_ascii_alphabetic()) {
         return None;
     }
 
-    let parts: Vec<&str> = trimmed[1..].split_whitespace().collect();
+    let parts: Vec<&str> = body.split_whitespace().collect();
     if parts.is_empty() {
         return None;
     }
 
     match parts[0] {
         "ls" => {
-            let domain = parts.get(1).map(|s| s.to_string());
-            Some(MagicCommand::Ls(domain))
+            let attr_pos = parts.iter().position(|p| *p == "--attrs");
+            let attr = match attr_pos {
+                Some(i) => Some(parts.get(i + 1)?.to_string()),
+                None => None,
+            };
+            let domain = parts
+                .iter()
+                .enumerate()
+                .skip(1)
+                .find(|(i, p)| !p.starts_with("--") && attr_pos.map(|a| a + 1) != Some(*i))
+                .map(|(_, s)| s.to_string());
+            let count = parts[1..].contains(&"--count");
+            let chart = parts[1..]
+                .iter()
+                .any(|p| *p == "--chart" || *p == "--domain-summary");
+            let json = parts[1..].contains(&"--json");
+            let all = parts[1..].contains(&"--all");
+            let spark = parts[1..].contains(&"--spark");
+            Some(MagicCommand::Ls {
+                domain,
+                count,
+                chart,
+                json,
+                all,
+                attr,
+                spark,
+            })
         }
         "get" => {
-            let entity_id = parts.get(1)?;
-            Some(MagicCommand::Get(entity_id.to_string()))
+            let entity_id = parts.get(1)?.to_string();
+            // `%get <id> --attrs` is an alias for `%attrs <id>`.
+            if parts[2..].contains(&"--attrs") {
+                return Some(MagicCommand::Attrs(entity_id));
+            }
+            let badge = parts[2..].contains(&"--badge");
+            let expand = parts[2..].contains(&"--expand");
+            let services = parts[2..].contains(&"--services");
+            let attribute = parts[2..]
+                .iter()
+                .find(|p| !p.starts_with("--"))
+                .map(|p| p.to_string());
+            Some(MagicCommand::Get {
+                entity_id,
+                badge,
+                attribute,
+                expand,
+                services,
+            })
         }
         "find" => {
             let pattern = parts.get(1)?;
@@ -78,13 +235,64 @@ pub fn parse_magic(input: &str) -> Option<MagicCommand> {
         "hist" => {
             let entity_id = parts.get(1)?.to_string();
             let mut hours = None;
-            if let Some(&flag) = parts.get(2) {
-                if flag == "-h" {
-                    hours = parts.get(3).and_then(|h| h.parse().ok());
+            let mut render_as = None;
+            let mut compare = None;
+            let mut flags = parts[2..].iter();
+            while let Some(&flag) = flags.next() {
+                match flag {
+                    "-h" => hours = flags.next().and_then(|h| h.parse().ok()),
+                    "--as" => render_as = Some(HistoryView::parse(flags.next()?)?),
+                    "--compare" => compare = Some(parse_window_hours(flags.next()?)?),
+                    _ => {}
+                }
+            }
+            Some(MagicCommand::Hist {
+                entity_id,
+                hours,
+                render_as,
+                compare,
+            })
+        }
+        "services" => {
+            let mut domain = None;
+            let mut query = None;
+            for part in &parts[1..] {
+                match part.strip_prefix('~') {
+                    Some(q) if !q.is_empty() => query = Some(q.to_string()),
+                    Some(_) => {}
+                    None => domain = Some(part.to_string()),
                 }
             }
-            Some(MagicCommand::Hist { entity_id, hours })
+            Some(MagicCommand::Services { domain, query })
         }
+        "refresh" => Some(MagicCommand::Refresh),
+        "caps" => Some(MagicCommand::Caps),
+        "copy" => Some(MagicCommand::Copy),
+        "count" => Some(MagicCommand::StateCounts),
+        "presence" => Some(MagicCommand::Presence),
+        "strict" => match parts.get(1).copied() {
+            Some("on") => Some(MagicCommand::Strict(Some(true))),
+            Some("off") => Some(MagicCommand::Strict(Some(false))),
+            Some(_) => None,
+            None => Some(MagicCommand::Strict(None)),
+        },
+        "yaml" => match parts.get(1).copied() {
+            Some("service") => Some(MagicCommand::Yaml { service: true }),
+            Some(_) => None,
+            None => Some(MagicCommand::Yaml { service: false }),
+        },
+        "plot" => Some(MagicCommand::Plot),
+        "raw" => match parts.get(1).copied() {
+            Some("on") => Some(MagicCommand::Raw(Some(true))),
+            Some("off") => Some(MagicCommand::Raw(Some(false))),
+            Some(_) => None,
+            None => Some(MagicCommand::Raw(None)),
+        },
+        "theme" => match parts.get(1) {
+            Some(name) => Theme::parse(name).map(|t| MagicCommand::Theme(Some(t))),
+            None => Some(MagicCommand::Theme(None)),
+        },
+        "examples" => Some(MagicCommand::Examples),
         "bundle" => {
             let name = parts.get(1)?;
             Some(MagicCommand::Bundle(name.to_string()))
@@ -97,24 +305,93 @@ pub fn parse_magic(input: &str) -> Option<MagicCommand> {
             let entity_id = parts.get(1)?;
             Some(MagicCommand::Attrs(entity_id.to_string()))
         }
+        "inspect" => {
+            let entity_id = parts.get(1)?;
+            Some(MagicCommand::Inspect(entity_id.to_string()))
+        }
+        "snapshot" => {
+            let entity_id = parts.get(1)?;
+            Some(MagicCommand::Snapshot(entity_id.to_string()))
+        }
         "diff" | "compare" => {
-            let entity_a = parts.get(1)?.to_string();
-            let entity_b = parts.get(2)?.to_string();
-            Some(MagicCommand::Diff(entity_a, entity_b))
+            let entity_ids: Vec<String> = parts[1..]
+                .iter()
+                .filter(|p| !p.starts_with("--"))
+                .map(|p| p.to_string())
+                .collect();
+            if entity_ids.len() < 2 {
+                return None;
+            }
+            Some(MagicCommand::Diff {
+                entity_ids,
+                attrs_only: parts[1..].contains(&"--attrs-only"),
+                changed_only: parts[1..].contains(&"--changed-only"),
+            })
+        }
+        "py" => {
+            let (_, code) = trimmed.split_once(char::is_whitespace)?;
+            let code = code.trim();
+            (!code.is_empty()).then(|| MagicCommand::Py(code.to_string()))
         }
         "ask" | "assistant" => {
-            // Everything after %ask is the question.
+            // Everything after %ask is the question, less an optional
+            // leading `@agent`.
             let question = trimmed.splitn(2, char::is_whitespace).nth(1)?;
-            let question = question.trim();
+            let mut question = question.trim();
+            let mut agent = None;
+            if let Some(rest) = question.strip_prefix('@') {
+                let (name, rest) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                if !name.is_empty() {
+                    agent = Some(name.to_string());
+                }
+                question = rest.trim();
+            }
             if question.is_empty() {
                 return None;
             }
-            Some(MagicCommand::Ask(question.to_string()))
+            Some(MagicCommand::Ask {
+                question: question.to_string(),
+                agent,
+            })
         }
         _ => None,
     }
 }
 
+/// A window like `1d`, `12h` or a bare number of hours, in hours.
+fn parse_window_hours(window: &str) -> Option<u32> {
+    let hours = match window.strip_suffix('d') {
+        Some(days) => days.parse::<u32>().ok()?.checked_mul(24)?,
+        None => window.strip_suffix('h').unwrap_or(window).parse().ok()?,
+    };
+    (hours > 0).then_some(hours)
+}
+
+/// Example commands listed by `%examples`, one per snippet — magic
+/// commands and Python alike, so each can be run straight from the list.
+pub const EXAMPLES: &[&str] = &[
+    "%ls light",
+    "%get sun.sun",
+    "%find *temperature*",
+    "%hist sensor.outdoor_temperature -h 24",
+    "%diff light.kitchen light.living_room",
+    "%services light",
+    "state(\"sun.sun\")",
+    "states(\"light\")",
+    "history([\"sensor.indoor_temperature\", \"sensor.outdoor_temperature\"], 12)",
+    "traces(state=\"error\")",
+    "plot_bar([\"mon\", \"tue\", \"wed\"], [3, 5, 2], \"Runs\")",
+    "template(\"{{ states.light | selectattr('state', 'eq', 'on') | list | count }} lights on\")",
+];
+
+/// `%examples` — the [`EXAMPLES`] as runnable snippets.
+pub fn examples() -> RenderSpec {
+    RenderSpec::snippets(
+        "Example commands — insert one to edit it, or run it as-is.",
+        EXAMPLES.iter().map(|e| e.to_string()).collect(),
+    )
+}
+
 /// Generate help text.
 pub fn help_text() -> RenderSpec {
     RenderSpec::help(
@@ -122,18 +399,55 @@ pub fn help_text() -> RenderSpec {
 
 Commands:
   :help              Show this help message
-  :clear             Clear the output
+  :clear [all|last]  Clear the output (or just the last block)
+  :info              Show session state (history, format, theme, names, last result)
 
 Magic Commands:
-  %ls [domain]       List entities (optionally filter by domain)
-  %get <entity_id>   Show entity state
+  %ls [domain]       List entities (no domain: per-domain overview when large)
+    --all            List every entity, however many there are
+    --attrs <key>    Add a column with that attribute (e.g. battery_level)
+    --spark          Add a mini sparkline of each numeric entity's last 6h
+    --count          Show only how many entities match
+    --chart          Pie chart of entity counts per domain
+    --json           Raw states as copyable JSON (for export)
+  %get <entity_id>   Show entity state (a glob like light.* shows a table;
+                     a name without a domain searches like %find)
+    <attribute>      Just one attribute (e.g. %get light.x brightness)
+    --badge          Compact state badge instead of the full card
+    --expand         For groups, a table of the member entities
+    --services       The services of the entity's domain, below the card
+    --attrs          All attributes (same as %attrs)
   %find <pattern>    Search entities by glob pattern
   %hist <id> [-h N]  Show entity history (last N hours)
+    --compare 1d     Overlay the last day on the day before
+                     (with -h N: the last N hours and the same hours a day earlier)
+    --as <view>      Force sparkline, timeline or table
   %attrs <id>        Show all entity attributes
-  %diff <id1> <id2>  Compare two entities side-by-side
+  %inspect <id>      State card, attributes and 6h history in one panel
+  %snapshot <id>     Save the entity's current state
+  %diff <id1> <id2>  Compare entities side-by-side (add more IDs for more columns)
+    <id> @snapshot   Compare live state against the saved snapshot
+    --attrs-only     Skip the state row
+    --changed-only   Only rows where the values differ
+  %services [d ~kw]  List services, optionally by domain or keyword
+  %refresh           Re-fetch the last %get/%hist/%attrs entity
+  %count             Count the last state list by state (on: 3, off: 5)
+  %presence          Who's home — a home/away badge per person
+  %copy              Copy the last result as JSON
+  %yaml [service]    Last result (or last call_service) as HA YAML
+  %plot              Chart the last result (numbers, (x, y) pairs, {name: n})
+  %raw [on|off]      Show history/statistics/etc. as raw JSON (toggles)
+  %theme [dark|light] Timeline colours for a dark or light card background
+  %strict [on|off]   state() on a missing entity errors (default: exists=False)
+  %caps              Host methods this HA setup supports (checked from then on)
   %bundle <name>     Run a named bundle
-  %fmt <format>      Set output format (table, json, text)
+  %fmt <format>      Set output format (table, json)
+    json             history(), traces() etc. as raw JSON until %fmt table
+    grouped          Thousands separators for big numbers (ungrouped: off)
   %ask <question>    Ask the AI assistant (via HA Conversation)
+    @agent           Use this conversation agent (e.g. %ask @claude why?)
+  %py <code>         Run as Python even if it looks like an entity or domain
+  %examples          Example commands, each with insert and run buttons
 
 Auto-resolve:
   sensor.temp        → %get sensor.temp
@@ -147,24 +461,27 @@ Python API — State & Entities:
 
 Python API — History & Diagnostics:
   history(id, [hours]) Get entity history (default 6h)
+  history([ids], [hours]) Several entities on one chart
   statistics(id, [hours], [period])  Get long-term statistics
   events(id, [hours])  Get calendar events (default 14 days forward)
   logbook([id], [hours])  Get logbook entries
   traces([automation_id]) Get automation traces (all or specific)
+  traces(state="error") Only runs in that state
   error_log()          Fetch the HA error log
   check_config()       Validate HA configuration
 
 Python API — Rooms & Services:
   room(name)           Get all entities in an area/room
   rooms()              List all areas/rooms
-  services([domain])   List available services
+  services([d], [kw])  List available services (kw filters by keyword)
   call_service(d,s,{}) Call a HA service (requires confirmation)
 
 Python API — Utilities:
-  show(value)          Pretty-print a value
+  show(value, [label]) Pretty-print a value, captioned by label (returns it)
   now()                Get current date/time
   ago(spec)            Relative time (e.g. ago("6h"), ago("2d"))
-  template(tpl)        Render a Jinja2 template
+                       ago("08:00") = hours since 8am (after now())
+  template(tpl, [vars]) Render a Jinja2 template (vars: dict of variables)
 
 Python API — Charts (ECharts):
   plot_line(labels, values, [title])  Line chart
@@ -174,6 +491,11 @@ Python API — Charts (ECharts):
   Multi-series: plot_line(labels, {"A": [...], "B": [...]}, title)
   Series data:  plot_series([(x,y),...]) or {"A": [(x,y),...], ...}
   Time axis auto-detected from epoch-ms x values.
+  Subplots:     plot_series({...}, title, subplots=True) stacks each series
+  Second axis:  plot_series({...}, title, axes={"B": "right"})
+  Donut:        plot_pie(data, title, donut=True) renders a ring
+  Pie labels:   plot_pie(data, labels="percent"|"value"|"both")
+  Running sum:  plot_line/plot_bar/plot_series(..., cumsum=True)
 
 Card Config:
   mode: embedded       Normal inline card (default)
@@ -200,10 +522,148 @@ mod tests {
 
     #[test]
     fn test_parse_ls() {
-        assert_eq!(parse_magic("%ls"), Some(MagicCommand::Ls(None)));
+        assert_eq!(
+            parse_magic("%ls"),
+            Some(MagicCommand::Ls {
+                domain: None,
+                count: false,
+                chart: false,
+                json: false,
+                all: false,
+                attr: None,
+                spark: false,
+            })
+        );
         assert_eq!(
             parse_magic("%ls binary_sensor"),
-            Some(MagicCommand::Ls(Some("binary_sensor".into())))
+            Some(MagicCommand::Ls {
+                domain: Some("binary_sensor".into()),
+                count: false,
+                chart: false,
+                json: false,
+                all: false,
+                attr: None,
+                spark: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%ls light --count"),
+            Some(MagicCommand::Ls {
+                domain: Some("light".into()),
+                count: true,
+                chart: false,
+                json: false,
+                all: false,
+                attr: None,
+                spark: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%ls --count"),
+            Some(MagicCommand::Ls {
+                domain: None,
+                count: true,
+                chart: false,
+                json: false,
+                all: false,
+                attr: None,
+                spark: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_ls_chart() {
+        let expected = Some(MagicCommand::Ls {
+            domain: None,
+            count: false,
+            chart: true,
+            json: false,
+            all: false,
+            attr: None,
+            spark: false,
+        });
+        assert_eq!(parse_magic("%ls --chart"), expected);
+        assert_eq!(parse_magic("%ls --domain-summary"), expected);
+        assert_eq!(
+            parse_magic("%ls --all"),
+            Some(MagicCommand::Ls {
+                domain: None,
+                count: false,
+                chart: false,
+                json: false,
+                all: true,
+                attr: None,
+                spark: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_ls_attrs() {
+        let expected = Some(MagicCommand::Ls {
+            domain: Some("sensor".into()),
+            count: false,
+            chart: false,
+            json: false,
+            all: false,
+            attr: Some("battery_level".into()),
+            spark: false,
+        });
+        assert_eq!(parse_magic("%ls sensor --attrs battery_level"), expected);
+        assert_eq!(parse_magic("%ls --attrs battery_level sensor"), expected);
+        assert_eq!(parse_magic("%ls sensor --attrs"), None);
+        assert!(matches!(
+            parse_magic("%ls sensor --spark"),
+            Some(MagicCommand::Ls { spark: true, .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_ls_json() {
+        assert_eq!(
+            parse_magic("%ls sensor --json"),
+            Some(MagicCommand::Ls {
+                domain: Some("sensor".into()),
+                count: false,
+                chart: false,
+                json: true,
+                all: false,
+                attr: None,
+                spark: false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_parse_inspect() {
+        assert_eq!(
+            parse_magic("%inspect sensor.temp"),
+            Some(MagicCommand::Inspect("sensor.temp".into()))
+        );
+        assert_eq!(parse_magic("%inspect"), None);
+    }
+
+    #[test]
+    fn test_parse_requires_word_after_percent() {
+        assert!(parse_magic("%ls").is_some());
+        assert_eq!(parse_magic("% 2"), None);
+        assert_eq!(parse_magic("% ls"), None);
+        assert_eq!(parse_magic("%%ls"), None);
+        assert_eq!(parse_magic("%"), None);
+    }
+
+    #[test]
+    fn test_parse_get_services() {
+        assert_eq!(
+            parse_magic("%get light.kitchen --services"),
+            Some(MagicCommand::Get {
+                entity_id: "light.kitchen".into(),
+                badge: false,
+                attribute: None,
+                expand: false,
+                services: true,
+            })
         );
     }
 
@@ -211,7 +671,47 @@ mod tests {
     fn test_parse_get() {
         assert_eq!(
             parse_magic("%get sensor.temp"),
-            Some(MagicCommand::Get("sensor.temp".into()))
+            Some(MagicCommand::Get {
+                entity_id: "sensor.temp".into(),
+                badge: false,
+                attribute: None,
+                expand: false,
+                services: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%get binary_sensor.door --badge"),
+            Some(MagicCommand::Get {
+                entity_id: "binary_sensor.door".into(),
+                badge: true,
+                attribute: None,
+                expand: false,
+                services: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%get light.kitchen attributes.brightness"),
+            Some(MagicCommand::Get {
+                entity_id: "light.kitchen".into(),
+                badge: false,
+                attribute: Some("attributes.brightness".into()),
+                expand: false,
+                services: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%get group.lights --expand"),
+            Some(MagicCommand::Get {
+                entity_id: "group.lights".into(),
+                badge: false,
+                attribute: None,
+                expand: true,
+                services: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%get sensor.temp --attrs"),
+            Some(MagicCommand::Attrs("sensor.temp".into()))
         );
         assert_eq!(parse_magic("%get"), None);
     }
@@ -231,6 +731,8 @@ mod tests {
             Some(MagicCommand::Hist {
                 entity_id: "sensor.temp".into(),
                 hours: Some(6),
+                render_as: None,
+                compare: None,
             })
         );
         assert_eq!(
@@ -238,8 +740,40 @@ mod tests {
             Some(MagicCommand::Hist {
                 entity_id: "sensor.temp".into(),
                 hours: None,
+                render_as: None,
+                compare: None,
+            })
+        );
+        assert_eq!(
+            parse_magic("%hist sensor.temp --as table -h 12"),
+            Some(MagicCommand::Hist {
+                entity_id: "sensor.temp".into(),
+                hours: Some(12),
+                render_as: Some(HistoryView::Table),
+                compare: None,
+            })
+        );
+        assert_eq!(parse_magic("%hist sensor.temp --as pie"), None);
+        assert_eq!(
+            parse_magic("%hist sensor.temp --compare 1d"),
+            Some(MagicCommand::Hist {
+                entity_id: "sensor.temp".into(),
+                hours: None,
+                render_as: None,
+                compare: Some(24),
             })
         );
+        assert_eq!(parse_window_hours("12h"), Some(12));
+        assert_eq!(parse_window_hours("0d"), None);
+        assert_eq!(parse_magic("%hist sensor.temp --compare soon"), None);
+    }
+
+    #[test]
+    fn test_parse_examples() {
+        assert_eq!(parse_magic("%examples"), Some(MagicCommand::Examples));
+        for example in EXAMPLES.iter().filter(|e| e.starts_with('%')) {
+            assert!(parse_magic(example).is_some(), "{example}");
+        }
     }
 
     #[test]
@@ -258,8 +792,95 @@ mod tests {
 
     #[test]
     fn test_parse_clear() {
-        assert_eq!(parse_magic(":clear"), Some(MagicCommand::Clear));
-        assert_eq!(parse_magic(":cls"), Some(MagicCommand::Clear));
+        assert_eq!(parse_magic(":clear"), Some(MagicCommand::Clear { last: false }));
+        assert_eq!(parse_magic(":clear all"), Some(MagicCommand::Clear { last: false }));
+        assert_eq!(parse_magic(":clear last"), Some(MagicCommand::Clear { last: true }));
+        assert_eq!(parse_magic(":clear everything"), None);
+        assert_eq!(parse_magic(":cls"), Some(MagicCommand::Clear { last: false }));
+    }
+
+    #[test]
+    fn test_parse_info() {
+        assert_eq!(parse_magic(":info"), Some(MagicCommand::Info));
+    }
+
+    #[test]
+    fn test_parse_refresh() {
+        assert_eq!(parse_magic("%refresh"), Some(MagicCommand::Refresh));
+    }
+
+    #[test]
+    fn test_parse_copy() {
+        assert_eq!(parse_magic("%copy"), Some(MagicCommand::Copy));
+    }
+
+    #[test]
+    fn test_parse_plot() {
+        assert_eq!(parse_magic("%plot"), Some(MagicCommand::Plot));
+    }
+
+    #[test]
+    fn test_parse_raw() {
+        assert_eq!(parse_magic("%raw on"), Some(MagicCommand::Raw(Some(true))));
+        assert_eq!(parse_magic("%raw off"), Some(MagicCommand::Raw(Some(false))));
+        assert_eq!(parse_magic("%raw"), Some(MagicCommand::Raw(None)));
+        assert_eq!(parse_magic("%raw maybe"), None);
+    }
+
+    #[test]
+    fn test_parse_yaml() {
+        assert_eq!(parse_magic("%yaml"), Some(MagicCommand::Yaml { service: false }));
+        assert_eq!(parse_magic("%yaml service"), Some(MagicCommand::Yaml { service: true }));
+        assert_eq!(parse_magic("%yaml bogus"), None);
+    }
+
+    #[test]
+    fn test_parse_strict() {
+        assert_eq!(parse_magic("%strict"), Some(MagicCommand::Strict(None)));
+        assert_eq!(parse_magic("%strict on"), Some(MagicCommand::Strict(Some(true))));
+    }
+
+    #[test]
+    fn test_parse_count() {
+        assert_eq!(parse_magic("%count"), Some(MagicCommand::StateCounts));
+    }
+
+    #[test]
+    fn test_parse_caps() {
+        assert_eq!(parse_magic("%caps"), Some(MagicCommand::Caps));
+    }
+
+    #[test]
+    fn test_parse_theme() {
+        assert_eq!(parse_magic("%theme light"), Some(MagicCommand::Theme(Some(Theme::Light))));
+        assert_eq!(parse_magic("%theme"), Some(MagicCommand::Theme(None)));
+        assert_eq!(parse_magic("%theme sepia"), None);
+    }
+
+    #[test]
+    fn test_parse_py() {
+        assert_eq!(parse_magic("%py light"), Some(MagicCommand::Py("light".into())));
+        assert_eq!(
+            parse_magic("%py x = 1\nx + 1"),
+            Some(MagicCommand::Py("x = 1\nx + 1".into()))
+        );
+        assert_eq!(parse_magic("%py"), None);
+    }
+
+    #[test]
+    fn test_parse_presence() {
+        assert_eq!(parse_magic("%presence"), Some(MagicCommand::Presence));
+    }
+
+    #[test]
+    fn test_parse_services() {
+        assert_eq!(
+            parse_magic("%services light ~turn"),
+            Some(MagicCommand::Services {
+                domain: Some("light".into()),
+                query: Some("turn".into()),
+            })
+        );
     }
 
     #[test]
@@ -275,21 +896,61 @@ mod tests {
     fn test_parse_diff() {
         assert_eq!(
             parse_magic("%diff sensor.temp sensor.humidity"),
-            Some(MagicCommand::Diff("sensor.temp".into(), "sensor.humidity".into()))
+            Some(MagicCommand::Diff {
+                entity_ids: vec!["sensor.temp".into(), "sensor.humidity".into()],
+                attrs_only: false,
+                changed_only: false,
+            })
+        );
+        assert_eq!(
+            parse_magic("%diff light.a light.b light.c"),
+            Some(MagicCommand::Diff {
+                entity_ids: vec!["light.a".into(), "light.b".into(), "light.c".into()],
+                attrs_only: false,
+                changed_only: false,
+            })
         );
         assert_eq!(parse_magic("%diff sensor.temp"), None);
+        assert_eq!(
+            parse_magic("%snapshot light.kitchen"),
+            Some(MagicCommand::Snapshot("light.kitchen".into()))
+        );
+        assert_eq!(parse_magic("%snapshot"), None);
+        assert_eq!(
+            parse_magic("%diff light.a --changed-only light.b --attrs-only"),
+            Some(MagicCommand::Diff {
+                entity_ids: vec!["light.a".into(), "light.b".into()],
+                attrs_only: true,
+                changed_only: true,
+            })
+        );
     }
 
     #[test]
     fn test_parse_ask() {
         assert_eq!(
             parse_magic("%ask why is the light on?"),
-            Some(MagicCommand::Ask("why is the light on?".into()))
+            Some(MagicCommand::Ask {
+                question: "why is the light on?".into(),
+                agent: None,
+            })
         );
         assert_eq!(
             parse_magic("%assistant explain this entity"),
-            Some(MagicCommand::Ask("explain this entity".into()))
+            Some(MagicCommand::Ask {
+                question: "explain this entity".into(),
+                agent: None,
+            })
+        );
+        assert_eq!(
+            parse_magic("%ask @claude why"),
+            Some(MagicCommand::Ask {
+                question: "why".into(),
+                agent: Some("claude".into()),
+            })
         );
+        // An agent but no question is still incomplete.
+        assert_eq!(parse_magic("%ask @claude"), None);
         // Empty question returns None.
         assert_eq!(parse_magic("%ask"), None);
         assert_eq!(parse_magic("%ask   "), None);
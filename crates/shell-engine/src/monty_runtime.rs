@@ -54,14 +54,19 @@ pub const HA_EXTERNAL_FUNCTIONS: &[&str] = &[
     // Areas
     "get_areas",
     "get_area_entities",
+    // Templates
+    "template",
+    "render_template",
     // Time
     "ago",
+    "now",
     "get_datetime",
     // Display
     "show",
     // Logbook
     "get_logbook",
     // Traces
+    "traces",
     "get_trace",
     "list_traces",
     // Charting
@@ -71,6 +76,39 @@ pub const HA_EXTERNAL_FUNCTIONS: &[&str] = &[
     "plot_series",
 ];
 
+/// Short call signatures for each external function, for autocomplete
+/// and tooling. Covers every name in [`HA_EXTERNAL_FUNCTIONS`].
+pub const FUNCTION_SIGNATURES: &[(&str, &str)] = &[
+    ("state", "state(entity_id)"),
+    ("states", "states([domain])"),
+    ("get_state", "get_state(entity_id)"),
+    ("get_states", "get_states([domain])"),
+    ("history", "history(entity_id or [ids], [hours])"),
+    ("statistics", "statistics(entity_id, [period])"),
+    ("get_history", "get_history(entity_id, [hours])"),
+    ("get_statistics", "get_statistics(entity_id, [period])"),
+    ("events", "events(entity_id, [hours])"),
+    ("get_events", "get_events(entity_id, [hours])"),
+    ("call_service", "call_service(domain, service, [data])"),
+    ("get_services", "get_services([domain], [query])"),
+    ("get_areas", "get_areas()"),
+    ("get_area_entities", "get_area_entities(area_id)"),
+    ("template", "template(tpl, [variables])"),
+    ("render_template", "render_template(tpl, [variables])"),
+    ("ago", "ago(spec)"),
+    ("now", "now()"),
+    ("get_datetime", "get_datetime()"),
+    ("show", "show(value, [label])"),
+    ("get_logbook", "get_logbook([entity_id], [hours])"),
+    ("traces", "traces([automation_id], state=None)"),
+    ("get_trace", "get_trace(automation_id, [run_id], state=None)"),
+    ("list_traces", "list_traces([domain], state=None)"),
+    ("plot_line", "plot_line(labels, values, [title], cumsum=False, unit=None, fill=False)"),
+    ("plot_bar", "plot_bar(labels, values, [title], cumsum=False, unit=None)"),
+    ("plot_pie", "plot_pie(data, [title], donut=False, labels=\"both\")"),
+    ("plot_series", "plot_series(points, [title], subplots=False, cumsum=False, unit=None, axes=None)"),
+];
+
 // ---------------------------------------------------------------------------
 // REPL lifecycle
 // ---------------------------------------------------------------------------
@@ -89,6 +127,8 @@ pub enum ReplEvalResult {
         output: String,
         function_name: String,
         args: Vec<MontyObject>,
+        /// Keyword arguments as (name, value) pairs — e.g. `subplots=True`.
+        kwargs: Vec<(MontyObject, MontyObject)>,
         snapshot: ReplSnapshot<NoLimitTracker>,
     },
     /// Snippet failed with an error.
@@ -96,6 +136,8 @@ pub enum ReplEvalResult {
     /// via `ReplProgress::Error`.  `repl: None` only occurs on syntax/compile
     /// errors during `start()` (before execution began).
     Error {
+        /// Print output captured before the error, shown alongside it.
+        output: String,
         message: String,
         repl: Option<MontyRepl<NoLimitTracker>>,
     },
@@ -130,13 +172,17 @@ pub fn init_repl(init_code: &str) -> Result<MontyRepl<NoLimitTracker>, String> {
 /// If the snippet calls `state()`, `show()`, etc., `feed()` returns an
 /// error containing "not implemented with standard execution".  The
 /// caller should detect this and retry with `start_snippet()`.
+///
+/// On error, returns `(output, message)` — print output produced before
+/// the error is kept so it can be shown alongside the message.
 pub fn feed_snippet(
     repl: &mut MontyRepl<NoLimitTracker>,
     code: &str,
-) -> Result<(String, Option<MontyObject>), String> {
+) -> Result<(String, Option<MontyObject>), (String, String)> {
     let mut print = PrintWriter::Collect(String::new());
-    let value = repl.feed(code, &mut print).map_err(|e| format_monty_error(&e))?;
-    let output = print.collected_output().unwrap_or("").to_owned();
+    let result = repl.feed(code, &mut print);
+    let output = concise_entity_reprs(print.collected_output().unwrap_or(""));
+    let value = result.map_err(|e| (output.clone(), format_monty_error(&e)))?;
     let val = if value == MontyObject::None {
         None
     } else {
@@ -157,12 +203,13 @@ pub fn feed_snippet(
 pub fn start_snippet(repl: MontyRepl<NoLimitTracker>, code: &str) -> ReplEvalResult {
     let mut print = PrintWriter::Collect(String::new());
     let progress = repl.start(code, &mut print);
-    let output = print.collected_output().unwrap_or("").to_owned();
+    let output = concise_entity_reprs(print.collected_output().unwrap_or(""));
     match progress {
         Ok(prog) => finish_repl_progress(prog, output),
         Err(e) => {
             // Syntax/compile error — REPL was consumed, snippet never ran.
             ReplEvalResult::Error {
+                output,
                 message: format_monty_error(&e),
                 repl: None,
             }
@@ -177,13 +224,14 @@ pub fn resume_snapshot(
 ) -> ReplEvalResult {
     let mut print = PrintWriter::Collect(String::new());
     let progress = snapshot.run(result, &mut print);
-    let output = print.collected_output().unwrap_or("").to_owned();
+    let output = concise_entity_reprs(print.collected_output().unwrap_or(""));
     match progress {
         Ok(prog) => finish_repl_progress(prog, output),
         Err(e) => {
             // Should not happen with the exceptions branch — runtime errors
             // come back as ReplProgress::Error.  But handle defensively.
             ReplEvalResult::Error {
+                output,
                 message: format_monty_error(&e),
                 repl: None,
             }
@@ -191,6 +239,110 @@ pub fn resume_snapshot(
     }
 }
 
+/// Field order of the `EntityState` dataclass, as it appears in its repr.
+const ENTITY_STATE_FIELDS: [&str; 10] = [
+    "entity_id",
+    "state",
+    "domain",
+    "name",
+    "last_changed",
+    "last_updated",
+    "is_on",
+    "is_off",
+    "exists",
+    "attributes",
+];
+
+/// Shorten printed `EntityState(...)` reprs to just `entity_id` and `state`.
+///
+/// Monty's dataclass repr includes every field — attributes and all — which
+/// swamps `print(state(...))` output.  `show()` and auto-display render the
+/// full object from the value itself, so only print output is rewritten.
+/// Only text with the exact shape of a generated repr (every field, in
+/// order) is touched, so user strings that merely mention `EntityState(`
+/// print as written.
+pub fn concise_entity_reprs(output: &str) -> String {
+    const MARKER: &str = "EntityState(";
+    let mut out = String::with_capacity(output.len());
+    let mut rest = output;
+    while let Some(start) = rest.find(MARKER) {
+        out.push_str(&rest[..start]);
+        let body_start = start + MARKER.len();
+        let Some(body_len) = find_top_level(&rest[body_start..], ')') else {
+            // Unbalanced — leave the remainder untouched.
+            break;
+        };
+        let body = &rest[body_start..body_start + body_len];
+        let end = body_start + body_len + 1;
+        match entity_repr_id_and_state(body) {
+            Some((entity_id, state)) => {
+                out.push_str(&format!("EntityState(entity_id={entity_id}, state={state})"));
+            }
+            None => out.push_str(&rest[start..end]),
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The raw `entity_id` and `state` values of a repr body, if the body lists
+/// exactly the `EntityState` fields in order and both values are strings.
+fn entity_repr_id_and_state(body: &str) -> Option<(&str, &str)> {
+    let mut values = Vec::with_capacity(ENTITY_STATE_FIELDS.len());
+    let mut rest = body;
+    for (i, name) in ENTITY_STATE_FIELDS.iter().enumerate() {
+        // Each field ends at the next top-level comma (or the body end).
+        let field_end = find_top_level(rest, ',').unwrap_or(rest.len());
+        let value = rest[..field_end]
+            .trim()
+            .strip_prefix(name)
+            .and_then(|f| f.strip_prefix('='))?;
+        values.push(value);
+        let last = i + 1 == ENTITY_STATE_FIELDS.len();
+        if last != (field_end == rest.len()) {
+            return None;
+        }
+        if !last {
+            rest = &rest[field_end + 1..];
+        }
+    }
+    let is_str = |v: &str| {
+        v.len() >= 2
+            && (v.starts_with('\'') && v.ends_with('\'')
+                || v.starts_with('"') && v.ends_with('"'))
+    };
+    (is_str(values[0]) && is_str(values[1])).then(|| (values[0], values[1]))
+}
+
+/// Byte offset of the first `target` not nested in brackets or quotes.
+/// With `)` as the target this finds the paren closing a repr body.
+fn find_top_level(s: &str, target: char) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            c if c == target && depth == 0 => return Some(i),
+            '\'' | '"' => quote = Some(c),
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    None
+}
+
 /// Convert a `ReplProgress` into our `ReplEvalResult`.
 fn finish_repl_progress(
     progress: ReplProgress<NoLimitTracker>,
@@ -208,23 +360,28 @@ fn finish_repl_progress(
         ReplProgress::FunctionCall {
             function_name,
             args,
+            kwargs,
             state,
             ..
         } => ReplEvalResult::HostCallNeeded {
             output,
             function_name,
             args,
+            kwargs,
             snapshot: state,
         },
         ReplProgress::Error { repl, error } => ReplEvalResult::Error {
+            output,
             message: format_monty_error(&error),
             repl: Some(repl),
         },
         ReplProgress::OsCall { .. } => ReplEvalResult::Error {
+            output,
             message: "OS calls are not supported in Signal Deck.".to_string(),
             repl: None,
         },
         ReplProgress::ResolveFutures(_) => ReplEvalResult::Error {
+            output,
             message: "Async futures are not supported in Signal Deck.".to_string(),
             repl: None,
         },
@@ -235,6 +392,36 @@ fn finish_repl_progress(
 // Host call mapping
 // ---------------------------------------------------------------------------
 
+/// Host methods whose `entity_id` / `entity_ids` params must be entity IDs.
+/// `get_statistics` is left out: external statistic IDs such as
+/// `opower:elec_cost` have no dot.
+const ENTITY_ID_METHODS: &[&str] = &["get_state", "get_history", "get_events", "get_logbook"];
+
+/// Check the `entity_id` / `entity_ids` params of a mapped state, history
+/// or event host call, so a stray quote, space or newline never reaches the
+/// host. Returns a usage message naming the first ID that isn't
+/// `domain.object_id`.
+pub fn invalid_entity_id(
+    function_name: &str,
+    method: &str,
+    params: &serde_json::Value,
+) -> Option<String> {
+    if !ENTITY_ID_METHODS.contains(&method) {
+        return None;
+    }
+    let is_valid = |id: &str| {
+        let part = |p: &str| !p.is_empty() && p.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        id.split_once('.').is_some_and(|(domain, object_id)| part(domain) && part(object_id))
+    };
+    let ids = params
+        .get("entity_id")
+        .into_iter()
+        .chain(params.get("entity_ids").and_then(|v| v.as_array()).into_iter().flatten());
+    ids.filter_map(|v| v.as_str()).find(|id| !is_valid(id)).map(|id| {
+        format!("{function_name}(): {id:?} is not an entity ID — expected domain.object_id (e.g. sensor.temperature)")
+    })
+}
+
 /// Map an external function call from Monty to a host call method + params.
 ///
 /// Returns `None` for functions that are handled locally (show, ago, charts).
@@ -268,40 +455,32 @@ pub fn map_ext_call_to_host_call(
             Some(("get_states", params))
         }
         "history" | "get_history" => {
-            let entity_id = args.first().and_then(|a| {
-                if let MontyObject::String(s) = a {
-                    Some(s.as_str())
-                } else {
-                    None
+            // history(['sensor.a', 'sensor.b']) fetches several series at once.
+            let mut params = match args.first()? {
+                MontyObject::String(s) => serde_json::json!({ "entity_id": s }),
+                MontyObject::List(items) => {
+                    let ids: Vec<&str> = items
+                        .iter()
+                        .filter_map(|item| match item {
+                            MontyObject::String(s) => Some(s.as_str()),
+                            _ => None,
+                        })
+                        .collect();
+                    if ids.is_empty() {
+                        return None;
+                    }
+                    serde_json::json!({ "entity_ids": ids })
                 }
-            })?;
+                _ => return None,
+            };
             // Second arg can be hours (int/float) or an ISO timestamp string from ago().
             match args.get(1) {
-                Some(MontyObject::String(s)) => {
-                    Some(("get_history", serde_json::json!({
-                        "entity_id": entity_id,
-                        "start_time": s,
-                    })))
-                }
-                Some(MontyObject::Int(n)) => {
-                    Some(("get_history", serde_json::json!({
-                        "entity_id": entity_id,
-                        "hours": *n as f64,
-                    })))
-                }
-                Some(MontyObject::Float(f)) => {
-                    Some(("get_history", serde_json::json!({
-                        "entity_id": entity_id,
-                        "hours": f,
-                    })))
-                }
-                _ => {
-                    Some(("get_history", serde_json::json!({
-                        "entity_id": entity_id,
-                        "hours": 6.0,
-                    })))
-                }
+                Some(MontyObject::String(s)) => params["start_time"] = serde_json::json!(s),
+                Some(MontyObject::Int(n)) => params["hours"] = serde_json::json!(*n as f64),
+                Some(MontyObject::Float(f)) => params["hours"] = serde_json::json!(f),
+                _ => params["hours"] = serde_json::json!(6.0),
             }
+            Some(("get_history", params))
         }
         "events" | "get_events" => {
             let entity_id = args.first().and_then(|a| {
@@ -356,10 +535,14 @@ pub fn map_ext_call_to_host_call(
             let domain = args.first().and_then(|a| {
                 if let MontyObject::String(s) = a { Some(s.clone()) } else { None }
             });
-            let params = match domain {
+            let mut params = match domain {
                 Some(d) => serde_json::json!({ "domain": d }),
                 None => serde_json::json!({}),
             };
+            // Optional keyword filter: get_services(None, "turn").
+            if let Some(MontyObject::String(q)) = args.get(1) {
+                params["query"] = serde_json::json!(q);
+            }
             Some(("get_services", params))
         }
         "get_areas" => {
@@ -371,9 +554,20 @@ pub fn map_ext_call_to_host_call(
             })?;
             Some(("get_area_entities", serde_json::json!({ "area_id": area_id })))
         }
-        "get_datetime" => {
+        "now" | "get_datetime" => {
             Some(("get_datetime", serde_json::json!({})))
         }
+        "template" | "render_template" => {
+            let template = args.first().and_then(|a| {
+                if let MontyObject::String(s) = a { Some(s.as_str()) } else { None }
+            })?;
+            let mut params = serde_json::json!({ "template": template });
+            // template(tpl, {"x": 5}) — variables the template can reference.
+            if let Some(vars @ MontyObject::Dict(_)) = args.get(1) {
+                params["variables"] = monty_obj_to_json(vars);
+            }
+            Some(("render_template", params))
+        }
         "get_logbook" => {
             let entity_id = args.first().and_then(|a| {
                 if let MontyObject::String(s) = a { Some(s.as_str()) } else { None }
@@ -389,6 +583,11 @@ pub fn map_ext_call_to_host_call(
             }
             Some(("get_logbook", params))
         }
+        // `traces()` lists recent runs; `traces(id)` narrows to one automation.
+        "traces" => match args.first() {
+            Some(MontyObject::String(_)) => map_ext_call_to_host_call("get_trace", &args[..1]),
+            _ => map_ext_call_to_host_call("list_traces", &[]),
+        },
         "get_trace" => {
             let automation_id = args.first().and_then(|a| {
                 if let MontyObject::String(s) = a { Some(s.as_str()) } else { None }
@@ -422,6 +621,9 @@ pub fn map_ext_call_to_host_call(
 // ---------------------------------------------------------------------------
 
 /// Convert a MontyObject to a serde_json::Value.
+///
+/// JSON has no bytes type, so `bytes` become their Python repr
+/// (`b'ok\xff'`) — readable for ASCII payloads and lossless otherwise.
 pub fn monty_obj_to_json(obj: &MontyObject) -> serde_json::Value {
     match obj {
         MontyObject::None => serde_json::Value::Null,
@@ -452,9 +654,7 @@ pub fn monty_obj_to_json(obj: &MontyObject) -> serde_json::Value {
         MontyObject::FrozenSet(items) => {
             serde_json::Value::Array(items.iter().map(monty_obj_to_json).collect())
         }
-        MontyObject::Bytes(b) => {
-            serde_json::Value::String(format!("b\"{}\"", String::from_utf8_lossy(b)))
-        }
+        MontyObject::Bytes(b) => serde_json::Value::String(bytes_repr(b)),
         MontyObject::Dataclass { name, attrs, .. } => {
             let mut map = serde_json::Map::new();
             map.insert("__type__".to_string(), serde_json::json!(name));
@@ -472,6 +672,25 @@ pub fn monty_obj_to_json(obj: &MontyObject) -> serde_json::Value {
     }
 }
 
+/// Python's repr of a bytes value: printable ASCII as-is, everything
+/// else escaped, so invalid UTF-8 never turns into replacement characters.
+fn bytes_repr(bytes: &[u8]) -> String {
+    let mut out = String::from("b'");
+    for &b in bytes {
+        match b {
+            b'\\' => out.push_str("\\\\"),
+            b'\'' => out.push_str("\\'"),
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            0x20..=0x7e => out.push(b as char),
+            _ => out.push_str(&format!("\\x{b:02x}")),
+        }
+    }
+    out.push('\'');
+    out
+}
+
 /// Convert a JSON value to a MontyObject.
 pub fn json_to_monty_obj(value: &serde_json::Value) -> MontyObject {
     match value {
@@ -502,6 +721,17 @@ pub fn json_to_monty_obj(value: &serde_json::Value) -> MontyObject {
 
 /// Convert a HA state JSON object to an EntityState dataclass.
 pub fn json_to_entity_state(value: &serde_json::Value) -> MontyObject {
+    entity_state(value, true)
+}
+
+/// A placeholder EntityState for an entity that doesn't exist — state
+/// `"unknown"` and `exists=False`, so `state("x").state` degrades
+/// gracefully instead of failing.
+pub fn missing_entity_state(entity_id: &str) -> MontyObject {
+    entity_state(&serde_json::json!({ "entity_id": entity_id }), false)
+}
+
+fn entity_state(value: &serde_json::Value, exists: bool) -> MontyObject {
     let entity_id = value
         .get("entity_id")
         .and_then(|v| v.as_str())
@@ -536,7 +766,7 @@ pub fn json_to_entity_state(value: &serde_json::Value) -> MontyObject {
         .unwrap_or(&entity_id)
         .to_string();
 
-    let is_on = matches!(state.as_str(), "on" | "home" | "open" | "playing" | "active");
+    let (is_on, is_off) = entity_on_off(&domain, &state);
 
     let attributes = value
         .get("attributes")
@@ -547,16 +777,7 @@ pub fn json_to_entity_state(value: &serde_json::Value) -> MontyObject {
     MontyObject::Dataclass {
         name: "EntityState".to_string(),
         type_id: 0,
-        field_names: vec![
-            "entity_id".into(),
-            "state".into(),
-            "domain".into(),
-            "name".into(),
-            "last_changed".into(),
-            "last_updated".into(),
-            "is_on".into(),
-            "attributes".into(),
-        ],
+        field_names: ENTITY_STATE_FIELDS.iter().map(|f| (*f).into()).collect(),
         attrs: vec![
             (MontyObject::String("entity_id".into()), MontyObject::String(entity_id)),
             (MontyObject::String("state".into()), MontyObject::String(state)),
@@ -565,12 +786,53 @@ pub fn json_to_entity_state(value: &serde_json::Value) -> MontyObject {
             (MontyObject::String("last_changed".into()), MontyObject::String(last_changed)),
             (MontyObject::String("last_updated".into()), MontyObject::String(last_updated)),
             (MontyObject::String("is_on".into()), MontyObject::Bool(is_on)),
+            (MontyObject::String("is_off".into()), MontyObject::Bool(is_off)),
+            (MontyObject::String("exists".into()), MontyObject::Bool(exists)),
             (MontyObject::String("attributes".into()), attrs_monty),
         ].into(),
         frozen: false,
     }
 }
 
+/// Classify a state as `(is_on, is_off)` for `EntityState`.
+///
+/// Convention: "on" means engaged or active — a light/switch on, a cover
+/// open, a lock locked, an alarm armed or triggered, a person home, media
+/// playing, a binary sensor detecting.  "Off" is the resting counterpart.
+/// Anything else (unknown, unavailable, jammed, transitional states,
+/// numeric sensor values) is neither, so both flags are false.
+pub fn entity_on_off(domain: &str, state: &str) -> (bool, bool) {
+    if matches!(state, "unknown" | "unavailable") {
+        return (false, false);
+    }
+    match domain {
+        "lock" => (state == "locked", matches!(state, "unlocked" | "open")),
+        "alarm_control_panel" => (
+            state.starts_with("armed_") || state == "triggered",
+            state == "disarmed",
+        ),
+        "cover" => (
+            matches!(state, "open" | "opening"),
+            matches!(state, "closed" | "closing"),
+        ),
+        // Any zone other than home counts as away.
+        "person" | "device_tracker" => (state == "home", state != "home"),
+        "media_player" => (
+            matches!(state, "playing" | "on" | "buffering"),
+            matches!(state, "off" | "idle" | "standby" | "paused"),
+        ),
+        "vacuum" => (
+            state == "cleaning",
+            matches!(state, "docked" | "idle" | "paused" | "returning"),
+        ),
+        "climate" | "water_heater" => (state != "off", state == "off"),
+        _ => (
+            matches!(state, "on" | "home" | "open" | "playing" | "active" | "detected"),
+            matches!(state, "off" | "not_home" | "closed" | "idle" | "clear"),
+        ),
+    }
+}
+
 /// Convert a JSON array of HA state objects to a list of EntityState.
 pub fn json_to_entity_state_list(value: &serde_json::Value) -> MontyObject {
     match value {
@@ -628,6 +890,10 @@ pub fn json_to_calendar_event_list(value: &serde_json::Value) -> MontyObject {
 // ---------------------------------------------------------------------------
 
 /// Format a MontyException into a user-friendly error string.
+///
+/// Snippets run directly against the stateful REPL with no replayed
+/// context prefix, so traceback line numbers already refer to the user's
+/// own input and need no adjustment.
 pub fn format_monty_error(err: &MontyException) -> String {
     // MontyException implements Display with Python-style tracebacks
     err.to_string()
@@ -819,6 +1085,23 @@ mod tests {
         assert!(result.is_none());
     }
 
+    #[test]
+    fn test_map_ext_call_now_maps_to_get_datetime() {
+        let (method, _) = map_ext_call_to_host_call("now", &[]).unwrap();
+        assert_eq!(method, "get_datetime");
+    }
+
+    #[test]
+    fn test_every_function_has_signature() {
+        for name in HA_EXTERNAL_FUNCTIONS {
+            assert!(
+                FUNCTION_SIGNATURES.iter().any(|(n, _)| n == name),
+                "Missing signature for {name}"
+            );
+        }
+        assert_eq!(FUNCTION_SIGNATURES.len(), HA_EXTERNAL_FUNCTIONS.len());
+    }
+
     #[test]
     fn test_map_ext_call_unknown_returns_none() {
         let args = vec![];
@@ -837,6 +1120,18 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_monty_obj_to_json_bytes() {
+        assert_eq!(
+            monty_obj_to_json(&MontyObject::Bytes(b"ok".to_vec())),
+            serde_json::json!("b'ok'")
+        );
+        assert_eq!(
+            monty_obj_to_json(&MontyObject::Bytes(vec![0xff, 0x00, b'\'', b'\n', b'\\'])),
+            serde_json::json!(r"b'\xff\x00\'\n\\'")
+        );
+    }
+
     #[test]
     fn test_monty_obj_to_json_list() {
         let list = MontyObject::List(vec![MontyObject::Int(1), MontyObject::Int(2)]);
@@ -888,6 +1183,80 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_missing_entity_state() {
+        let json = monty_obj_to_json(&missing_entity_state("sensor.nope"));
+        assert_eq!(json["entity_id"], "sensor.nope");
+        assert_eq!(json["state"], "unknown");
+        assert_eq!(json["exists"], false);
+
+        let json = monty_obj_to_json(&json_to_entity_state(&serde_json::json!({
+            "entity_id": "sensor.temp", "state": "21"
+        })));
+        assert_eq!(json["exists"], true);
+    }
+
+    #[test]
+    fn test_entity_state_is_on_is_off() {
+        let flags = |entity_id: &str, state: &str| {
+            let json = monty_obj_to_json(&json_to_entity_state(&serde_json::json!({
+                "entity_id": entity_id,
+                "state": state,
+                "attributes": {}
+            })));
+            (json["is_on"].as_bool().unwrap(), json["is_off"].as_bool().unwrap())
+        };
+        assert_eq!(flags("lock.front_door", "locked"), (true, false));
+        assert_eq!(flags("lock.front_door", "unlocked"), (false, true));
+        assert_eq!(flags("lock.front_door", "jammed"), (false, false));
+        assert_eq!(flags("alarm_control_panel.home", "armed_away"), (true, false));
+        assert_eq!(flags("alarm_control_panel.home", "disarmed"), (false, true));
+        assert_eq!(flags("binary_sensor.motion", "detected"), (true, false));
+        assert_eq!(flags("light.kitchen", "off"), (false, true));
+        assert_eq!(flags("person.alex", "Work"), (false, true));
+        assert_eq!(flags("sensor.temp", "21.5"), (false, false));
+        assert_eq!(flags("switch.fan", "unavailable"), (false, false));
+    }
+
+    #[test]
+    fn test_concise_entity_reprs() {
+        let printed = "EntityState(entity_id='sensor.temp', state='22.5', domain='sensor', \
+name='Temp, upstairs', last_changed='', last_updated='', is_on=False, is_off=False, \
+exists=True, attributes={'unit_of_measurement': '°C', 'hs': (1, 2), 'note': 'a ) b'})\n";
+        assert_eq!(
+            concise_entity_reprs(printed),
+            "EntityState(entity_id='sensor.temp', state='22.5')\n"
+        );
+    }
+
+    #[test]
+    fn test_concise_entity_reprs_in_list() {
+        let fields = "domain='light', name='A', last_changed='', last_updated='', \
+is_on=True, is_off=False, exists=True, attributes={}";
+        let printed = format!(
+            "[EntityState(entity_id='light.a', state='on', {fields}), \
+EntityState(entity_id='light.b', state='off', {fields})]"
+        );
+        assert_eq!(
+            concise_entity_reprs(&printed),
+            "[EntityState(entity_id='light.a', state='on'), EntityState(entity_id='light.b', state='off')]"
+        );
+    }
+
+    #[test]
+    fn test_concise_entity_reprs_leaves_other_output() {
+        assert_eq!(concise_entity_reprs("hello\n"), "hello\n");
+        assert_eq!(concise_entity_reprs("EntityState(broken"), "EntityState(broken");
+        // User text that only resembles a repr prints as written.
+        for text in [
+            "EntityState(x)",
+            "EntityState(entity_id=foo, state=bar)",
+            "EntityState(entity_id='light.a', state='on', attributes={})",
+        ] {
+            assert_eq!(concise_entity_reprs(text), text);
+        }
+    }
+
     #[test]
     fn test_json_to_entity_state_list() {
         let json = serde_json::json!([
@@ -924,6 +1293,37 @@ mod tests {
         assert_eq!(params["hours"], 12.0);
     }
 
+    #[test]
+    fn test_map_ext_call_history_multiple_entities() {
+        let args = vec![
+            MontyObject::List(vec![
+                MontyObject::String("sensor.a".to_string()),
+                MontyObject::String("sensor.b".to_string()),
+            ]),
+            MontyObject::Int(3),
+        ];
+        let (method, params) = map_ext_call_to_host_call("history", &args).unwrap();
+        assert_eq!(method, "get_history");
+        assert_eq!(params["entity_ids"], serde_json::json!(["sensor.a", "sensor.b"]));
+        assert!(params.get("entity_id").is_none());
+        assert_eq!(params["hours"], 3.0);
+    }
+
+    #[test]
+    fn test_invalid_entity_id() {
+        let params = serde_json::json!({ "entity_id": "foo bar" });
+        let message = invalid_entity_id("state", "get_state", &params).unwrap();
+        assert!(message.starts_with("state(): \"foo bar\" is not an entity ID"), "{message}");
+        let params = serde_json::json!({ "entity_ids": ["sensor.a", "sensor.b\"x"] });
+        assert!(invalid_entity_id("history", "get_history", &params).is_some());
+        let params = serde_json::json!({ "entity_id": "sensor.outdoor_temp_2", "hours": 6 });
+        assert_eq!(invalid_entity_id("history", "get_history", &params), None);
+        let params = serde_json::json!({ "domain": "light" });
+        assert_eq!(invalid_entity_id("states", "get_states", &params), None);
+        let params = serde_json::json!({ "entity_id": "opower:elec_cost", "period": "hour" });
+        assert_eq!(invalid_entity_id("statistics", "get_statistics", &params), None);
+    }
+
     #[test]
     fn test_map_ext_call_call_service() {
         let args = vec![
@@ -941,6 +1341,21 @@ mod tests {
         assert_eq!(params["service"], "turn_on");
     }
 
+    #[test]
+    fn test_map_ext_call_template_variables() {
+        let args = vec![
+            MontyObject::String("{{ x }}".to_string()),
+            MontyObject::Dict(vec![(MontyObject::String("x".to_string()), MontyObject::Int(5))].into()),
+        ];
+        let (method, params) = map_ext_call_to_host_call("template", &args).unwrap();
+        assert_eq!(method, "render_template");
+        assert_eq!(params["template"], "{{ x }}");
+        assert_eq!(params["variables"], serde_json::json!({ "x": 5 }));
+
+        let (_, params) = map_ext_call_to_host_call("template", &args[..1]).unwrap();
+        assert!(params.get("variables").is_none());
+    }
+
     #[test]
     fn test_map_ext_call_get_areas() {
         let result = map_ext_call_to_host_call("get_areas", &[]);
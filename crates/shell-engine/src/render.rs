@@ -18,6 +18,17 @@ pub enum RenderSpec {
     Table {
         headers: Vec<String>,
         rows: Vec<Vec<String>>,
+        /// Per-column type hints for alignment. Defaults to all text.
+        #[serde(default)]
+        column_types: Vec<ColumnType>,
+        /// Optional colour token (`"success"`, `"dim"`, …) per cell,
+        /// parallel to `rows`. Empty for plain tables.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        cell_styles: Vec<Vec<Option<String>>>,
+        /// Recent values per row for a [`ColumnType::Sparkline`] column,
+        /// parallel to `rows`. Empty when the table has no such column.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        sparklines: Vec<Vec<f64>>,
     },
 
     /// A host call request — TypeScript must fulfill this and call back.
@@ -49,10 +60,17 @@ pub enum RenderSpec {
         state: String,
         state_color: String,
         unit: Option<String>,
+        /// The state as the card's headline — with its unit when numeric
+        /// (`"22.5 °C"`), otherwise just the state.
+        display_state: String,
         domain: String,
         device_class: Option<String>,
         last_changed: String,
         attributes: Vec<(String, String)>,
+        /// `"up"`, `"down"` or `"flat"` against the value shown last time
+        /// this entity was fetched; `None` for the first fetch or
+        /// non-numeric states.
+        trend: Option<String>,
     },
 
     /// A key-value display (list of labeled pairs).
@@ -60,6 +78,9 @@ pub enum RenderSpec {
     KeyValue {
         title: Option<String>,
         pairs: Vec<(String, String)>,
+        /// Named groups of pairs, rendered after `pairs` under sub-headings.
+        #[serde(default)]
+        sections: Vec<(String, Vec<(String, String)>)>,
     },
 
     /// A colored badge.
@@ -82,17 +103,24 @@ pub enum RenderSpec {
         snippets: Vec<String>,
     },
 
+    /// A list of runnable snippets, each with insert and run buttons.
+    #[serde(rename = "snippets")]
+    Snippets { title: String, snippets: Vec<String> },
+
     /// A sparkline chart — SVG polyline for numeric time series.
     #[serde(rename = "sparkline")]
     Sparkline {
         entity_id: String,
         name: String,
         unit: Option<String>,
-        /// Data points: (timestamp_ms, value).
+        /// Data points: (timestamp_ms, value). A NaN value (serialized as
+        /// `null`) marks a gap, e.g. while the entity was unavailable.
         points: Vec<(f64, f64)>,
         min: f64,
         max: f64,
         current: f64,
+        /// Reference value drawn as a dashed line (e.g. the period mean).
+        baseline: Option<f64>,
     },
 
     /// A state timeline — HA-style colored bar showing state changes over time.
@@ -140,6 +168,17 @@ pub enum RenderSpec {
     },
 }
 
+/// Column type hint for a table — lets the frontend right-align numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Text,
+    Number,
+    Icon,
+    /// Rendered from the table's `sparklines`; the cell text is unused.
+    Sparkline,
+}
+
 /// A single logbook entry — a state change event with context.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LogbookEntry {
@@ -204,7 +243,44 @@ impl RenderSpec {
     }
 
     pub fn table(headers: Vec<String>, rows: Vec<Vec<String>>) -> Self {
-        Self::Table { headers, rows }
+        let column_types = vec![ColumnType::Text; headers.len()];
+        Self::table_with_types(headers, rows, column_types)
+    }
+
+    pub fn table_with_types(
+        headers: Vec<String>,
+        rows: Vec<Vec<String>>,
+        column_types: Vec<ColumnType>,
+    ) -> Self {
+        Self::table_styled(headers, rows, column_types, Vec::new())
+    }
+
+    /// A table whose cells may carry a colour token, e.g. a state column
+    /// coloured by [`crate::icons::state_color`].
+    pub fn table_styled(
+        headers: Vec<String>,
+        rows: Vec<Vec<String>>,
+        column_types: Vec<ColumnType>,
+        cell_styles: Vec<Vec<Option<String>>>,
+    ) -> Self {
+        Self::table_with_sparklines(headers, rows, column_types, cell_styles, Vec::new())
+    }
+
+    /// A styled table with a mini sparkline in its `Sparkline` column.
+    pub fn table_with_sparklines(
+        headers: Vec<String>,
+        rows: Vec<Vec<String>>,
+        column_types: Vec<ColumnType>,
+        cell_styles: Vec<Vec<Option<String>>>,
+        sparklines: Vec<Vec<f64>>,
+    ) -> Self {
+        Self::Table {
+            headers,
+            rows,
+            column_types,
+            cell_styles,
+            sparklines,
+        }
     }
 
     pub fn host_call(
@@ -245,22 +321,45 @@ impl RenderSpec {
         last_changed: impl Into<String>,
         attributes: Vec<(String, String)>,
     ) -> Self {
+        let state = state.into();
+        let display_state = match &unit {
+            Some(u) if state.parse::<f64>().is_ok() => format!("{state} {u}"),
+            _ => state.clone(),
+        };
         Self::EntityCard {
             entity_id: entity_id.into(),
             icon: icon.into(),
             name: name.into(),
-            state: state.into(),
+            state,
             state_color: state_color.into(),
             unit,
+            display_state,
             domain: domain.into(),
             device_class,
             last_changed: last_changed.into(),
             attributes,
+            trend: None,
         }
     }
 
     pub fn key_value(title: Option<String>, pairs: Vec<(String, String)>) -> Self {
-        Self::KeyValue { title, pairs }
+        Self::KeyValue {
+            title,
+            pairs,
+            sections: Vec::new(),
+        }
+    }
+
+    /// Create a key-value spec with pairs grouped into named sections.
+    pub fn key_value_sections(
+        title: Option<String>,
+        sections: Vec<(String, Vec<(String, String)>)>,
+    ) -> Self {
+        Self::KeyValue {
+            title,
+            pairs: Vec::new(),
+            sections,
+        }
     }
 
     pub fn badge(label: impl Into<String>, color: impl Into<String>) -> Self {
@@ -294,24 +393,67 @@ impl RenderSpec {
         }
     }
 
-    /// Create a sparkline spec from numeric time-series data.
+    /// Create a snippet list spec.
+    pub fn snippets(title: impl Into<String>, snippets: Vec<String>) -> Self {
+        Self::Snippets {
+            title: title.into(),
+            snippets,
+        }
+    }
+
+    /// Create a sparkline spec from numeric time-series data, downsampled
+    /// to at most [`SPARKLINE_MAX_POINTS`] points.
     pub fn sparkline(
         entity_id: impl Into<String>,
         name: impl Into<String>,
         unit: Option<String>,
         points: Vec<(f64, f64)>,
+    ) -> Self {
+        Self::sparkline_with_max(entity_id, name, unit, points, SPARKLINE_MAX_POINTS)
+    }
+
+    /// Create a sparkline spec with a dashed reference line at `baseline`.
+    pub fn sparkline_with_baseline(
+        entity_id: impl Into<String>,
+        name: impl Into<String>,
+        unit: Option<String>,
+        points: Vec<(f64, f64)>,
+        baseline: f64,
+    ) -> Self {
+        match Self::sparkline(entity_id, name, unit, points) {
+            Self::Sparkline { entity_id, name, unit, points, min, max, current, .. } => {
+                Self::Sparkline { entity_id, name, unit, points, min, max, current, baseline: Some(baseline) }
+            }
+            other => other,
+        }
+    }
+
+    /// Create a sparkline spec, downsampling to at most `max_points` points.
+    /// Min, max and current are taken from the full series, ignoring gaps.
+    pub fn sparkline_with_max(
+        entity_id: impl Into<String>,
+        name: impl Into<String>,
+        unit: Option<String>,
+        points: Vec<(f64, f64)>,
+        max_points: usize,
     ) -> Self {
         let min = points.iter().map(|(_, v)| *v).fold(f64::INFINITY, f64::min);
         let max = points.iter().map(|(_, v)| *v).fold(f64::NEG_INFINITY, f64::max);
-        let current = points.last().map(|(_, v)| *v).unwrap_or(0.0);
+        let current = points
+            .iter()
+            .rev()
+            .map(|(_, v)| *v)
+            .find(|v| !v.is_nan())
+            .unwrap_or(0.0);
         Self::Sparkline {
             entity_id: entity_id.into(),
             name: name.into(),
             unit,
-            points,
+            points: downsample_min_max(points, max_points),
             min,
             max,
             current,
+            baseline: None,
         }
     }
 
@@ -366,6 +508,53 @@ impl RenderSpec {
     }
 }
 
+/// Default point cap for sparklines — a week of 5-minute history is ~2000
+/// points, far more than a sparkline can show.
+pub const SPARKLINE_MAX_POINTS: usize = 200;
+
+/// Downsample a time series to at most `max_points` by bucketed min/max.
+///
+/// The first and last points are always kept; the rest are split into
+/// equal buckets, each contributing its min and max point in time order,
+/// so spikes and dips survive. A bucket containing a gap (NaN) also keeps
+/// one gap point, so outages stay visible after downsampling. Limits too
+/// small for a single bucket fall back to evenly spaced points.
+fn downsample_min_max(points: Vec<(f64, f64)>, max_points: usize) -> Vec<(f64, f64)> {
+    if points.len() <= max_points {
+        return points;
+    }
+    // Each bucket keeps its min, max and — if the series has gaps — a gap.
+    let per_bucket = if points.iter().any(|p| p.1.is_nan()) { 3 } else { 2 };
+    let buckets = max_points.saturating_sub(2) / per_bucket;
+    if buckets == 0 {
+        let last = points.len() - 1;
+        return match max_points {
+            0 => Vec::new(),
+            1 => vec![points[last]],
+            n => (0..n).map(|i| points[i * last / (n - 1)]).collect(),
+        };
+    }
+    let first = points[0];
+    let last = points[points.len() - 1];
+    let interior = &points[1..points.len() - 1];
+    let bucket_size = interior.len().div_ceil(buckets);
+
+    let mut out = Vec::with_capacity(max_points);
+    out.push(first);
+    for chunk in interior.chunks(bucket_size) {
+        let values = chunk.iter().enumerate().filter(|(_, p)| !p.1.is_nan());
+        let lo = values.clone().min_by(|a, b| a.1.1.total_cmp(&b.1.1)).map(|(i, _)| i);
+        let hi = values.max_by(|a, b| a.1.1.total_cmp(&b.1.1)).map(|(i, _)| i);
+        let gap = chunk.iter().position(|p| p.1.is_nan());
+        let mut keep: Vec<usize> = [lo, hi, gap].into_iter().flatten().collect();
+        keep.sort_unstable();
+        keep.dedup();
+        out.extend(keep.into_iter().map(|i| chunk[i]));
+    }
+    out.push(last);
+    out
+}
+
 /// Extract ```signal-deck fenced code blocks from a markdown response.
 fn extract_signal_deck_blocks(markdown: &str) -> Vec<String> {
     let mut blocks = Vec::new();
@@ -383,7 +572,8 @@ fn extract_signal_deck_blocks(markdown: &str) -> Vec<String> {
                 }
                 block_lines.push(inner);
             }
-            let block = block_lines.join("\n").trim().to_string();
+            let block = dedent(&block_lines);
+            let block = block.trim_start_matches('\n').trim_end().to_string();
             if !block.is_empty() {
                 blocks.push(block);
             }
@@ -392,6 +582,24 @@ fn extract_signal_deck_blocks(markdown: &str) -> Vec<String> {
     blocks
 }
 
+/// Strip the whitespace prefix common to all non-blank lines.
+///
+/// LLMs often nest fenced blocks inside markdown lists, indenting every
+/// line — left as-is, Python rejects the snippet with an indentation error.
+fn dedent(lines: &[&str]) -> String {
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|l| if l.trim().is_empty() { "" } else { l.get(indent..).unwrap_or(l.trim_start()) })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -431,6 +639,37 @@ mod tests {
         assert!(json.contains("sensor.temp"));
     }
 
+    #[test]
+    fn test_table_column_types_default_text() {
+        let spec = RenderSpec::table(vec!["a".into(), "b".into()], vec![]);
+        let json = serde_json::to_string(&spec).unwrap();
+        assert!(json.contains(r#""column_types":["text","text"]"#), "{json}");
+
+        // Specs without column_types still deserialize.
+        let legacy: RenderSpec =
+            serde_json::from_str(r#"{"type":"table","headers":["a"],"rows":[]}"#).unwrap();
+        match legacy {
+            RenderSpec::Table { column_types, .. } => assert!(column_types.is_empty()),
+            _ => panic!("Expected Table"),
+        }
+    }
+
+    #[test]
+    fn test_table_cell_styles_only_when_styled() {
+        let plain = RenderSpec::table(vec!["a".into()], vec![vec!["x".into()]]);
+        let json = serde_json::to_string(&plain).unwrap();
+        assert!(!json.contains("cell_styles"), "{json}");
+
+        let styled = RenderSpec::table_styled(
+            vec!["a".into()],
+            vec![vec!["on".into()]],
+            vec![ColumnType::Text],
+            vec![vec![Some("success".into())]],
+        );
+        let json = serde_json::to_value(&styled).unwrap();
+        assert_eq!(json["cell_styles"], serde_json::json!([["success"]]));
+    }
+
     #[test]
     fn test_entity_card_serialization() {
         let spec = RenderSpec::entity_card(
@@ -457,6 +696,19 @@ mod tests {
         assert!(json.contains("Living Room Temperature"));
     }
 
+    #[test]
+    fn test_entity_card_display_state_includes_unit() {
+        let card = |state: &str| {
+            let spec = RenderSpec::entity_card(
+                "sensor.temp", "", "Temp", state, "accent", Some("°C".into()),
+                "sensor", None, "-", vec![],
+            );
+            serde_json::to_value(spec).unwrap()["display_state"].clone()
+        };
+        assert_eq!(card("22.5"), "22.5 °C");
+        assert_eq!(card("unavailable"), "unavailable");
+    }
+
     #[test]
     fn test_key_value_serialization() {
         let spec = RenderSpec::key_value(
@@ -550,6 +802,17 @@ mod tests {
         assert_eq!(blocks[0], "states()");
     }
 
+    #[test]
+    fn test_extract_signal_deck_blocks_dedents_list_nesting() {
+        let md = "1. Check the sensors:\n\n    ```signal-deck\n    for e in states('sensor'):\n        print(e.entity_id)\n\n    show('done')\n    ```";
+        let blocks = extract_signal_deck_blocks(md);
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(
+            blocks[0],
+            "for e in states('sensor'):\n    print(e.entity_id)\n\nshow('done')"
+        );
+    }
+
     #[test]
     fn test_sparkline_serialization() {
         let spec = RenderSpec::sparkline(
@@ -583,6 +846,103 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_sparkline_baseline() {
+        let points = vec![(1000.0, 18.0), (2000.0, 25.0), (3000.0, 21.0)];
+        match RenderSpec::sparkline_with_baseline("sensor.temp", "Temp", None, points.clone(), 21.3) {
+            RenderSpec::Sparkline { baseline, .. } => assert_eq!(baseline, Some(21.3)),
+            _ => panic!("Expected Sparkline"),
+        }
+        let spec = RenderSpec::sparkline("sensor.temp", "Temp", None, points);
+        match &spec {
+            RenderSpec::Sparkline { baseline, .. } => assert_eq!(*baseline, None),
+            _ => panic!("Expected Sparkline"),
+        }
+        let json = serde_json::to_value(&spec).unwrap();
+        assert!(json["baseline"].is_null());
+    }
+
+    #[test]
+    fn test_sparkline_downsamples_preserving_extremes() {
+        let mut points: Vec<(f64, f64)> = (0..1000)
+            .map(|i| (i as f64 * 1000.0, 20.0 + (i as f64 / 10.0).sin() * 5.0))
+            .chain(std::iter::once((1_000_000.0, 21.0)))
+            .collect();
+        points[437].1 = 99.0; // spike
+        points[712].1 = -4.0; // dip
+
+        let spec = RenderSpec::sparkline("sensor.temp", "Temp", None, points.clone());
+        match &spec {
+            RenderSpec::Sparkline { points: sampled, min, max, current, .. } => {
+                assert!(sampled.len() <= SPARKLINE_MAX_POINTS, "Got {} points", sampled.len());
+                assert_eq!(*min, -4.0);
+                assert_eq!(*max, 99.0);
+                assert_eq!(*current, 21.0);
+                let sampled_min = sampled.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+                let sampled_max = sampled.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+                assert_eq!(sampled_min, -4.0);
+                assert_eq!(sampled_max, 99.0);
+                assert_eq!(sampled.first(), points.first());
+                assert_eq!(sampled.last(), points.last());
+                assert!(sampled.windows(2).all(|w| w[0].0 < w[1].0), "Points out of order");
+            }
+            _ => panic!("Expected Sparkline"),
+        }
+    }
+
+    #[test]
+    fn test_sparkline_gap_survives_downsampling() {
+        let mut points: Vec<(f64, f64)> = (0..1000).map(|i| (i as f64, 20.0)).collect();
+        points[500].1 = f64::NAN;
+        let spec = RenderSpec::sparkline("sensor.temp", "Temp", None, points);
+        match spec {
+            RenderSpec::Sparkline { points: sampled, min, max, .. } => {
+                assert!(sampled.iter().any(|(_, v)| v.is_nan()), "Gap was dropped");
+                assert_eq!((min, max), (20.0, 20.0));
+            }
+            _ => panic!("Expected Sparkline"),
+        }
+    }
+
+    #[test]
+    fn test_sparkline_with_max_small_limits_hold() {
+        let mut points: Vec<(f64, f64)> = (0..500).map(|i| (i as f64, i as f64)).collect();
+        for max_points in [0, 1, 2, 3] {
+            let spec = RenderSpec::sparkline_with_max("sensor.x", "X", None, points.clone(), max_points);
+            match spec {
+                RenderSpec::Sparkline { points: sampled, .. } => {
+                    assert_eq!(sampled.len(), max_points);
+                    assert_eq!(sampled.last(), (max_points > 0).then_some(&(499.0, 499.0)));
+                }
+                _ => panic!("Expected Sparkline"),
+            }
+        }
+        let spec = RenderSpec::sparkline_with_max("sensor.x", "X", None, points.clone(), 2);
+        match spec {
+            RenderSpec::Sparkline { points: sampled, .. } => {
+                assert_eq!(sampled, vec![(0.0, 0.0), (499.0, 499.0)]);
+            }
+            _ => panic!("Expected Sparkline"),
+        }
+        // Gap buckets keep three points each, still within the limit.
+        points[250].1 = f64::NAN;
+        let spec = RenderSpec::sparkline_with_max("sensor.x", "X", None, points, 8);
+        match spec {
+            RenderSpec::Sparkline { points: sampled, .. } => assert!(sampled.len() <= 8, "{sampled:?}"),
+            _ => panic!("Expected Sparkline"),
+        }
+    }
+
+    #[test]
+    fn test_sparkline_with_max_small_series_untouched() {
+        let points = vec![(1000.0, 1.0), (2000.0, 2.0), (3000.0, 3.0)];
+        let spec = RenderSpec::sparkline_with_max("sensor.x", "X", None, points.clone(), 10);
+        match spec {
+            RenderSpec::Sparkline { points: sampled, .. } => assert_eq!(sampled, points),
+            _ => panic!("Expected Sparkline"),
+        }
+    }
+
     #[test]
     fn test_timeline_serialization() {
         let spec = RenderSpec::timeline(
@@ -1,4 +1,6 @@
-use monty::{MontyRepl, NoLimitTracker, ReplSnapshot};
+use std::collections::HashMap;
+
+use monty::{MontyObject, MontyRepl, NoLimitTracker, ReplSnapshot};
 
 use crate::monty_runtime;
 
@@ -15,12 +17,80 @@ pub struct Session {
     /// Stored here so we can resume when `fulfill_host_call` is called.
     pending_monty: Option<PendingMonty>,
 
+    /// A magic command host call whose response needs a non-default view.
+    /// Taken when `fulfill_host_call` is called with the matching ID.
+    pending_magic: Option<PendingMagic>,
+
+    /// The most recent entity host call issued by a magic command, so
+    /// `%refresh` can re-fetch it without retyping.
+    last_entity_call: Option<EntityCall>,
+
+    /// The value of the most recent Python expression, for `%copy`.
+    last_result: Option<MontyObject>,
+
+    /// `(entity_id, state)` of the most recent state list fetched, by
+    /// `%ls`/`%find` or `states()`, for `%count`.
+    last_states: Option<Vec<(String, String)>>,
+
+    /// State objects received so far for host calls whose response the
+    /// host streams in chunks, keyed by call ID.
+    partial_states: HashMap<String, Vec<serde_json::Value>>,
+
+    /// The latest host call issued and not yet fulfilled.
+    awaited_call: Option<String>,
+
+    /// Entity states saved by `%snapshot`, keyed by entity ID, for
+    /// `%diff <id> @snapshot`.
+    snapshots: HashMap<String, serde_json::Value>,
+
+    /// The numeric state each entity had when last fetched on its own,
+    /// for the trend arrow on entity cards.
+    numeric_states: HashMap<String, f64>,
+
+    /// Host methods the host reported via `%caps`; `None` until asked, in
+    /// which case every method is assumed supported.
+    capabilities: Option<Vec<String>>,
+
+    /// Parameters of the last `call_service` the host carried out, for
+    /// `%yaml service`.
+    last_service_call: Option<serde_json::Value>,
+
+    /// The `get_datetime` response for the current eval, so repeated
+    /// `now()` calls in one snippet reuse it instead of round-tripping.
+    now_cache: Option<serde_json::Value>,
+
+    /// Top-level names assigned by Python snippets, for autocomplete.
+    defined_names: Vec<String>,
+
+    /// `%strict on` — `state()` on a missing entity returns the host's error
+    /// instead of a placeholder EntityState with `exists=False`.
+    strict: bool,
+
+    /// `%fmt grouped` — show large whole-number states with thousands
+    /// separators in entity tables.
+    grouped_numbers: bool,
+
+    /// `%raw on` — show host data from Python calls as raw JSON instead of
+    /// auto-visualizing it.
+    raw_output: bool,
+
+    /// `%fmt json` — show host data from Python calls as JSON, like `%raw on`
+    /// but kept until another format is chosen.
+    output_format: OutputFormat,
+
+    /// `%theme light` — timeline colours for a light card background.
+    theme: Theme,
+
     /// The stateful Monty REPL session.
     /// `Some` when idle (ready to start a new snippet).
     /// `None` when a snippet is in-flight (consumed by `start()`).
     pub(crate) repl: Option<MontyRepl<NoLimitTracker>>,
 }
 
+/// Most host calls a single snippet may chain before it is aborted —
+/// e.g. `state()` inside a loop over hundreds of entities.
+pub const MAX_HOST_CALLS_PER_SNIPPET: u32 = 50;
+
 /// A Monty execution that paused at an external function call.
 pub struct PendingMonty {
     /// The host call ID this snapshot is waiting on.
@@ -31,6 +101,9 @@ pub struct PendingMonty {
     pub output_so_far: String,
     /// The original user snippet (for display/debugging).
     pub original_snippet: String,
+    /// Host calls issued so far for the original snippet, this one included.
+    /// Capped at [`MAX_HOST_CALLS_PER_SNIPPET`] to stop runaway chaining.
+    pub host_calls: u32,
     /// The host call method name (e.g. "get_state", "get_states") —
     /// used to decide how to convert the response back to MontyObject.
     pub method: String,
@@ -38,6 +111,182 @@ pub struct PendingMonty {
     pub params: serde_json::Value,
 }
 
+/// A magic command host call awaiting its response.
+pub struct PendingMagic {
+    /// The host call ID this view applies to.
+    pub call_id: String,
+    /// How to render the response once it arrives.
+    pub view: MagicView,
+}
+
+/// How a magic command's host response should be rendered, when it
+/// differs from the default shape-based formatting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MagicView {
+    /// `%get <id> --badge` — a compact state badge instead of a full card.
+    Badge,
+    /// `%get <id> <attribute>` — just one attribute's value.
+    Attribute { path: String },
+    /// `%get <group> --expand` — fetch the group's members and show them
+    /// as a table.
+    GroupMembers,
+    /// `%get <glob>` — fetch the current states of the entities the
+    /// pattern found.
+    GlobGet { pattern: String },
+    /// `%hist <id> --compare` — the latest `hours` arrive first, then the
+    /// same-length window `offset_hours` earlier; both are overlaid.
+    HistCompare {
+        entity_id: String,
+        hours: u32,
+        offset_hours: u32,
+        current: Option<serde_json::Value>,
+    },
+    /// `%find <pattern>` — filter the returned entities against the
+    /// pattern in case the host returned more than matched.
+    Find { pattern: String },
+    /// `%ls [domain] --count` — just the number of entities, no table.
+    Count { domain: Option<String> },
+    /// `%ls [domain] --chart` — a pie chart of entity counts per domain.
+    DomainChart,
+    /// `%ls` with no domain — entity counts per domain when there are
+    /// too many entities to list.
+    Overview,
+    /// `%ls [domain] --attrs <key>` — the usual table plus a column for
+    /// that attribute.
+    AttrColumn { key: String },
+    /// `%ls [domain] --spark` — the states list; a batched history fetch
+    /// for the numeric entities follows.
+    Spark { attr: Option<String> },
+    /// `%ls [domain] --spark`, second step — the history for the `states`
+    /// already fetched, to draw a sparkline per row.
+    SparkHistory {
+        attr: Option<String>,
+        states: serde_json::Value,
+    },
+    /// `%presence` — the `person` states, shown as home/away badges.
+    Presence,
+    /// `%ls [domain] --json` — the raw states list as copyable JSON.
+    Json,
+    /// `%diff a b --attrs-only/--changed-only` — filtered comparison rows.
+    Diff { attrs_only: bool, changed_only: bool },
+    /// `%diff a b c ...` — the entities to compare, in column order, from
+    /// one `get_states_by_id` response.
+    MultiDiff {
+        entity_ids: Vec<String>,
+        attrs_only: bool,
+        changed_only: bool,
+    },
+    /// `%inspect <id>` — one step of the state → attributes → history
+    /// chain, carrying the responses gathered so far.
+    Inspect {
+        entity_id: String,
+        state: Option<serde_json::Value>,
+        attrs: Option<serde_json::Value>,
+    },
+    /// `%caps` — the list of host methods the host supports.
+    Capabilities,
+    /// `%get <id> --services` — the entity's state arrives first, then the
+    /// services of its domain; both are shown together.
+    EntityServices { state: Option<serde_json::Value> },
+    /// `%snapshot <id>` — save the returned state for a later diff.
+    Snapshot { entity_id: String },
+    /// `%diff <id> @snapshot` — compare the live state against the saved one.
+    SnapshotDiff {
+        entity_id: String,
+        attrs_only: bool,
+        changed_only: bool,
+    },
+    /// `%services [domain] ~keyword` — keep only services matching the keyword.
+    Services { query: Option<String> },
+    /// `%ask <question>` — the question, for suggesting local commands
+    /// when the host has no conversation agent.
+    Ask { question: String },
+    /// `%hist <id> --as <view>` — force a history view instead of picking
+    /// one from the data.
+    History { render_as: HistoryView },
+}
+
+/// How `%hist --as` renders history.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HistoryView {
+    Sparkline,
+    Timeline,
+    /// A plain (time, state) table.
+    Table,
+}
+
+impl HistoryView {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "sparkline" => Some(Self::Sparkline),
+            "timeline" => Some(Self::Timeline),
+            "table" => Some(Self::Table),
+            _ => None,
+        }
+    }
+}
+
+/// `%theme` — which card background state colours should contrast with.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Theme {
+    #[default]
+    Dark,
+    Light,
+}
+
+impl Theme {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "dark" => Some(Self::Dark),
+            "light" => Some(Self::Light),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Dark => "dark",
+            Self::Light => "light",
+        }
+    }
+}
+
+/// `%fmt` — how host data is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum OutputFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "table" => Some(Self::Table),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Table => "table",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// A cached entity host call — enough to re-issue it with a fresh ID.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntityCall {
+    /// Host method name (e.g. "get_state", "get_history").
+    pub method: String,
+    /// Host call parameters, as originally sent.
+    pub params: serde_json::Value,
+    /// The view the original call was rendered with, if non-default.
+    pub view: Option<MagicView>,
+}
+
 impl Session {
     pub fn new() -> Self {
         // Initialise a fresh Monty REPL with all HA external functions registered.
@@ -46,6 +295,23 @@ impl Session {
             history_entries: Vec::new(),
             call_counter: 0,
             pending_monty: None,
+            pending_magic: None,
+            last_entity_call: None,
+            last_result: None,
+            last_states: None,
+            partial_states: HashMap::new(),
+            awaited_call: None,
+            snapshots: HashMap::new(),
+            numeric_states: HashMap::new(),
+            capabilities: None,
+            last_service_call: None,
+            now_cache: None,
+            defined_names: Vec::new(),
+            strict: false,
+            grouped_numbers: false,
+            raw_output: false,
+            output_format: OutputFormat::default(),
+            theme: Theme::default(),
             repl,
         }
     }
@@ -63,10 +329,36 @@ impl Session {
         &self.history_entries
     }
 
+    /// Number of host call IDs issued so far this session.
+    pub fn call_count(&self) -> u64 {
+        self.call_counter
+    }
+
     /// Generate a unique host call ID.
+    ///
+    /// Only one host call is in flight at a time, so rows streamed for an
+    /// earlier call that never finished are dropped here.
     pub fn next_call_id(&mut self) -> String {
         self.call_counter += 1;
-        format!("call_{}", self.call_counter)
+        let call_id = format!("call_{}", self.call_counter);
+        self.partial_states.clear();
+        self.awaited_call = Some(call_id.clone());
+        call_id
+    }
+
+    /// Note that the host answered `call_id`.
+    pub fn mark_fulfilled(&mut self, call_id: &str) {
+        if self.awaited_call.as_deref() == Some(call_id) {
+            self.awaited_call = None;
+        }
+    }
+
+    /// Whether `call_id` is still waiting on the host — a paused snippet,
+    /// a magic view, or a plain call not yet fulfilled.
+    pub fn is_awaiting(&self, call_id: &str) -> bool {
+        self.has_pending_monty(call_id)
+            || self.pending_magic_view(call_id).is_some()
+            || self.awaited_call.as_deref() == Some(call_id)
     }
 
     /// Store a paused Monty execution.
@@ -88,6 +380,194 @@ impl Session {
         self.pending_monty.as_ref().map(|p| p.call_id.as_str()) == Some(call_id)
     }
 
+    /// Store the view for an in-flight magic command host call.
+    pub fn store_pending_magic(&mut self, pending: PendingMagic) {
+        self.pending_magic = Some(pending);
+    }
+
+    /// The pending magic view for the given call ID, left in place.
+    pub fn pending_magic_view(&self, call_id: &str) -> Option<&MagicView> {
+        self.pending_magic
+            .as_ref()
+            .filter(|p| p.call_id == call_id)
+            .map(|p| &p.view)
+    }
+
+    /// Take the pending magic view matching the given call ID.
+    pub fn take_pending_magic(&mut self, call_id: &str) -> Option<PendingMagic> {
+        if self.pending_magic.as_ref().map(|p| p.call_id.as_str()) == Some(call_id) {
+            self.pending_magic.take()
+        } else {
+            None
+        }
+    }
+
+    /// Remember the last entity host call for `%refresh`.
+    pub fn set_last_entity_call(&mut self, call: EntityCall) {
+        self.last_entity_call = Some(call);
+    }
+
+    /// The last entity host call, if any.
+    pub fn last_entity_call(&self) -> Option<&EntityCall> {
+        self.last_entity_call.as_ref()
+    }
+
+    /// Remember the value of the most recent Python expression.
+    pub fn set_last_result(&mut self, value: MontyObject) {
+        self.last_result = Some(value);
+    }
+
+    /// The value of the most recent Python expression, if any.
+    pub fn last_result(&self) -> Option<&MontyObject> {
+        self.last_result.as_ref()
+    }
+
+    /// Remember the entities of a fetched state list.
+    pub fn set_last_states(&mut self, states: Vec<(String, String)>) {
+        self.last_states = Some(states);
+    }
+
+    /// The `(entity_id, state)` pairs of the last state list, if any.
+    pub fn last_states(&self) -> Option<&[(String, String)]> {
+        self.last_states.as_deref()
+    }
+
+    /// Add one streamed chunk of state objects to those already received
+    /// for `call_id`, returning everything received so far.
+    pub fn append_partial_states(
+        &mut self,
+        call_id: &str,
+        chunk: Vec<serde_json::Value>,
+    ) -> &[serde_json::Value] {
+        let rows = self.partial_states.entry(call_id.to_string()).or_default();
+        rows.extend(chunk);
+        rows
+    }
+
+    /// Take the streamed state objects received for `call_id`, if any.
+    pub fn take_partial_states(&mut self, call_id: &str) -> Option<Vec<serde_json::Value>> {
+        self.partial_states.remove(call_id)
+    }
+
+    /// Save an entity's state object, replacing any earlier snapshot of it.
+    pub fn save_snapshot(&mut self, entity_id: &str, state: serde_json::Value) {
+        self.snapshots.insert(entity_id.to_string(), state);
+    }
+
+    /// The state saved by `%snapshot` for an entity, if any.
+    pub fn snapshot(&self, entity_id: &str) -> Option<&serde_json::Value> {
+        self.snapshots.get(entity_id)
+    }
+
+    /// Record an entity's latest numeric state, returning the previous one.
+    pub fn record_numeric_state(&mut self, entity_id: &str, value: f64) -> Option<f64> {
+        self.numeric_states.insert(entity_id.to_string(), value)
+    }
+
+    /// Record the host methods the host supports.
+    pub fn set_capabilities(&mut self, methods: Vec<String>) {
+        self.capabilities = Some(methods);
+    }
+
+    /// Whether the host supports `method`. Always true before `%caps`, and
+    /// for the capabilities query itself.
+    pub fn supports(&self, method: &str) -> bool {
+        method == "get_capabilities"
+            || self
+                .capabilities
+                .as_ref()
+                .is_none_or(|methods| methods.iter().any(|m| m == method))
+    }
+
+    /// Remember the parameters of a completed `call_service`.
+    pub fn set_last_service_call(&mut self, params: serde_json::Value) {
+        self.last_service_call = Some(params);
+    }
+
+    /// The parameters of the last completed `call_service`, if any.
+    pub fn last_service_call(&self) -> Option<&serde_json::Value> {
+        self.last_service_call.as_ref()
+    }
+
+    /// Cache the current time for the rest of this eval.
+    pub fn set_now_cache(&mut self, value: serde_json::Value) {
+        self.now_cache = Some(value);
+    }
+
+    /// The cached current time, if fetched during this eval.
+    pub fn now_cache(&self) -> Option<&serde_json::Value> {
+        self.now_cache.as_ref()
+    }
+
+    /// Forget the cached time — called at the start of each eval.
+    pub fn clear_now_cache(&mut self) {
+        self.now_cache = None;
+    }
+
+    /// Remember names defined by a Python snippet.
+    pub fn record_defined_names(&mut self, names: impl IntoIterator<Item = String>) {
+        for name in names {
+            if !self.defined_names.contains(&name) {
+                self.defined_names.push(name);
+            }
+        }
+    }
+
+    /// Names defined so far this session, in definition order.
+    pub fn defined_names(&self) -> &[String] {
+        &self.defined_names
+    }
+
+    /// Turn thousands separators for large whole-number states on or off.
+    pub fn set_grouped_numbers(&mut self, grouped: bool) {
+        self.grouped_numbers = grouped;
+    }
+
+    /// Whether large whole-number states are shown grouped.
+    pub fn grouped_numbers(&self) -> bool {
+        self.grouped_numbers
+    }
+
+    /// Enable or disable strict mode.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Whether strict mode is on.
+    pub fn strict(&self) -> bool {
+        self.strict
+    }
+
+    /// Enable or disable raw output mode.
+    pub fn set_raw_output(&mut self, raw: bool) {
+        self.raw_output = raw;
+    }
+
+    /// Whether raw output mode is on.
+    pub fn raw_output(&self) -> bool {
+        self.raw_output
+    }
+
+    /// Set the output format.
+    pub fn set_output_format(&mut self, format: OutputFormat) {
+        self.output_format = format;
+    }
+
+    /// The output format chosen with `%fmt`.
+    pub fn output_format(&self) -> OutputFormat {
+        self.output_format
+    }
+
+    /// Set the colour theme.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// The current colour theme.
+    pub fn theme(&self) -> Theme {
+        self.theme
+    }
+
     /// Take the REPL out of the session (for starting a new snippet).
     /// Returns `None` if the REPL is currently in-flight or failed to init.
     pub fn take_repl(&mut self) -> Option<MontyRepl<NoLimitTracker>> {
@@ -134,6 +614,33 @@ mod tests {
         assert_eq!(session.next_call_id(), "call_3");
     }
 
+    #[test]
+    fn test_pending_magic_matches_call_id() {
+        let mut session = Session::new();
+        session.store_pending_magic(PendingMagic {
+            call_id: "call_1".into(),
+            view: MagicView::Badge,
+        });
+        assert!(session.take_pending_magic("call_2").is_none());
+        let pending = session.take_pending_magic("call_1").unwrap();
+        assert_eq!(pending.view, MagicView::Badge);
+        assert!(session.take_pending_magic("call_1").is_none());
+    }
+
+    #[test]
+    fn test_last_entity_call() {
+        let mut session = Session::new();
+        assert!(session.last_entity_call().is_none());
+        session.set_last_entity_call(EntityCall {
+            method: "get_state".into(),
+            params: serde_json::json!({ "entity_id": "sensor.temp" }),
+            view: None,
+        });
+        let call = session.last_entity_call().unwrap();
+        assert_eq!(call.method, "get_state");
+        assert_eq!(call.params["entity_id"], "sensor.temp");
+    }
+
     #[test]
     fn test_repl_initialized() {
         let session = Session::new();
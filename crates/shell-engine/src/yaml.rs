@@ -0,0 +1,166 @@
+/// Minimal JSON → YAML serializer for `%yaml`.
+///
+/// Covers what Signal Deck produces — scalars, lists and maps — in the
+/// block style Home Assistant configs use. Strings are quoted only when
+/// YAML would otherwise read them as something else (`on`, `12`, `a: b`).
+use serde_json::Value;
+
+/// Serialize a JSON value as a YAML document (always newline-terminated).
+pub fn to_yaml(value: &Value) -> String {
+    let mut out = String::new();
+    match value {
+        Value::Object(map) if !map.is_empty() => write_map(&mut out, map, 0),
+        Value::Array(items) if !items.is_empty() => write_list(&mut out, items, 0),
+        other => {
+            out.push_str(&scalar(other));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn write_map(out: &mut String, map: &serde_json::Map<String, Value>, indent: usize) {
+    for (key, value) in map {
+        pad(out, indent);
+        out.push_str(&string(key));
+        out.push(':');
+        write_child(out, value, indent);
+    }
+}
+
+fn write_list(out: &mut String, items: &[Value], indent: usize) {
+    for item in items {
+        pad(out, indent);
+        out.push('-');
+        match item {
+            // First key shares the dash line; the rest align under it.
+            Value::Object(map) if !map.is_empty() => {
+                for (i, (key, value)) in map.iter().enumerate() {
+                    if i == 0 {
+                        out.push(' ');
+                    } else {
+                        pad(out, indent + 2);
+                    }
+                    out.push_str(&string(key));
+                    out.push(':');
+                    write_child(out, value, indent + 2);
+                }
+            }
+            Value::Array(inner) if !inner.is_empty() => {
+                out.push('\n');
+                write_list(out, inner, indent + 2);
+            }
+            other => {
+                out.push(' ');
+                out.push_str(&scalar(other));
+                out.push('\n');
+            }
+        }
+    }
+}
+
+/// Write the value after `key:` — inline for scalars, as an indented
+/// block for non-empty maps and lists.
+fn write_child(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            out.push('\n');
+            write_map(out, map, indent + 2);
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push('\n');
+            write_list(out, items, indent + 2);
+        }
+        other => {
+            out.push(' ');
+            out.push_str(&scalar(other));
+            out.push('\n');
+        }
+    }
+}
+
+fn pad(out: &mut String, indent: usize) {
+    out.extend(std::iter::repeat_n(' ', indent));
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Bool(b) => b.to_string(),
+        Value::Number(n) => n.to_string(),
+        Value::String(s) => string(s),
+        Value::Array(_) => "[]".to_string(),
+        Value::Object(_) => "{}".to_string(),
+    }
+}
+
+/// A string as a YAML scalar, double-quoted when a plain scalar would be
+/// misread or invalid.
+fn string(s: &str) -> String {
+    if needs_quotes(s) {
+        let escaped = s
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+            .replace('\t', "\\t");
+        format!("\"{escaped}\"")
+    } else {
+        s.to_string()
+    }
+}
+
+fn needs_quotes(s: &str) -> bool {
+    const RESERVED: &[&str] = &[
+        "true", "false", "yes", "no", "on", "off", "y", "n", "null", "~",
+    ];
+    s.is_empty()
+        || s != s.trim()
+        || RESERVED.contains(&s.to_lowercase().as_str())
+        || s.parse::<f64>().is_ok()
+        || s.starts_with(|c: char| "-?:,[]{}#&*!|>'\"%@`".contains(c))
+        || s.contains(": ")
+        || s.contains(" #")
+        || s.ends_with(':')
+        || s.contains(['\n', '\t', '\\'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_service_call_yaml() {
+        let value = serde_json::json!({
+            "action": "light.turn_on",
+            "data": { "brightness": 200, "entity_id": "light.kitchen", "transition": 1.5 },
+        });
+        assert_eq!(
+            to_yaml(&value),
+            "action: light.turn_on\ndata:\n  brightness: 200\n  entity_id: light.kitchen\n  transition: 1.5\n"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        let value = serde_json::json!({
+            "entity_id": ["light.a", "light.b"],
+            "steps": [{ "delay": 5, "action": "x.y" }, [1, 2]],
+            "empty": [],
+        });
+        assert_eq!(
+            to_yaml(&value),
+            "empty: []\nentity_id:\n  - light.a\n  - light.b\nsteps:\n  - action: x.y\n    delay: 5\n  -\n    - 1\n    - 2\n"
+        );
+    }
+
+    #[test]
+    fn test_quotes_ambiguous_strings() {
+        assert_eq!(string("on"), "\"on\"");
+        assert_eq!(string("12"), "\"12\"");
+        assert_eq!(string("a: b"), "\"a: b\"");
+        assert_eq!(string(""), "\"\"");
+        assert_eq!(string("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+        assert_eq!(string("light.kitchen"), "light.kitchen");
+        assert_eq!(to_yaml(&serde_json::json!(null)), "null\n");
+    }
+}
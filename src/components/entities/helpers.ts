@@ -48,6 +48,20 @@ export function renderCardHeader(spec: EntityCardSpec): TemplateResult {
   `;
 }
 
+/** Render the ▲/▼/▬ trend arrow next to a numeric state, if known. */
+export function renderTrend(spec: EntityCardSpec): TemplateResult | typeof nothing {
+  switch (spec.trend) {
+    case 'up':
+      return html`<span class="entity-card-trend trend-up" title="Up since last fetch">▲</span>`;
+    case 'down':
+      return html`<span class="entity-card-trend trend-down" title="Down since last fetch">▼</span>`;
+    case 'flat':
+      return html`<span class="entity-card-trend" title="Unchanged since last fetch">▬</span>`;
+    default:
+      return nothing;
+  }
+}
+
 /** Render the standard meta row (domain · device_class · last_changed). */
 export function renderCardMeta(spec: EntityCardSpec): TemplateResult {
   return html`
@@ -10,6 +10,7 @@ import {
   renderAttrsTable,
   renderBar,
   attrsExcluding,
+  renderTrend,
 } from './helpers.js';
 
 const HANDLED_ATTRS = ['state_class'];
@@ -34,9 +35,12 @@ export function renderSensorCard(spec: EntityCardSpec): TemplateResult {
 
       <div class="entity-card-state">
         <span class="entity-card-state-value ${stateClass}">
-          ${isTimestamp ? formatRelativeTime(spec.state) : spec.state}
+          ${isTimestamp ? formatRelativeTime(spec.state) : (spec.display_state ?? spec.state)}
         </span>
-        ${spec.unit ? html`<span class="entity-card-state-unit">${spec.unit}</span>` : nothing}
+        ${spec.unit && spec.display_state === undefined
+          ? html`<span class="entity-card-state-unit">${spec.unit}</span>`
+          : nothing}
+        ${renderTrend(spec)}
       </div>
 
       ${batteryLevel !== undefined
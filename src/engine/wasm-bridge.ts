@@ -47,6 +47,12 @@ export class ShellEngine {
     return JSON.parse(json) as RenderSpec;
   }
 
+  /** Feed one chunk of a streamed host call response. Returns the table so far. */
+  fulfillHostCallChunk(callId: string, partial: string): RenderSpec {
+    const json = this.engine.fulfill_host_call_chunk(callId, partial);
+    return JSON.parse(json) as RenderSpec;
+  }
+
   /** Get the current prompt string. */
   prompt(): string {
     return this.engine.prompt();
@@ -65,6 +65,38 @@ describe('fulfillHostCall', () => {
     expect(data.error).toContain('not found');
   });
 
+  it('should return states for get_states_by_id, skipping unknown ids', async () => {
+    const hass = mockHass({
+      'light.kitchen': { state: 'on' },
+      'light.hall': { state: 'off' },
+    });
+    const result = await fulfillHostCall(hass, 'get_states_by_id', {
+      entity_ids: ['light.hall', 'light.missing', 'light.kitchen'],
+    });
+    const data = JSON.parse(result.data);
+    expect(data.map((s: { entity_id: string }) => s.entity_id)).toEqual(['light.hall', 'light.kitchen']);
+  });
+
+  it('should leave out methods whose integration is not loaded from get_capabilities', async () => {
+    const hass = mockHass({});
+    (hass as { config?: { components: string[] } }).config = { components: ['history', 'logbook'] };
+    const result = await fulfillHostCall(hass, 'get_capabilities', {});
+    const methods = JSON.parse(result.data) as string[];
+    expect(methods).toContain('get_state');
+    expect(methods).toContain('get_history');
+    expect(methods).not.toContain('get_statistics');
+    expect(methods).not.toContain('get_trace');
+  });
+
+  it('should report no agent for conversation_process without the conversation integration', async () => {
+    const hass = mockHass({});
+    (hass as { config?: { components: string[] } }).config = { components: ['history'] };
+    const result = await fulfillHostCall(hass, 'conversation_process', { text: 'why' });
+    const data = JSON.parse(result.data);
+    expect(data.__conversation).toBe(true);
+    expect(data.no_agent).toBe(true);
+  });
+
   it('should find entities by glob pattern', async () => {
     const hass = mockHass({
       'binary_sensor.lr_occupied': { state: 'on' },
@@ -76,6 +108,27 @@ describe('fulfillHostCall', () => {
     expect(data).toHaveLength(2);
   });
 
+  it('should include the changed states from a service call', async () => {
+    const hass = mockHass({});
+    let posted: { path: string; body: unknown } | undefined;
+    (hass as { callApi: unknown }).callApi = async (_method: string, path: string, body: unknown) => {
+      posted = { path, body };
+      return [{ entity_id: 'light.kitchen', state: 'on' }];
+    };
+    const result = await fulfillHostCall(hass, 'call_service', {
+      domain: 'light',
+      service: 'turn_on',
+      service_data: { entity_id: 'light.kitchen', brightness: 200 },
+    });
+    const data = JSON.parse(result.data);
+    expect(posted).toEqual({
+      path: 'services/light/turn_on',
+      body: { brightness: 200, entity_id: 'light.kitchen' },
+    });
+    expect(data.success).toBe(true);
+    expect(data.states[0].entity_id).toBe('light.kitchen');
+  });
+
   it('should return error for unknown method', async () => {
     const hass = mockHass({});
     const result = await fulfillHostCall(hass, 'unknown_method', {});
@@ -23,6 +23,8 @@ export async function fulfillHostCall(
       return getStates(hass, params);
     case 'get_state':
       return getState(hass, params);
+    case 'get_states_by_id':
+      return getStatesById(hass, params);
     case 'find_entities':
       return findEntities(hass, params);
     case 'get_history':
@@ -61,11 +63,42 @@ export async function fulfillHostCall(
       return callService(hass, params);
     case 'get_events':
       return getCalendarEvents(hass, params);
+    case 'get_capabilities':
+      return getCapabilities(hass);
     default:
       return { data: JSON.stringify({ error: `Unknown host method: ${method}` }) };
   }
 }
 
+/** Every host method `fulfillHostCall` handles. */
+const HOST_METHODS = [
+  'get_states', 'get_states_by_id', 'get_state', 'find_entities', 'get_history',
+  'get_statistics', 'get_diff', 'get_area_entities', 'get_areas', 'conversation_process',
+  'get_logbook', 'render_template', 'get_trace', 'list_traces', 'get_devices',
+  'get_entity_entry', 'check_config', 'get_error_log', 'get_datetime', 'get_services',
+  'call_service', 'get_events',
+];
+
+/** HA integrations a host method needs loaded to work. */
+const METHOD_COMPONENTS: Record<string, string> = {
+  get_history: 'history',
+  get_statistics: 'recorder',
+  get_logbook: 'logbook',
+  get_trace: 'trace',
+  list_traces: 'trace',
+  conversation_process: 'conversation',
+  get_events: 'calendar',
+};
+
+/** Host methods usable with this HA instance, for `%caps`. */
+function getCapabilities(hass: HomeAssistant): HostCallResult {
+  const components = hass.config?.components;
+  const methods = components
+    ? HOST_METHODS.filter((m) => !METHOD_COMPONENTS[m] || components.includes(METHOD_COMPONENTS[m]))
+    : HOST_METHODS;
+  return { data: JSON.stringify(methods) };
+}
+
 /** Get all states, optionally filtered by domain. */
 function getStates(
   hass: HomeAssistant,
@@ -102,6 +135,16 @@ function getState(
   return { data: JSON.stringify(state) };
 }
 
+/** Get the states of specific entities, in the order given. Unknown ids are skipped. */
+function getStatesById(
+  hass: HomeAssistant,
+  params: Record<string, unknown>,
+): HostCallResult {
+  const entityIds = (params.entity_ids as string[] | undefined) ?? [];
+  const states = entityIds.map((id) => hass.states[id]).filter((s) => s !== undefined);
+  return { data: JSON.stringify(states) };
+}
+
 /** Find entities matching a glob pattern. */
 function findEntities(
   hass: HomeAssistant,
@@ -121,20 +164,24 @@ function findEntities(
   return { data: JSON.stringify(matches) };
 }
 
-/** Get entity history via HA WebSocket. */
+/** Get entity history via HA WebSocket. `entity_ids` fetches several series at once. */
 async function getHistory(
   hass: HomeAssistant,
   params: Record<string, unknown>,
 ): Promise<HostCallResult> {
-  const entityId = params.entity_id as string;
+  const entityId = (params.entity_ids as string[] | undefined)?.join(',') ?? (params.entity_id as string);
   const hours = (params.hours as number) || 6;
+  // `offset_hours` moves the whole window back (for `%hist --compare`).
+  const offsetHours = (params.offset_hours as number) || 0;
 
-  const startTime = new Date(Date.now() - hours * 60 * 60 * 1000).toISOString();
+  const endMs = Date.now() - offsetHours * 60 * 60 * 1000;
+  const startTime = new Date(endMs - hours * 60 * 60 * 1000).toISOString();
+  const endParam = offsetHours ? `&end_time=${encodeURIComponent(new Date(endMs).toISOString())}` : '';
 
   try {
     const result = await hass.callApi<HassEntity[][]>(
       'GET',
-      `history/period/${startTime}?filter_entity_id=${entityId}&minimal_response&no_attributes`,
+      `history/period/${startTime}?filter_entity_id=${entityId}&minimal_response&no_attributes${endParam}`,
     );
     return { data: JSON.stringify(result) };
   } catch (e) {
@@ -403,12 +450,13 @@ async function renderTemplate(
   params: Record<string, unknown>,
 ): Promise<HostCallResult> {
   const template = params.template as string;
+  const variables = params.variables as Record<string, unknown> | undefined;
 
   try {
     const result = await hass.callApi<string>(
       'POST',
       'template',
-      { template },
+      variables ? { template, variables } : { template },
     );
     return { data: JSON.stringify({ result }) };
   } catch (e) {
@@ -734,28 +782,20 @@ async function callService(
 ): Promise<HostCallResult> {
   const domain = params.domain as string | undefined;
   const service = params.service as string | undefined;
-  const serviceData = { ...((params.service_data ?? params.data ?? {}) as Record<string, unknown>) };
+  const serviceData = (params.service_data ?? params.data ?? {}) as Record<string, unknown>;
 
   if (!domain || !service) {
     return { data: JSON.stringify({ error: 'call_service requires domain and service' }) };
   }
 
-  // HA's callService expects entity_id / device_id / area_id in a
-  // separate `target` parameter rather than in `serviceData`.
-  const target: Record<string, unknown> = {};
-  for (const key of ['entity_id', 'device_id', 'area_id'] as const) {
-    if (serviceData[key] !== undefined) {
-      target[key] = serviceData[key];
-      delete serviceData[key];
-    }
-  }
-
   try {
-    await hass.callService(
-      domain,
-      service,
+    // The REST endpoint takes entity_id / device_id / area_id alongside the
+    // other fields, and answers with the states that changed during the
+    // call, so the result can show the effect straight away.
+    const states = await hass.callApi<HassEntity[]>(
+      'POST',
+      `services/${domain}/${service}`,
       serviceData,
-      Object.keys(target).length > 0 ? target : undefined,
     );
     return {
       data: JSON.stringify({
@@ -763,7 +803,7 @@ async function callService(
         domain,
         service,
         service_data: serviceData,
-        ...(Object.keys(target).length > 0 ? { target } : {}),
+        ...(Array.isArray(states) && states.length > 0 ? { states } : {}),
       }),
     };
   } catch (e) {
@@ -852,9 +892,24 @@ async function conversationProcess(
     ? `${context}\n\nUser question: ${question}`
     : question;
 
+  // Without the conversation integration there's no agent to ask — the
+  // engine suggests local commands instead.
+  const components = hass.config?.components;
+  if (components && !components.includes('conversation')) {
+    return {
+      data: JSON.stringify({
+        __conversation: true,
+        no_agent: true,
+        response: 'No conversation agent is configured.',
+        agent_id: 'none',
+      }),
+    };
+  }
+
   try {
-    // Find a conversation agent — prefer Claude if available.
-    const agentId = findConversationAgent(hass);
+    // Use the agent asked for (`%ask @name`), else prefer Claude if available.
+    const requested = params.agent_id as string | undefined;
+    const agentId = requested ? resolveConversationAgent(hass, requested) : findConversationAgent(hass);
 
     const response = await hass.callWS<{
       response: {
@@ -887,6 +942,20 @@ async function conversationProcess(
   }
 }
 
+/**
+ * Resolve a `%ask @name` agent: an exact entity ID, `conversation.<name>`,
+ * or the first conversation entity containing the name. Anything else is
+ * passed through as an agent ID.
+ */
+function resolveConversationAgent(hass: HomeAssistant, name: string): string {
+  if (hass.states[name]) return name;
+  if (hass.states[`conversation.${name}`]) return `conversation.${name}`;
+  const match = Object.keys(hass.states).find(
+    (id) => id.startsWith('conversation.') && id.includes(name.toLowerCase()),
+  );
+  return match ?? name;
+}
+
 /** Find the best conversation agent entity. Prefers Claude/Anthropic. */
 function findConversationAgent(hass: HomeAssistant): string | null {
   const entities = Object.keys(hass.states).filter((id) =>
@@ -193,10 +193,17 @@ export class SignalDeck extends LitElement {
     // Eval the input through the Rust engine.
     let spec = this._engine.eval(input);
 
-    // Handle :clear — wipe output history.
-    if (spec.type === 'text' && (spec as { content: string }).content === '\x1b[clear]') {
-      this._outputs = [];
-      return;
+    // Handle :clear — wipe output history (or just the last block).
+    if (spec.type === 'text') {
+      const content = (spec as { content: string }).content;
+      if (content === '\x1b[clear]') {
+        this._outputs = [];
+        return;
+      }
+      if (content === '\x1b[clear:last]') {
+        this._outputs = this._outputs.slice(0, -1);
+        return;
+      }
     }
 
     // If the engine requests a host call, fulfill it.
@@ -739,6 +746,36 @@ export class SignalDeck extends LitElement {
       border-bottom: 1px solid var(--sd-surface);
     }
 
+    .table-output th.num,
+    .table-output td.num {
+      text-align: right;
+      font-variant-numeric: tabular-nums;
+    }
+
+    .table-output .cell-sparkline {
+      display: block;
+    }
+
+    .table-output td.cell-success {
+      color: var(--sd-success);
+    }
+
+    .table-output td.cell-warning {
+      color: var(--sd-warning);
+    }
+
+    .table-output td.cell-error {
+      color: var(--sd-error);
+    }
+
+    .table-output td.cell-accent {
+      color: var(--sd-accent);
+    }
+
+    .table-output td.cell-dim {
+      color: var(--sd-dim);
+    }
+
     .table-output tr:hover td {
       background: var(--sd-surface);
     }
@@ -967,6 +1004,14 @@ export class SignalDeck extends LitElement {
       color: var(--sd-dim);
     }
 
+    .entity-card-trend {
+      font-size: 12px;
+      color: var(--sd-dim);
+    }
+
+    .entity-card-trend.trend-up { color: var(--sd-success); }
+    .entity-card-trend.trend-down { color: var(--sd-cyan); }
+
     .entity-card-meta {
       display: flex;
       gap: 12px;
@@ -1551,6 +1596,14 @@ export class SignalDeck extends LitElement {
       word-break: break-word;
     }
 
+    .kv-table td.kv-section {
+      color: var(--sd-accent);
+      font-size: 11px;
+      text-transform: uppercase;
+      letter-spacing: 0.04em;
+      padding-top: 6px;
+    }
+
     /* Summary line */
     .summary-output {
       color: var(--sd-dim);
@@ -1568,6 +1621,8 @@ export class SignalDeck extends LitElement {
     .copyable-content {
       white-space: pre-wrap;
       word-break: break-word;
+      max-height: 320px;
+      overflow-y: auto;
       background: var(--sd-surface);
       border: 1px solid var(--sd-border);
       border-radius: 4px;
@@ -1689,6 +1744,11 @@ export class SignalDeck extends LitElement {
       margin: 4px 0;
     }
 
+    .snippet-list {
+      padding: 8px 0;
+      margin: 4px 0;
+    }
+
     .assistant-header {
       display: flex;
       align-items: center;
@@ -1809,6 +1869,13 @@ export class SignalDeck extends LitElement {
       fill: rgba(0, 255, 159, 0.08);
     }
 
+    .sparkline-baseline {
+      stroke: var(--sd-dim);
+      stroke-width: 1;
+      stroke-dasharray: 4 3;
+      vector-effect: non-scaling-stroke;
+    }
+
     .sparkline-dot {
       fill: var(--sd-success);
     }
@@ -2412,6 +2479,19 @@ export class SignalDeck extends LitElement {
                     </tr>
                   `,
                 )}
+                ${(spec.sections ?? []).map(
+                  ([section, pairs]) => html`
+                    <tr><td class="kv-section" colspan="2">${section}</td></tr>
+                    ${pairs.map(
+                      ([key, value]) => html`
+                        <tr>
+                          <td class="kv-key">${key}</td>
+                          <td class="kv-value">${value}</td>
+                        </tr>
+                      `,
+                    )}
+                  `,
+                )}
               </tbody>
             </table>
           </div>
@@ -2447,24 +2527,15 @@ export class SignalDeck extends LitElement {
               <span class="assistant-agent">${spec.agent}</span>
             </div>
             <div class="assistant-body">${spec.response}</div>
-            ${spec.snippets.length > 0
-              ? html`
-                  <div class="assistant-snippets">
-                    ${spec.snippets.map(
-                      (snippet, idx) => html`
-                        <div class="assistant-snippet">
-                          <div class="assistant-snippet-header">
-                            <span>snippet ${idx + 1}</span>
-                            <button class="snippet-btn" @click=${() => this._insertSnippet(snippet)}>⎘ insert</button>
-                            <button class="snippet-btn snippet-run" @click=${() => this._runSnippet(snippet)}>▶ run</button>
-                          </div>
-                          <pre class="assistant-snippet-code">${snippet}</pre>
-                        </div>
-                      `,
-                    )}
-                  </div>
-                `
-              : nothing}
+            ${this._renderSnippets(spec.snippets)}
+          </div>
+        `;
+
+      case 'snippets':
+        return html`
+          <div class="snippet-list">
+            <div class="assistant-body">${spec.title}</div>
+            ${this._renderSnippets(spec.snippets)}
           </div>
         `;
 
@@ -2514,11 +2585,11 @@ export class SignalDeck extends LitElement {
       return html`
         <table class="table-output">
           <thead>
-            <tr>${spec.headers.map((h) => html`<th>${h}</th>`)}</tr>
+            <tr>${spec.headers.map((h, ci) => html`<th class=${this._cellClass(spec, ci)}>${h}</th>`)}</tr>
           </thead>
           <tbody>
             ${spec.rows.map(
-              (row) => html`<tr>${row.map((cell, ci) => html`<td>${this._renderCellValue(cell, ci, spec.headers)}</td>`)}</tr>`,
+              (row, ri) => html`<tr>${row.map((cell, ci) => html`<td class=${this._cellClass(spec, ci, ri)}>${this._renderTableCell(spec, cell, ci, ri)}</td>`)}</tr>`,
             )}
           </tbody>
         </table>
@@ -2535,11 +2606,11 @@ export class SignalDeck extends LitElement {
     return html`
       <table class="table-output">
         <thead>
-          <tr>${spec.headers.map((h) => html`<th>${h}</th>`)}</tr>
+          <tr>${spec.headers.map((h, ci) => html`<th class=${this._cellClass(spec, ci)}>${h}</th>`)}</tr>
         </thead>
         <tbody>
           ${pageRows.map(
-            (row) => html`<tr>${row.map((cell, ci) => html`<td>${this._renderCellValue(cell, ci, spec.headers)}</td>`)}</tr>`,
+            (row, ri) => html`<tr>${row.map((cell, ci) => html`<td class=${this._cellClass(spec, ci, start + ri)}>${this._renderTableCell(spec, cell, ci, start + ri)}</td>`)}</tr>`,
           )}
         </tbody>
       </table>
@@ -2559,6 +2630,44 @@ export class SignalDeck extends LitElement {
     `;
   }
 
+  /** CSS class for a table cell from its column type hint and colour token. */
+  private _cellClass(spec: RenderSpec & { type: 'table' }, col: number, row?: number): string {
+    const classes = spec.column_types?.[col] === 'number' ? ['num'] : [];
+    const style = row === undefined ? null : spec.cell_styles?.[row]?.[col];
+    if (style) classes.push(`cell-${style}`);
+    return classes.join(' ');
+  }
+
+  /** A table cell's content — a mini sparkline in `sparkline` columns. */
+  private _renderTableCell(
+    spec: RenderSpec & { type: 'table' },
+    cell: string,
+    col: number,
+    row: number,
+  ): TemplateResult | string {
+    if (spec.column_types?.[col] !== 'sparkline') {
+      return this._renderCellValue(cell, col, spec.headers);
+    }
+    const values = spec.sparklines?.[row] ?? [];
+    if (values.length < 2) return '';
+
+    const width = 80;
+    const height = 18;
+    const padding = 1;
+    const min = Math.min(...values);
+    const range = Math.max(...values) - min || 1;
+    const points = values
+      .map((v, i) => {
+        const x = padding + (i / (values.length - 1)) * (width - 2 * padding);
+        const y = padding + (1 - (v - min) / range) * (height - 2 * padding);
+        return `${x.toFixed(1)},${y.toFixed(1)}`;
+      })
+      .join(' ');
+    return html`<svg class="cell-sparkline" viewBox="0 0 ${width} ${height}" width="${width}" height="${height}">
+      <polyline points="${points}" class="sparkline-line" />
+    </svg>`;
+  }
+
   private _setTablePage(tableId: string, page: number): void {
     const next = new Map(this._tablePages);
     next.set(tableId, page);
@@ -2572,7 +2681,7 @@ export class SignalDeck extends LitElement {
 
   /** Render a sparkline SVG for numeric time series. */
   private _renderSparkline(spec: RenderSpec & { type: 'sparkline' }): TemplateResult {
-    const { points, min, max, current, name, unit, entity_id } = spec;
+    const { points, min, max, current, baseline, name, unit, entity_id } = spec;
     const width = 320;
     const height = 60;
     const padding = 2;
@@ -2586,16 +2695,36 @@ export class SignalDeck extends LitElement {
     const tRange = tMax - tMin || 1;
     const vRange = max - min || 1;
 
-    // Scale points to SVG coordinates.
-    const svgPoints = points.map(([t, v]) => {
+    // Scale points to SVG coordinates, splitting into runs at null gaps
+    // (e.g. while the entity was unavailable).
+    const runs: [number, number][][] = [[]];
+    for (const [t, v] of points) {
+      if (v === null) {
+        if (runs[runs.length - 1].length > 0) runs.push([]);
+        continue;
+      }
       const x = padding + ((t - tMin) / tRange) * (width - 2 * padding);
       const y = padding + (1 - (v - min) / vRange) * (height - 2 * padding);
-      return `${x.toFixed(1)},${y.toFixed(1)}`;
-    });
-
-    // Build the filled area path.
-    const linePath = `M ${svgPoints.join(' L ')}`;
-    const areaPath = `${linePath} L ${(width - padding).toFixed(1)},${(height - padding).toFixed(1)} L ${padding.toFixed(1)},${(height - padding).toFixed(1)} Z`;
+      runs[runs.length - 1].push([x, y]);
+    }
+    const segments = runs.filter((run) => run.length > 0);
+    if (segments.length === 0) {
+      return html`<div class="text-output">Not enough data for sparkline.</div>`;
+    }
+    const fmt = ([x, y]: [number, number]) => `${x.toFixed(1)},${y.toFixed(1)}`;
+
+    // Build the line and filled area paths, one subpath per run.
+    const linePath = segments.map((run) => `M ${run.map(fmt).join(' L ')}`).join(' ');
+    const bottom = (height - padding).toFixed(1);
+    const areaPath = segments
+      .map((run) => `M ${run.map(fmt).join(' L ')} L ${run[run.length - 1][0].toFixed(1)},${bottom} L ${run[0][0].toFixed(1)},${bottom} Z`)
+      .join(' ');
+    const baselineY =
+      baseline != null && baseline >= min && baseline <= max
+        ? padding + (1 - (baseline - min) / vRange) * (height - 2 * padding)
+        : null;
+    const lastRun = segments[segments.length - 1];
+    const [dotX, dotY] = lastRun[lastRun.length - 1];
 
     const unitStr = unit ? ` ${unit}` : '';
     const minStr = `${min.toFixed(1)}${unitStr}`;
@@ -2616,14 +2745,20 @@ export class SignalDeck extends LitElement {
           height="${height}"
         >
           <path d="${areaPath}" class="sparkline-area" />
-          <polyline
-            points="${svgPoints.join(' ')}"
-            class="sparkline-line"
-          />
+          ${baselineY !== null
+            ? svg`<line
+                x1="${padding}"
+                x2="${width - padding}"
+                y1="${baselineY.toFixed(1)}"
+                y2="${baselineY.toFixed(1)}"
+                class="sparkline-baseline"
+              />`
+            : nothing}
+          <path d="${linePath}" class="sparkline-line" />
           <!-- Current value dot -->
           <circle
-            cx="${svgPoints[svgPoints.length - 1].split(',')[0]}"
-            cy="${svgPoints[svgPoints.length - 1].split(',')[1]}"
+            cx="${dotX.toFixed(1)}"
+            cy="${dotY.toFixed(1)}"
             r="2.5"
             class="sparkline-dot"
           />
@@ -2701,6 +2836,27 @@ export class SignalDeck extends LitElement {
     )}`;
   }
 
+  /** Render runnable snippets, each with insert and run buttons. */
+  private _renderSnippets(snippets: string[]): TemplateResult | string {
+    if (snippets.length === 0) return '';
+    return html`
+      <div class="assistant-snippets">
+        ${snippets.map(
+          (snippet, idx) => html`
+            <div class="assistant-snippet">
+              <div class="assistant-snippet-header">
+                <span>snippet ${idx + 1}</span>
+                <button class="snippet-btn" @click=${() => this._insertSnippet(snippet)}>⎘ insert</button>
+                <button class="snippet-btn snippet-run" @click=${() => this._runSnippet(snippet)}>▶ run</button>
+              </div>
+              <pre class="assistant-snippet-code">${snippet}</pre>
+            </div>
+          `,
+        )}
+      </div>
+    `;
+  }
+
   /** Render a rich logbook display — vertical timeline of state changes with context. */
   private _renderLogbook(spec: RenderSpec & { type: 'logbook' }): TemplateResult {
     const { entries, entity_id } = spec;
@@ -2988,6 +3144,12 @@ export class SignalDeck extends LitElement {
             label: { ...sdFont, ...(s.label as Record<string, unknown> ?? {}) },
           }))
         : spec.option.series;
+      // `unit=` arrives as a `{value} °C` template; ECharts wants a function.
+      const tooltip = spec.option.tooltip as Record<string, unknown> | undefined;
+      const valueTemplate = tooltip?.valueFormatter;
+      const themedTooltip = typeof valueTemplate === 'string'
+        ? { ...tooltip, valueFormatter: (v: unknown) => valueTemplate.replace('{value}', String(v)) }
+        : tooltip;
       const themedOption = {
         ...spec.option,
         backgroundColor: 'transparent',
@@ -2996,6 +3158,7 @@ export class SignalDeck extends LitElement {
           ...(spec.option.legend as Record<string, unknown> ?? {}),
           textStyle: sdFont,
         },
+        tooltip: themedTooltip,
         series: themedSeries,
       };
       chart.setOption(themedOption as echarts.EChartsCoreOption);
@@ -3050,7 +3213,13 @@ export class SignalDeck extends LitElement {
       case 'entity_card':
         return `${spec.entity_id}\t${spec.state}${spec.unit ? ' ' + spec.unit : ''}\t${spec.name}`;
       case 'key_value':
-        return spec.pairs.map(([k, v]) => `${k}: ${v}`).join('\n');
+        return [
+          ...spec.pairs.map(([k, v]) => `${k}: ${v}`),
+          ...(spec.sections ?? []).flatMap(([section, pairs]) => [
+            `[${section}]`,
+            ...pairs.map(([k, v]) => `${k}: ${v}`),
+          ]),
+        ].join('\n');
       case 'sparkline':
         return `${spec.entity_id}\tmin=${spec.min}\tcurrent=${spec.current}\tmax=${spec.max}${spec.unit ? ' ' + spec.unit : ''}`;
       case 'timeline': {
@@ -28,6 +28,8 @@ export interface HomeAssistant {
   ) => Promise<void>;
   language: string;
   locale: Record<string, unknown>;
+  /** Loaded integrations, among other config. */
+  config?: { components?: string[] };
 }
 
 export interface LovelaceCardConfig {
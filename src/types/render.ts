@@ -13,10 +13,18 @@ export interface ErrorSpec {
   message: string;
 }
 
+export type ColumnType = 'text' | 'number' | 'icon' | 'sparkline';
+
 export interface TableSpec {
   type: 'table';
   headers: string[];
   rows: string[][];
+  /** Per-column type hints; absent or shorter than headers means text. */
+  column_types?: ColumnType[];
+  /** Optional colour token per cell (e.g. `success`), parallel to `rows`. */
+  cell_styles?: (string | null)[][];
+  /** Recent values per row for a `sparkline` column, parallel to `rows`. */
+  sparklines?: number[][];
 }
 
 export interface HostCallSpec {
@@ -49,16 +57,22 @@ export interface EntityCardSpec {
   state: string;
   state_color: string;
   unit: string | null;
+  /** The state with its unit when numeric (`22.5 °C`) — the card headline. */
+  display_state?: string;
   domain: string;
   device_class: string | null;
   last_changed: string;
   attributes: [string, string][];
+  /** Numeric trend vs the previous fetch of this entity. */
+  trend?: 'up' | 'down' | 'flat' | null;
 }
 
 export interface KeyValueSpec {
   type: 'key_value';
   title: string | null;
   pairs: [string, string][];
+  /** Named groups of pairs, shown after `pairs` under sub-headings. */
+  sections?: [string, [string, string][]][];
 }
 
 export interface BadgeSpec {
@@ -85,16 +99,24 @@ export interface AssistantSpec {
   snippets: string[];
 }
 
+export interface SnippetsSpec {
+  type: 'snippets';
+  title: string;
+  snippets: string[];
+}
+
 export interface SparklineSpec {
   type: 'sparkline';
   entity_id: string;
   name: string;
   unit: string | null;
-  /** Data points: [timestamp_ms, value] */
-  points: [number, number][];
+  /** Data points: [timestamp_ms, value]; a null value marks a gap. */
+  points: [number, number | null][];
   min: number;
   max: number;
   current: number;
+  /** Reference value drawn as a dashed line (e.g. the period mean). */
+  baseline: number | null;
 }
 
 export interface TimelineSpec {
@@ -184,6 +206,7 @@ export type RenderSpec =
   | CopyableSpec
   | SummarySpec
   | AssistantSpec
+  | SnippetsSpec
   | SparklineSpec
   | TimelineSpec
   | LogbookSpec